@@ -0,0 +1,637 @@
+//! Native basic block matching within a pair of matched functions, using the
+//! algorithm families BinDiff names in `BasicBlockAlgorithm`.
+
+use crate::flowgraph::{BasicBlock, Function};
+use crate::instruction_diff::lcs;
+use crate::matching::{group, hash_unordered, unique_pairs};
+use crate::md_index::{self, Direction};
+use crate::prime::prime_product;
+use crate::{is_generated_name, BasicBlockAlgorithm, BasicBlockMatch, Instruction};
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::hash::{Hash, Hasher};
+
+/// Upper bound on passes over the matching steps
+const MAX_ROUNDS: usize = 4;
+
+/// Basic block matching steps in the order BinDiff runs them by default
+pub fn default_basic_block_steps() -> Vec<BasicBlockAlgorithm> {
+    vec![
+        BasicBlockAlgorithm::EdgesPrimeProduct,
+        BasicBlockAlgorithm::HashMatchingFourInstMin,
+        BasicBlockAlgorithm::PrimeMatchingFourInstMin,
+        BasicBlockAlgorithm::CallReferenceMatching,
+        BasicBlockAlgorithm::StringReferencesMatching,
+        BasicBlockAlgorithm::EdgesMdIndexTopDown,
+        BasicBlockAlgorithm::MdIndexMatchingTopDown,
+        BasicBlockAlgorithm::EdgesMdIndexBottomUp,
+        BasicBlockAlgorithm::MdIndexMatchingBottomUp,
+        BasicBlockAlgorithm::RelaxedMdIndexMatching,
+        BasicBlockAlgorithm::PrimeMatchingNoInstMin,
+        BasicBlockAlgorithm::EdgesLengauerTarjanDominated,
+        BasicBlockAlgorithm::LoopEntryMatching,
+        BasicBlockAlgorithm::SelfLoopMatching,
+        BasicBlockAlgorithm::EntryPointMatching,
+        BasicBlockAlgorithm::ExitPointMatching,
+        BasicBlockAlgorithm::InstructionCountMatching,
+        BasicBlockAlgorithm::JumpSequenceMatching,
+        BasicBlockAlgorithm::PropagationSizeOne,
+    ]
+}
+
+/// Confidence BinDiff's default configuration assigns to a basic block
+/// matching step
+pub fn default_basic_block_confidence(algorithm: &BasicBlockAlgorithm) -> f64 {
+    match algorithm {
+        BasicBlockAlgorithm::EdgesPrimeProduct
+        | BasicBlockAlgorithm::HashMatchingFourInstMin
+        | BasicBlockAlgorithm::Manual => 1.0,
+        BasicBlockAlgorithm::PrimeMatchingFourInstMin => 0.9,
+        BasicBlockAlgorithm::CallReferenceMatching
+        | BasicBlockAlgorithm::StringReferencesMatching => 0.8,
+        BasicBlockAlgorithm::EdgesMdIndexTopDown
+        | BasicBlockAlgorithm::MdIndexMatchingTopDown
+        | BasicBlockAlgorithm::EdgesMdIndexBottomUp
+        | BasicBlockAlgorithm::MdIndexMatchingBottomUp => 0.7,
+        BasicBlockAlgorithm::RelaxedMdIndexMatching => 0.6,
+        BasicBlockAlgorithm::PrimeMatchingNoInstMin => 0.5,
+        BasicBlockAlgorithm::EdgesLengauerTarjanDominated => 0.4,
+        BasicBlockAlgorithm::LoopEntryMatching | BasicBlockAlgorithm::SelfLoopMatching => 0.2,
+        BasicBlockAlgorithm::EntryPointMatching
+        | BasicBlockAlgorithm::ExitPointMatching
+        | BasicBlockAlgorithm::InstructionCountMatching
+        | BasicBlockAlgorithm::JumpSequenceMatching
+        | BasicBlockAlgorithm::PropagationSizeOne
+        | BasicBlockAlgorithm::None
+        | BasicBlockAlgorithm::Other(_) => 0.0,
+    }
+}
+
+/// Basic block and instruction matches of one function pair
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct BasicBlockMatches {
+    pub basic_blocks: Vec<BasicBlockMatch>,
+    /// Instruction matches, `id` being the id of their basic block match
+    pub instructions: Vec<Instruction>,
+    /// Number of flow graph edges whose endpoints are matched on both sides
+    pub edges: usize,
+}
+
+/// Matches the basic blocks of two functions by running a sequence of
+/// matching steps, each only considering blocks left unmatched by the steps
+/// before it
+#[derive(Debug, Clone)]
+pub struct BasicBlockMatcher {
+    steps: Vec<BasicBlockAlgorithm>,
+}
+
+impl Default for BasicBlockMatcher {
+    fn default() -> Self {
+        Self {
+            steps: default_basic_block_steps(),
+        }
+    }
+}
+
+impl BasicBlockMatcher {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Run `steps` in the given order instead of the default ones
+    pub fn with_steps(steps: Vec<BasicBlockAlgorithm>) -> Self {
+        Self { steps }
+    }
+
+    pub fn steps(&self) -> &[BasicBlockAlgorithm] {
+        &self.steps
+    }
+
+    /// Match the basic blocks of `primary` and `secondary`, the functions of
+    /// function match `function_id`. Basic block matches are numbered from
+    /// `first_id` in primary address order.
+    pub fn match_basic_blocks(
+        &self,
+        primary: &Function,
+        secondary: &Function,
+        function_id: i64,
+        first_id: i64,
+    ) -> BasicBlockMatches {
+        let mut state = State::new(Side::new(primary), Side::new(secondary));
+        for _ in 0..MAX_ROUNDS {
+            let before = state.pairs.len();
+            for step in &self.steps {
+                state.run(step);
+            }
+            if state.pairs.len() == before {
+                break;
+            }
+        }
+        state.into_matches(function_id, first_id)
+    }
+}
+
+/// Per-block features the matching steps key on
+struct Block<'a> {
+    block: BasicBlock<'a>,
+    address: u64,
+    hash: u64,
+    prime: u64,
+    instructions: usize,
+    md_top_down: f64,
+    md_bottom_up: f64,
+    /// Names of called functions, if any are named
+    calls: Option<u64>,
+    strings: Option<u64>,
+    loop_entry: bool,
+    self_loop: bool,
+    successors: Vec<usize>,
+    predecessors: Vec<usize>,
+}
+
+/// The basic blocks of one function, in address order
+struct Side<'a> {
+    blocks: Vec<Block<'a>>,
+    edges: Vec<(usize, usize)>,
+    /// Dominator tree edges for BinDiff's "edges Lengauer Tarjan dominated"
+    /// step. The tree does not depend on the algorithm that builds it, so it
+    /// is computed with Cooper-Harvey-Kennedy rather than Lengauer-Tarjan.
+    dominator_edges: Vec<(usize, usize)>,
+    entry: Option<usize>,
+}
+
+impl<'a> Side<'a> {
+    fn new(function: &Function<'a>) -> Self {
+        let binexport = function.binexport();
+        let blocks: Vec<BasicBlock<'a>> = function.basic_blocks().collect();
+        let index: HashMap<usize, usize> = blocks
+            .iter()
+            .enumerate()
+            .map(|(i, block)| (block.index(), i))
+            .collect();
+        let mut edges = Vec::new();
+        let mut back_edge_targets = HashSet::new();
+        for edge in function.edges() {
+            let (Some(&source), Some(&target)) = (
+                index.get(&edge.source.index()),
+                index.get(&edge.target.index()),
+            ) else {
+                continue;
+            };
+            if edge.is_back_edge {
+                back_edge_targets.insert(target);
+            }
+            edges.push((source, target));
+        }
+        let entry = function
+            .entry_basic_block()
+            .and_then(|block| index.get(&block.index()).copied());
+
+        let md_indices = |direction| -> Vec<f64> {
+            md_index::basic_block_md_indices(function, direction)
+                .into_iter()
+                .map(|(_, md_index)| md_index)
+                .collect()
+        };
+        let md_top_down = md_indices(Direction::TopDown);
+        let md_bottom_up = md_indices(Direction::BottomUp);
+
+        let mut successors = vec![Vec::new(); blocks.len()];
+        let mut predecessors = vec![Vec::new(); blocks.len()];
+        for &(source, target) in &edges {
+            successors[source].push(target);
+            predecessors[target].push(source);
+        }
+        let dominator_edges = entry
+            .map(|entry| {
+                immediate_dominators(&successors, &predecessors, entry)
+                    .into_iter()
+                    .enumerate()
+                    .filter_map(|(node, dominator)| Some((dominator?, node)))
+                    .filter(|&(dominator, node)| dominator != node)
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let blocks = blocks
+            .into_iter()
+            .enumerate()
+            .map(|(i, block)| {
+                let mut hasher = DefaultHasher::new();
+                let mut mnemonics = Vec::new();
+                let mut calls = Vec::new();
+                let mut strings = Vec::new();
+                for instruction in block.instructions() {
+                    instruction.raw_bytes().hash(&mut hasher);
+                    mnemonics.push(instruction.mnemonic());
+                    strings.extend(instruction.string_references());
+                    calls.extend(
+                        instruction
+                            .call_targets()
+                            .iter()
+                            .filter_map(|&target| binexport.function_name(target))
+                            .filter(|name| !is_generated_name(name)),
+                    );
+                }
+                Block {
+                    block,
+                    address: block.address(),
+                    hash: hasher.finish(),
+                    prime: prime_product(mnemonics.iter().copied()),
+                    instructions: mnemonics.len(),
+                    md_top_down: md_top_down[i],
+                    md_bottom_up: md_bottom_up[i],
+                    calls: hash_unordered(calls),
+                    strings: hash_unordered(strings),
+                    loop_entry: back_edge_targets.contains(&i),
+                    self_loop: successors[i].contains(&i),
+                    successors: std::mem::take(&mut successors[i]),
+                    predecessors: std::mem::take(&mut predecessors[i]),
+                }
+            })
+            .collect();
+
+        Self {
+            blocks,
+            edges,
+            dominator_edges,
+            entry,
+        }
+    }
+}
+
+/// Immediate dominator of every node reachable from `entry`, computed with the
+/// iterative algorithm of Cooper, Harvey and Kennedy
+fn immediate_dominators(
+    successors: &[Vec<usize>],
+    predecessors: &[Vec<usize>],
+    entry: usize,
+) -> Vec<Option<usize>> {
+    // Reverse postorder from the entry
+    let mut order = Vec::new();
+    let mut visited = vec![false; successors.len()];
+    let mut stack = vec![(entry, 0)];
+    visited[entry] = true;
+    while let Some((node, next)) = stack.pop() {
+        if let Some(&successor) = successors[node].get(next) {
+            stack.push((node, next + 1));
+            if !visited[successor] {
+                visited[successor] = true;
+                stack.push((successor, 0));
+            }
+        } else {
+            order.push(node);
+        }
+    }
+    order.reverse();
+    let mut position = vec![usize::MAX; successors.len()];
+    for (i, &node) in order.iter().enumerate() {
+        position[node] = i;
+    }
+
+    let mut dominators: Vec<Option<usize>> = vec![None; successors.len()];
+    dominators[entry] = Some(entry);
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for &node in order.iter().skip(1) {
+            let mut new_dominator: Option<usize> = None;
+            for &predecessor in &predecessors[node] {
+                if dominators[predecessor].is_none() {
+                    continue;
+                }
+                new_dominator = Some(match new_dominator {
+                    None => predecessor,
+                    Some(mut a) => {
+                        let mut b = predecessor;
+                        while a != b {
+                            while position[a] > position[b] {
+                                a = dominators[a].unwrap();
+                            }
+                            while position[b] > position[a] {
+                                b = dominators[b].unwrap();
+                            }
+                        }
+                        a
+                    }
+                });
+            }
+            if new_dominator.is_some() && dominators[node] != new_dominator {
+                dominators[node] = new_dominator;
+                changed = true;
+            }
+        }
+    }
+    dominators
+}
+
+struct State<'a> {
+    primary: Side<'a>,
+    secondary: Side<'a>,
+    pairs: Vec<(usize, usize, BasicBlockAlgorithm)>,
+    matched1: HashMap<usize, usize>,
+    matched2: HashMap<usize, usize>,
+}
+
+impl<'a> State<'a> {
+    fn new(primary: Side<'a>, secondary: Side<'a>) -> Self {
+        Self {
+            primary,
+            secondary,
+            pairs: Vec::new(),
+            matched1: HashMap::new(),
+            matched2: HashMap::new(),
+        }
+    }
+
+    fn try_match(&mut self, i: usize, j: usize, algorithm: &BasicBlockAlgorithm) -> bool {
+        if self.matched1.contains_key(&i) || self.matched2.contains_key(&j) {
+            return false;
+        }
+        self.matched1.insert(i, j);
+        self.matched2.insert(j, i);
+        self.pairs.push((i, j, algorithm.clone()));
+        true
+    }
+
+    fn run(&mut self, algorithm: &BasicBlockAlgorithm) {
+        match algorithm {
+            BasicBlockAlgorithm::EdgesPrimeProduct => {
+                self.match_edges(algorithm, false, |b| Some(b.prime))
+            }
+            BasicBlockAlgorithm::HashMatchingFourInstMin => {
+                self.match_unique(algorithm, |b| (b.instructions >= 4).then_some(b.hash))
+            }
+            BasicBlockAlgorithm::PrimeMatchingFourInstMin => {
+                self.match_unique(algorithm, |b| (b.instructions >= 4).then_some(b.prime))
+            }
+            BasicBlockAlgorithm::CallReferenceMatching => self.match_unique(algorithm, |b| b.calls),
+            BasicBlockAlgorithm::StringReferencesMatching => {
+                self.match_unique(algorithm, |b| b.strings)
+            }
+            BasicBlockAlgorithm::EdgesMdIndexTopDown => {
+                self.match_edges(algorithm, false, |b| Some(b.md_top_down.to_bits()))
+            }
+            BasicBlockAlgorithm::MdIndexMatchingTopDown => self.match_unique(algorithm, |b| {
+                (b.md_top_down > 0.0).then(|| b.md_top_down.to_bits())
+            }),
+            BasicBlockAlgorithm::EdgesMdIndexBottomUp => {
+                self.match_edges(algorithm, false, |b| Some(b.md_bottom_up.to_bits()))
+            }
+            BasicBlockAlgorithm::MdIndexMatchingBottomUp => self.match_unique(algorithm, |b| {
+                (b.md_bottom_up > 0.0).then(|| b.md_bottom_up.to_bits())
+            }),
+            BasicBlockAlgorithm::RelaxedMdIndexMatching => self.match_unique(algorithm, |b| {
+                Some((b.md_top_down.to_bits(), b.md_bottom_up.to_bits()))
+            }),
+            BasicBlockAlgorithm::PrimeMatchingNoInstMin => {
+                self.match_unique(algorithm, |b| Some(b.prime))
+            }
+            BasicBlockAlgorithm::EdgesLengauerTarjanDominated => {
+                self.match_edges(algorithm, true, |b| Some(b.prime))
+            }
+            BasicBlockAlgorithm::LoopEntryMatching => {
+                self.match_unique(algorithm, |b| b.loop_entry.then_some(()))
+            }
+            BasicBlockAlgorithm::SelfLoopMatching => {
+                self.match_unique(algorithm, |b| b.self_loop.then_some(()))
+            }
+            BasicBlockAlgorithm::EntryPointMatching => {
+                if let (Some(i), Some(j)) = (self.primary.entry, self.secondary.entry) {
+                    self.try_match(i, j, algorithm);
+                }
+            }
+            BasicBlockAlgorithm::ExitPointMatching => {
+                self.match_unique(algorithm, |b| b.successors.is_empty().then_some(b.prime));
+                self.match_unique(algorithm, |b| b.successors.is_empty().then_some(()));
+            }
+            BasicBlockAlgorithm::InstructionCountMatching => {
+                self.match_unique(algorithm, |b| Some(b.instructions))
+            }
+            BasicBlockAlgorithm::JumpSequenceMatching => self.match_jump_sequences(algorithm),
+            BasicBlockAlgorithm::PropagationSizeOne => self.match_propagation(algorithm),
+            BasicBlockAlgorithm::None
+            | BasicBlockAlgorithm::Manual
+            | BasicBlockAlgorithm::Other(_) => {}
+        }
+    }
+
+    /// Match unmatched blocks whose key is unique on both sides
+    fn match_unique<K, F>(&mut self, algorithm: &BasicBlockAlgorithm, key: F)
+    where
+        K: Hash + Eq,
+        F: Fn(&Block) -> Option<K>,
+    {
+        let groups1 = group(&self.primary.blocks, &self.matched1, &key);
+        let groups2 = group(&self.secondary.blocks, &self.matched2, &key);
+        for (i, j) in unique_pairs(&groups1, &groups2) {
+            self.try_match(i, j, algorithm);
+        }
+    }
+
+    /// Match the endpoints of flow graph (or dominator tree) edges whose
+    /// (source, target) key pair is unique on both sides
+    fn match_edges<K, F>(&mut self, algorithm: &BasicBlockAlgorithm, dominator_tree: bool, key: F)
+    where
+        K: Hash + Eq,
+        F: Fn(&Block) -> Option<K>,
+    {
+        let edge_groups = |side: &Side, matched: &HashMap<usize, usize>| {
+            let edges = if dominator_tree {
+                &side.dominator_edges
+            } else {
+                &side.edges
+            };
+            let mut groups: HashMap<(K, K), Vec<(usize, usize)>> = HashMap::new();
+            for &(s, t) in edges {
+                if matched.contains_key(&s) && matched.contains_key(&t) {
+                    continue;
+                }
+                if let (Some(ks), Some(kt)) = (key(&side.blocks[s]), key(&side.blocks[t])) {
+                    groups.entry((ks, kt)).or_default().push((s, t));
+                }
+            }
+            groups
+        };
+        let groups1 = edge_groups(&self.primary, &self.matched1);
+        let groups2 = edge_groups(&self.secondary, &self.matched2);
+        for ((s1, t1), (s2, t2)) in unique_pairs(&groups1, &groups2) {
+            self.try_match(s1, s2, algorithm);
+            self.try_match(t1, t2, algorithm);
+        }
+    }
+
+    /// Match the unmatched successors of matched blocks by their position when
+    /// both sides branch to the same number of them
+    fn match_jump_sequences(&mut self, algorithm: &BasicBlockAlgorithm) {
+        for (i, j) in self.matched_pairs() {
+            let successors1 = self.unmatched_by_address(&self.primary, &self.matched1, i, true);
+            let successors2 = self.unmatched_by_address(&self.secondary, &self.matched2, j, true);
+            if successors1.len() == successors2.len() {
+                for (s1, s2) in successors1.into_iter().zip(successors2) {
+                    self.try_match(s1, s2, algorithm);
+                }
+            }
+        }
+    }
+
+    /// Match the single unmatched successor (or predecessor) of matched blocks
+    fn match_propagation(&mut self, algorithm: &BasicBlockAlgorithm) {
+        for (i, j) in self.matched_pairs() {
+            for successors in [true, false] {
+                let unmatched1 =
+                    self.unmatched_by_address(&self.primary, &self.matched1, i, successors);
+                let unmatched2 =
+                    self.unmatched_by_address(&self.secondary, &self.matched2, j, successors);
+                if let ([n1], [n2]) = (unmatched1.as_slice(), unmatched2.as_slice()) {
+                    self.try_match(*n1, *n2, algorithm);
+                }
+            }
+        }
+    }
+
+    fn matched_pairs(&self) -> Vec<(usize, usize)> {
+        let mut pairs: Vec<(usize, usize)> = self.matched1.iter().map(|(&i, &j)| (i, j)).collect();
+        pairs.sort_unstable();
+        pairs
+    }
+
+    /// Distinct unmatched successors (or predecessors) of a block, by address
+    fn unmatched_by_address(
+        &self,
+        side: &Side,
+        matched: &HashMap<usize, usize>,
+        block: usize,
+        successors: bool,
+    ) -> Vec<usize> {
+        let block = &side.blocks[block];
+        let neighbors = if successors {
+            &block.successors
+        } else {
+            &block.predecessors
+        };
+        let mut unmatched: Vec<usize> = neighbors
+            .iter()
+            .copied()
+            .filter(|n| !matched.contains_key(n))
+            .collect();
+        unmatched.sort_by_key(|&n| side.blocks[n].address);
+        unmatched.dedup();
+        unmatched
+    }
+
+    fn into_matches(self, function_id: i64, first_id: i64) -> BasicBlockMatches {
+        let edges = self
+            .primary
+            .edges
+            .iter()
+            .filter(
+                |&&(s, t)| match (self.matched1.get(&s), self.matched1.get(&t)) {
+                    (Some(s2), Some(t2)) => self.secondary.blocks[*s2].successors.contains(t2),
+                    _ => false,
+                },
+            )
+            .count();
+
+        let mut pairs = self.pairs;
+        pairs.sort_by_key(|&(i, _, _)| self.primary.blocks[i].address);
+        let mut matches = BasicBlockMatches {
+            edges,
+            ..Default::default()
+        };
+        for (id, (i, j, algorithm)) in (first_id..).zip(pairs) {
+            let block1 = &self.primary.blocks[i];
+            let block2 = &self.secondary.blocks[j];
+            matches.basic_blocks.push(BasicBlockMatch {
+                id,
+                function_id,
+                address1: block1.address as i64,
+                address2: block2.address as i64,
+                algorithm,
+                evaluate: false,
+            });
+
+            let instructions1: Vec<_> = block1.block.instructions().collect();
+            let instructions2: Vec<_> = block2.block.instructions().collect();
+            let mnemonics1: Vec<&str> = instructions1.iter().map(|i| i.mnemonic()).collect();
+            let mnemonics2: Vec<&str> = instructions2.iter().map(|i| i.mnemonic()).collect();
+            for (a, b) in lcs(&mnemonics1, &mnemonics2) {
+                matches.instructions.push(Instruction {
+                    id,
+                    address1: instructions1[a].address() as i64,
+                    address2: instructions2[b].address() as i64,
+                });
+            }
+        }
+        matches
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::{binexport, FunctionSpec};
+
+    #[test]
+    fn test_match_basic_blocks() {
+        let primary = binexport(&[FunctionSpec::new(
+            0x1000,
+            "foo",
+            vec![
+                vec!["cmp x0, #0x0", "b.eq 0x100c"],
+                vec!["mov x0, #0x1"],
+                vec!["ret"],
+            ],
+        )
+        .edges(&[(0, 1), (0, 2), (1, 2)])]);
+        let secondary = binexport(&[FunctionSpec::new(
+            0x2000,
+            "foo",
+            vec![
+                vec!["cmp x0, #0x0", "b.eq 0x2010"],
+                vec!["mov x0, #0x2", "nop"],
+                vec!["ret"],
+                vec!["brk #0x1"],
+            ],
+        )
+        .edges(&[(0, 1), (0, 2), (1, 2)])]);
+
+        let matches = BasicBlockMatcher::new().match_basic_blocks(
+            &primary.function(0x1000).unwrap(),
+            &secondary.function(0x2000).unwrap(),
+            7,
+            1,
+        );
+        let pairs: Vec<(i64, i64, i64)> = matches
+            .basic_blocks
+            .iter()
+            .map(|m| (m.id, m.address1, m.address2))
+            .collect();
+        assert_eq!(
+            pairs,
+            [
+                (1, 0x1000, 0x2000),
+                (2, 0x1008, 0x2008),
+                (3, 0x100c, 0x2010)
+            ]
+        );
+        assert!(matches.basic_blocks.iter().all(|m| m.function_id == 7));
+        assert_eq!(matches.edges, 3);
+        // cmp, b.eq, mov and ret; the added nop has no counterpart
+        assert_eq!(matches.instructions.len(), 4);
+        assert_eq!(matches.instructions[2].id, 2);
+        assert_eq!(matches.instructions[2].address2, 0x2008);
+    }
+
+    #[test]
+    fn test_immediate_dominators() {
+        // 0 -> 1 -> 3, 0 -> 2 -> 3, 3 -> 1
+        let successors = vec![vec![1, 2], vec![3], vec![3], vec![1]];
+        let predecessors = vec![vec![], vec![0, 3], vec![0], vec![1, 2]];
+        assert_eq!(
+            immediate_dominators(&successors, &predecessors, 0),
+            [Some(0), Some(0), Some(0), Some(0)]
+        );
+    }
+}
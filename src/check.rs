@@ -0,0 +1,477 @@
+//! Pass/fail checks of a BinDiff result against thresholds, for gating release
+//! pipelines on unexpected code changes.
+
+use crate::session::DiffSession;
+use crate::unmatched::{self, UnmatchedFunction};
+use crate::{BinDiff, BinExport, File, FunctionMatch, Metadata};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+/// Limits a diff has to stay within. Unset limits and empty lists always pass.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Thresholds {
+    /// Lowest allowed overall similarity
+    pub min_similarity: Option<f64>,
+    /// Lowest allowed overall confidence
+    pub min_confidence: Option<f64>,
+    /// Most matches allowed to have a similarity below 1.0
+    pub max_changed: Option<usize>,
+    /// Most functions of both binaries allowed to stay unmatched, counting
+    /// only functions with a flow graph (imports can never be matched)
+    pub max_unmatched: Option<usize>,
+    /// Function names that must be matched and unchanged
+    pub deny: Vec<String>,
+    /// If not empty, the only function names allowed to change
+    pub allow: Vec<String>,
+}
+
+/// A threshold a diff does not meet
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Violation {
+    SimilarityBelow {
+        similarity: f64,
+        threshold: f64,
+    },
+    ConfidenceBelow {
+        confidence: f64,
+        threshold: f64,
+    },
+    TooManyChanged {
+        changed: usize,
+        limit: usize,
+    },
+    TooManyUnmatched {
+        unmatched: usize,
+        limit: usize,
+    },
+    /// A function of the deny list changed
+    DeniedChanged {
+        address1: u64,
+        name: String,
+        similarity: f64,
+    },
+    /// A function of the deny list is not matched, e.g. it was removed
+    DeniedUnmatched {
+        name: String,
+    },
+    /// A function outside the allow list changed
+    NotAllowedChanged {
+        address1: u64,
+        name: String,
+        similarity: f64,
+    },
+}
+
+impl std::fmt::Display for Violation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Violation::SimilarityBelow {
+                similarity,
+                threshold,
+            } => write!(f, "similarity {:.3} is below {:.3}", similarity, threshold),
+            Violation::ConfidenceBelow {
+                confidence,
+                threshold,
+            } => write!(f, "confidence {:.3} is below {:.3}", confidence, threshold),
+            Violation::TooManyChanged { changed, limit } => {
+                write!(
+                    f,
+                    "{} changed functions, at most {} allowed",
+                    changed, limit
+                )
+            }
+            Violation::TooManyUnmatched { unmatched, limit } => write!(
+                f,
+                "{} unmatched functions, at most {} allowed",
+                unmatched, limit
+            ),
+            Violation::DeniedChanged {
+                address1,
+                name,
+                similarity,
+            } => write!(
+                f,
+                "{:#x} {} changed (similarity {:.3})",
+                address1, name, similarity
+            ),
+            Violation::DeniedUnmatched { name } => write!(f, "{} is not matched", name),
+            Violation::NotAllowedChanged {
+                address1,
+                name,
+                similarity,
+            } => write!(
+                f,
+                "{:#x} {} changed outside the allow list (similarity {:.3})",
+                address1, name, similarity
+            ),
+        }
+    }
+}
+
+/// Outcome of checking a diff against `Thresholds`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CheckReport {
+    pub passed: bool,
+    pub similarity: f64,
+    pub confidence: f64,
+    pub matched: usize,
+    /// Matches with a similarity below 1.0
+    pub changed: usize,
+    /// Primary functions with a flow graph but without a match
+    pub unmatched_primary: usize,
+    /// Secondary functions with a flow graph but without a match
+    pub unmatched_secondary: usize,
+    pub violations: Vec<Violation>,
+}
+
+impl std::fmt::Display for CheckReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "CHECK: {}",
+            if self.passed { "passed" } else { "FAILED" }
+        )?;
+        writeln!(f, "  similarity:          {:.3}", self.similarity)?;
+        writeln!(f, "  confidence:          {:.3}", self.confidence)?;
+        writeln!(f, "  matched:             {}", self.matched)?;
+        writeln!(f, "  changed:             {}", self.changed)?;
+        writeln!(f, "  unmatched_primary:   {}", self.unmatched_primary)?;
+        writeln!(f, "  unmatched_secondary: {}", self.unmatched_secondary)?;
+        if !self.violations.is_empty() {
+            writeln!(f, "VIOLATIONS ({}):", self.violations.len())?;
+            for violation in &self.violations {
+                writeln!(f, "  {}", violation)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Check the overall scores, function matches and function counts of a diff.
+/// Unmatched functions are estimated from the function totals of both files,
+/// library functions included.
+pub fn check_matches(
+    metadata: &Metadata,
+    primary: &File,
+    secondary: &File,
+    matches: &[FunctionMatch],
+    thresholds: &Thresholds,
+) -> CheckReport {
+    let unmatched = |file: &File| {
+        ((file.functions + file.lib_functions) as usize).saturating_sub(matches.len())
+    };
+    check_counts(
+        metadata,
+        (unmatched(primary), unmatched(secondary)),
+        matches,
+        thresholds,
+    )
+}
+
+fn check_counts(
+    metadata: &Metadata,
+    (unmatched_primary, unmatched_secondary): (usize, usize),
+    matches: &[FunctionMatch],
+    thresholds: &Thresholds,
+) -> CheckReport {
+    let mut violations = Vec::new();
+    if let Some(threshold) = thresholds.min_similarity {
+        if metadata.similarity < threshold {
+            violations.push(Violation::SimilarityBelow {
+                similarity: metadata.similarity,
+                threshold,
+            });
+        }
+    }
+    if let Some(threshold) = thresholds.min_confidence {
+        if metadata.confidence < threshold {
+            violations.push(Violation::ConfidenceBelow {
+                confidence: metadata.confidence,
+                threshold,
+            });
+        }
+    }
+
+    let mut changed: Vec<&FunctionMatch> = matches.iter().filter(|m| m.similarity < 1.0).collect();
+    changed.sort_by_key(|m| m.address1);
+    if let Some(limit) = thresholds.max_changed {
+        if changed.len() > limit {
+            violations.push(Violation::TooManyChanged {
+                changed: changed.len(),
+                limit,
+            });
+        }
+    }
+
+    if let Some(limit) = thresholds.max_unmatched {
+        let unmatched = unmatched_primary + unmatched_secondary;
+        if unmatched > limit {
+            violations.push(Violation::TooManyUnmatched { unmatched, limit });
+        }
+    }
+
+    let named = |m: &FunctionMatch, names: &HashSet<&str>| {
+        names.contains(m.name1.as_str()) || names.contains(m.name2.as_str())
+    };
+    let deny: HashSet<&str> = thresholds.deny.iter().map(String::as_str).collect();
+    for name in &thresholds.deny {
+        if !matches.iter().any(|m| &m.name1 == name || &m.name2 == name) {
+            violations.push(Violation::DeniedUnmatched { name: name.clone() });
+        }
+    }
+    let allow: HashSet<&str> = thresholds.allow.iter().map(String::as_str).collect();
+    for m in &changed {
+        if named(m, &deny) {
+            violations.push(Violation::DeniedChanged {
+                address1: m.address1 as u64,
+                name: m.name1.clone(),
+                similarity: m.similarity,
+            });
+        } else if !allow.is_empty() && !named(m, &allow) {
+            violations.push(Violation::NotAllowedChanged {
+                address1: m.address1 as u64,
+                name: m.name1.clone(),
+                similarity: m.similarity,
+            });
+        }
+    }
+
+    CheckReport {
+        passed: violations.is_empty(),
+        similarity: metadata.similarity,
+        confidence: metadata.confidence,
+        matched: matches.len(),
+        changed: changed.len(),
+        unmatched_primary,
+        unmatched_secondary,
+        violations,
+    }
+}
+
+/// Check a BinDiff database against `thresholds`
+pub fn check(bindiff: &BinDiff, thresholds: &Thresholds) -> Result<CheckReport> {
+    let metadata = bindiff.read_metadata()?;
+    Ok(check_matches(
+        &metadata,
+        &bindiff.read_file_by_id(metadata.file1)?,
+        &bindiff.read_file_by_id(metadata.file2)?,
+        &bindiff.read_function_matches()?,
+        thresholds,
+    ))
+}
+
+/// Check a BinDiff database against `thresholds`, counting the unmatched
+/// functions of the session's BinExport files directly. Like the `File`
+/// totals `check_matches` estimates from, only functions with a flow graph
+/// count, so both agree on what is unmatched.
+pub fn check_session(session: &DiffSession, thresholds: &Thresholds) -> Result<CheckReport> {
+    let matches = session.bindiff().read_function_matches()?;
+    let unmatched =
+        unmatched::unmatched_functions(session.primary(), session.secondary(), &matches);
+    let count = |functions: &[UnmatchedFunction], binexport: &BinExport| {
+        functions
+            .iter()
+            .filter(|function| binexport.function(function.address).is_some())
+            .count()
+    };
+    Ok(check_counts(
+        session.metadata(),
+        (
+            count(&unmatched.primary, session.primary()),
+            count(&unmatched.secondary, session.secondary()),
+        ),
+        &matches,
+        thresholds,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::binexport::bin_export2::call_graph::{vertex, Vertex};
+    use crate::testutil::{binexport, function_match, FunctionSpec};
+
+    #[test]
+    fn test_check_matches() {
+        let named = |address1, name: &str, similarity| FunctionMatch {
+            name1: name.to_string(),
+            name2: name.to_string(),
+            similarity,
+            ..function_match(address1, address1 + 0x100)
+        };
+        let file = |id, functions, lib_functions| File {
+            id,
+            filename: String::new(),
+            exe_filename: String::new(),
+            hash: String::new(),
+            functions,
+            lib_functions,
+            calls: 0,
+            basic_blocks: 0,
+            lib_basic_blocks: 0,
+            edges: 0,
+            lib_edges: 0,
+            instructions: 0,
+            lib_instructions: 0,
+        };
+        let metadata = Metadata {
+            version: "BinDiff 8".to_string(),
+            file1: 1,
+            file2: 1,
+            description: String::new(),
+            created: chrono::Utc::now(),
+            modified: chrono::Utc::now(),
+            similarity: 0.9,
+            confidence: 0.95,
+        };
+        let matches = [
+            named(0x1000, "_vm_map_copyin", 0.7),
+            named(0x2000, "_vm_map_enter", 1.0),
+            named(0x3000, "_ipc_kmsg_send", 0.5),
+        ];
+        let (primary, secondary) = (file(1, 4, 0), file(2, 5, 0));
+
+        let report = check_matches(
+            &metadata,
+            &primary,
+            &secondary,
+            &matches,
+            &Thresholds::default(),
+        );
+        assert!(report.passed);
+        assert_eq!(report.changed, 2);
+        assert_eq!(
+            (report.unmatched_primary, report.unmatched_secondary),
+            (1, 2)
+        );
+
+        // Matched library functions count against the library totals
+        let report = check_matches(
+            &metadata,
+            &file(1, 2, 2),
+            &file(2, 1, 3),
+            &matches,
+            &Thresholds::default(),
+        );
+        assert_eq!(
+            (report.unmatched_primary, report.unmatched_secondary),
+            (1, 1)
+        );
+
+        let report = check_matches(
+            &metadata,
+            &primary,
+            &secondary,
+            &matches,
+            &Thresholds {
+                min_similarity: Some(0.95),
+                max_unmatched: Some(2),
+                deny: vec!["_vm_map_copyin".to_string(), "_removed".to_string()],
+                allow: vec!["_vm_map_copyin".to_string()],
+                ..Default::default()
+            },
+        );
+        assert!(!report.passed);
+        assert_eq!(
+            report.violations,
+            [
+                Violation::SimilarityBelow {
+                    similarity: 0.9,
+                    threshold: 0.95
+                },
+                Violation::TooManyUnmatched {
+                    unmatched: 3,
+                    limit: 2
+                },
+                Violation::DeniedUnmatched {
+                    name: "_removed".to_string()
+                },
+                Violation::DeniedChanged {
+                    address1: 0x1000,
+                    name: "_vm_map_copyin".to_string(),
+                    similarity: 0.7
+                },
+                Violation::NotAllowedChanged {
+                    address1: 0x3000,
+                    name: "_ipc_kmsg_send".to_string(),
+                    similarity: 0.5
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_check_session() -> Result<()> {
+        let path =
+            std::env::temp_dir().join(format!("bindiff-rs-check-{}.BinDiff", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let db = BinDiff::create(&path)?;
+        // An import has a call graph vertex but no flow graph
+        let with_import = |functions: &[FunctionSpec]| {
+            let mut proto = binexport(functions).binexport;
+            let call_graph = proto.call_graph.as_mut().unwrap();
+            call_graph.vertex.push(Vertex {
+                address: Some(0x9000),
+                mangled_name: Some("_memcpy".to_string()),
+                r#type: Some(vertex::Type::Imported as i32),
+                ..Default::default()
+            });
+            BinExport::new(proto)
+        };
+        let primary = with_import(&[
+            FunctionSpec::new(0x1000, "foo", vec![vec!["ret"]]),
+            FunctionSpec::new(0x2000, "removed", vec![vec!["nop", "ret"]]),
+        ]);
+        let secondary = with_import(&[
+            FunctionSpec::new(0x1000, "foo", vec![vec!["ret"]]),
+            FunctionSpec::new(0x3000, "added", vec![vec!["b"]]),
+        ]);
+        // Both sides share one `file` row: two functions with a flow graph
+        db.write_file(&File {
+            id: 1,
+            filename: "test".to_string(),
+            exe_filename: "test".to_string(),
+            hash: "0123456789abcdef".to_string(),
+            functions: 2,
+            lib_functions: 0,
+            calls: 0,
+            basic_blocks: 2,
+            lib_basic_blocks: 0,
+            edges: 0,
+            lib_edges: 0,
+            instructions: 3,
+            lib_instructions: 0,
+        })?;
+        db.write_metadata(&Metadata {
+            version: "BinDiff 8".to_string(),
+            file1: 1,
+            file2: 1,
+            description: String::new(),
+            created: chrono::Utc::now(),
+            modified: chrono::Utc::now(),
+            similarity: 0.9,
+            confidence: 0.95,
+        })?;
+        db.write_function_matches(&[function_match(0x1000, 0x1000)])?;
+
+        let thresholds = Thresholds {
+            max_unmatched: Some(2),
+            ..Default::default()
+        };
+        let estimated = check(&db, &thresholds)?;
+        let session = DiffSession::new(db, primary, secondary)?;
+        let counted = check_session(&session, &thresholds)?;
+        for report in [&estimated, &counted] {
+            assert!(report.passed);
+            assert_eq!(
+                (report.unmatched_primary, report.unmatched_secondary),
+                (1, 1)
+            );
+        }
+
+        session.close()?;
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,339 @@
+//! Comment porting: carry the comments of the primary BinExport over to the
+//! matched addresses of the secondary binary, as a BinExport comment set or as
+//! IDA and Ghidra import scripts.
+
+use crate::binexport::bin_export2::{self, comment};
+use crate::binexport::BinExport2;
+use crate::symbols::python_string;
+use crate::{BasicBlockMatch, BinExport, FunctionMatch, Instruction};
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Kind of a comment, mirroring BinExport's comment types
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CommentType {
+    Default,
+    Anterior,
+    Posterior,
+    Function,
+    Enum,
+    Location,
+    GlobalReference,
+    LocalReference,
+}
+
+impl From<comment::Type> for CommentType {
+    fn from(value: comment::Type) -> Self {
+        match value {
+            comment::Type::Default => CommentType::Default,
+            comment::Type::Anterior => CommentType::Anterior,
+            comment::Type::Posterior => CommentType::Posterior,
+            comment::Type::Function => CommentType::Function,
+            comment::Type::Enum => CommentType::Enum,
+            comment::Type::Location => CommentType::Location,
+            comment::Type::GlobalReference => CommentType::GlobalReference,
+            comment::Type::LocalReference => CommentType::LocalReference,
+        }
+    }
+}
+
+impl From<CommentType> for comment::Type {
+    fn from(value: CommentType) -> Self {
+        match value {
+            CommentType::Default => comment::Type::Default,
+            CommentType::Anterior => comment::Type::Anterior,
+            CommentType::Posterior => comment::Type::Posterior,
+            CommentType::Function => comment::Type::Function,
+            CommentType::Enum => comment::Type::Enum,
+            CommentType::Location => comment::Type::Location,
+            CommentType::GlobalReference => comment::Type::GlobalReference,
+            CommentType::LocalReference => comment::Type::LocalReference,
+        }
+    }
+}
+
+/// Which match a comment was ported through, from most to least precise
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PortedVia {
+    Instruction,
+    BasicBlock,
+    Function,
+}
+
+/// A primary comment placed at its matched secondary address
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PortedComment {
+    pub address1: u64,
+    pub address2: u64,
+    pub comment: String,
+    pub comment_type: CommentType,
+    pub repeatable: bool,
+    /// Operand the comment is attached to, 0 for the whole instruction
+    pub operand_index: i32,
+    pub via: PortedVia,
+}
+
+impl std::fmt::Display for PortedComment {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{:#x} -> {:#x}\t{:?}: {}",
+            self.address1, self.address2, self.comment_type, self.comment
+        )
+    }
+}
+
+/// Output format of ported comments
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CommentFormat {
+    Json,
+    /// IDAPython script
+    Ida,
+    /// Ghidra Python script
+    Ghidra,
+}
+
+impl std::str::FromStr for CommentFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "json" => Ok(CommentFormat::Json),
+            "ida" | "idapython" => Ok(CommentFormat::Ida),
+            "ghidra" => Ok(CommentFormat::Ghidra),
+            _ => bail!("Unknown comment format: {}", s),
+        }
+    }
+}
+
+/// Map the comments of `primary` to secondary addresses. A comment follows its
+/// instruction's match; comments on unmatched instructions fall back to the
+/// match of the basic block or function they start. Comments without any
+/// matching address are dropped. Sorted by secondary address.
+pub fn port_comments(
+    primary: &BinExport,
+    function_matches: &[FunctionMatch],
+    basic_block_matches: &[BasicBlockMatch],
+    instruction_matches: &[Instruction],
+) -> Vec<PortedComment> {
+    let instructions: HashMap<u64, u64> = instruction_matches
+        .iter()
+        .map(|m| (m.address1 as u64, m.address2 as u64))
+        .collect();
+    let basic_blocks: HashMap<u64, u64> = basic_block_matches
+        .iter()
+        .map(|m| (m.address1 as u64, m.address2 as u64))
+        .collect();
+    let functions: HashMap<u64, u64> = function_matches
+        .iter()
+        .map(|m| (m.address1 as u64, m.address2 as u64))
+        .collect();
+
+    let mut comments: Vec<PortedComment> = primary
+        .binexport
+        .comment
+        .iter()
+        .filter_map(|comment| {
+            let address1 = primary.instruction_address(comment.instruction_index() as usize)?;
+            let text = primary
+                .binexport
+                .string_table
+                .get(comment.string_table_index() as usize)?;
+            let (address2, via) = if let Some(&address2) = instructions.get(&address1) {
+                (address2, PortedVia::Instruction)
+            } else if let Some(&address2) = basic_blocks.get(&address1) {
+                (address2, PortedVia::BasicBlock)
+            } else {
+                (*functions.get(&address1)?, PortedVia::Function)
+            };
+            Some(PortedComment {
+                address1,
+                address2,
+                comment: text.clone(),
+                comment_type: comment.r#type().into(),
+                repeatable: comment.repeatable(),
+                operand_index: comment.instruction_operand_index(),
+                via,
+            })
+        })
+        .collect();
+    comments.sort_by_key(|comment| (comment.address2, comment.address1));
+    comments
+}
+
+/// Add `comments` to the secondary BinExport message, appending their text to
+/// the string table and linking them from their instructions. Comments at
+/// addresses without an instruction are skipped; returns how many were added.
+pub fn apply_comments(
+    secondary: &BinExport,
+    binexport: &mut BinExport2,
+    comments: &[PortedComment],
+) -> usize {
+    let instruction_indices: HashMap<u64, usize> = (0..binexport.instruction.len())
+        .filter_map(|index| Some((secondary.instruction_address(index)?, index)))
+        .collect();
+
+    let mut added = 0;
+    for ported in comments {
+        let Some(&instruction_index) = instruction_indices.get(&ported.address2) else {
+            continue;
+        };
+        binexport.string_table.push(ported.comment.clone());
+        binexport.comment.push(bin_export2::Comment {
+            instruction_index: Some(instruction_index as i32),
+            instruction_operand_index: Some(ported.operand_index),
+            operand_expression_index: None,
+            string_table_index: Some(binexport.string_table.len() as i32 - 1),
+            repeatable: Some(ported.repeatable),
+            r#type: Some(comment::Type::from(ported.comment_type) as i32),
+        });
+        binexport.instruction[instruction_index]
+            .comment_index
+            .push(binexport.comment.len() as i32 - 1);
+        added += 1;
+    }
+    added
+}
+
+/// Render ported comments in `format`
+pub fn render_comments(comments: &[PortedComment], format: CommentFormat) -> Result<String> {
+    let mut out = String::new();
+    match format {
+        CommentFormat::Json => {
+            out = serde_json::to_string_pretty(comments)?;
+            out.push('\n');
+        }
+        CommentFormat::Ida => {
+            out.push_str("import idc\nimport ida_lines\n\n");
+            for ported in comments {
+                let text = python_string(&ported.comment);
+                let repeatable = if ported.repeatable { "True" } else { "False" };
+                let line = match ported.comment_type {
+                    CommentType::Function => format!(
+                        "idc.set_func_cmt({:#x}, {}, {})",
+                        ported.address2, text, repeatable
+                    ),
+                    CommentType::Anterior => format!(
+                        "ida_lines.add_extra_cmt({:#x}, True, {})",
+                        ported.address2, text
+                    ),
+                    CommentType::Posterior => format!(
+                        "ida_lines.add_extra_cmt({:#x}, False, {})",
+                        ported.address2, text
+                    ),
+                    _ => format!(
+                        "idc.set_cmt({:#x}, {}, {})",
+                        ported.address2, text, repeatable
+                    ),
+                };
+                out.push_str(&line);
+                out.push('\n');
+            }
+        }
+        CommentFormat::Ghidra => {
+            out.push_str("# Ported comments, run from Ghidra's script manager\n\n");
+            for ported in comments {
+                let setter = match ported.comment_type {
+                    CommentType::Function => "setPlateComment",
+                    CommentType::Anterior => "setPreComment",
+                    CommentType::Posterior => "setPostComment",
+                    _ if ported.repeatable => "setRepeatableComment",
+                    _ => "setEOLComment",
+                };
+                out.push_str(&format!(
+                    "{}(toAddr({:#x}), {})\n",
+                    setter,
+                    ported.address2,
+                    python_string(&ported.comment)
+                ));
+            }
+        }
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::{binexport, function_match, FunctionSpec};
+    use crate::BasicBlockAlgorithm;
+
+    #[test]
+    fn test_port_comments() -> Result<()> {
+        let spec = |address| {
+            FunctionSpec::new(address, "foo", vec![vec!["cbz x0", "nop"], vec!["ret"]])
+                .edges(&[(0, 1)])
+        };
+        let mut primary = binexport(&[spec(0x1000)]);
+        let secondary = binexport(&[spec(0x2000)]);
+        // A comment on each instruction, matched through different matches
+        for (index, (text, kind)) in [
+            ("entry", comment::Type::Function),
+            ("checked", comment::Type::Default),
+            ("done", comment::Type::Anterior),
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            primary.binexport.string_table.push(text.to_string());
+            primary.binexport.comment.push(bin_export2::Comment {
+                instruction_index: Some(index as i32),
+                string_table_index: Some(index as i32),
+                r#type: Some(kind as i32),
+                ..Default::default()
+            });
+        }
+
+        let function_matches = [function_match(0x1000, 0x2000)];
+        let basic_block_matches = [BasicBlockMatch {
+            id: 1,
+            function_id: 1,
+            address1: 0x1008,
+            address2: 0x2008,
+            algorithm: BasicBlockAlgorithm::EdgesPrimeProduct,
+            evaluate: false,
+        }];
+        let instruction_matches = [Instruction {
+            id: 1,
+            address1: 0x1004,
+            address2: 0x2004,
+        }];
+
+        let comments = port_comments(
+            &primary,
+            &function_matches,
+            &basic_block_matches,
+            &instruction_matches,
+        );
+        let ported: Vec<(u64, &str, PortedVia)> = comments
+            .iter()
+            .map(|c| (c.address2, c.comment.as_str(), c.via))
+            .collect();
+        assert_eq!(
+            ported,
+            [
+                (0x2000, "entry", PortedVia::Function),
+                (0x2004, "checked", PortedVia::Instruction),
+                (0x2008, "done", PortedVia::BasicBlock),
+            ]
+        );
+
+        let mut proto = secondary.binexport.clone();
+        assert_eq!(apply_comments(&secondary, &mut proto, &comments), 3);
+        let exported = BinExport::new(proto);
+        assert_eq!(exported.binexport.comment.len(), 3);
+        assert_eq!(exported.binexport.instruction[2].comment_index, [2]);
+        assert_eq!(
+            exported.binexport.comment[0].r#type(),
+            comment::Type::Function
+        );
+
+        let ida = render_comments(&comments, CommentFormat::Ida)?;
+        assert!(ida.contains("idc.set_func_cmt(0x2000, \"entry\", False)"));
+        assert!(ida.contains("ida_lines.add_extra_cmt(0x2008, True, \"done\")"));
+        let ghidra = render_comments(&comments, CommentFormat::Ghidra)?;
+        assert!(ghidra.contains("setEOLComment(toAddr(0x2004), \"checked\")"));
+        Ok(())
+    }
+}
@@ -0,0 +1,254 @@
+//! Differences between two BinDiff results of the same binary pair, e.g. after
+//! upgrading BinDiff or changing the matching config. Matches are keyed by
+//! their primary address.
+
+use crate::{BinDiff, FunctionAlgorithm, FunctionMatch};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+/// A primary function matched to different secondary functions
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChangedPartner {
+    pub address1: u64,
+    pub name1: String,
+    pub baseline: FunctionMatch,
+    pub candidate: FunctionMatch,
+}
+
+impl std::fmt::Display for ChangedPartner {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{:#x} {}\t{:#x} {} ({}) -> {:#x} {} ({})",
+            self.address1,
+            self.name1,
+            self.baseline.address2,
+            self.baseline.name2,
+            self.baseline.algorithm,
+            self.candidate.address2,
+            self.candidate.name2,
+            self.candidate.algorithm
+        )
+    }
+}
+
+/// A function pair matched by both results with different scores or steps
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MatchDelta {
+    pub address1: u64,
+    pub name1: String,
+    pub address2: u64,
+    pub name2: String,
+    pub baseline_similarity: f64,
+    pub candidate_similarity: f64,
+    pub baseline_confidence: f64,
+    pub candidate_confidence: f64,
+    pub baseline_algorithm: FunctionAlgorithm,
+    pub candidate_algorithm: FunctionAlgorithm,
+}
+
+impl MatchDelta {
+    pub fn similarity_delta(&self) -> f64 {
+        self.candidate_similarity - self.baseline_similarity
+    }
+
+    pub fn confidence_delta(&self) -> f64 {
+        self.candidate_confidence - self.baseline_confidence
+    }
+
+    pub fn algorithm_changed(&self) -> bool {
+        self.baseline_algorithm != self.candidate_algorithm
+    }
+}
+
+impl std::fmt::Display for MatchDelta {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{:#x} {} -> {:#x} {}\tsimilarity: {:.3} ({:+.3}), confidence: {:.3} ({:+.3})",
+            self.address1,
+            self.name1,
+            self.address2,
+            self.name2,
+            self.candidate_similarity,
+            self.similarity_delta(),
+            self.candidate_confidence,
+            self.confidence_delta()
+        )?;
+        if self.algorithm_changed() {
+            write!(
+                f,
+                ", algorithm: {} -> {}",
+                self.baseline_algorithm, self.candidate_algorithm
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// What changed between a baseline and a candidate BinDiff result
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Comparison {
+    /// Matches whose primary function is only matched by the baseline
+    pub baseline_only: Vec<FunctionMatch>,
+    /// Matches whose primary function is only matched by the candidate
+    pub candidate_only: Vec<FunctionMatch>,
+    pub changed_partners: Vec<ChangedPartner>,
+    /// Common pairs whose similarity, confidence or algorithm differ
+    pub deltas: Vec<MatchDelta>,
+    /// Common pairs that are identical in both results
+    pub unchanged: usize,
+}
+
+impl Comparison {
+    pub fn is_empty(&self) -> bool {
+        self.baseline_only.is_empty()
+            && self.candidate_only.is_empty()
+            && self.changed_partners.is_empty()
+            && self.deltas.is_empty()
+    }
+}
+
+impl std::fmt::Display for Comparison {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "BASELINE ONLY ({}):", self.baseline_only.len())?;
+        for function_match in &self.baseline_only {
+            writeln!(f, "  {}", function_match)?;
+        }
+        writeln!(f, "CANDIDATE ONLY ({}):", self.candidate_only.len())?;
+        for function_match in &self.candidate_only {
+            writeln!(f, "  {}", function_match)?;
+        }
+        writeln!(f, "CHANGED PARTNER ({}):", self.changed_partners.len())?;
+        for changed in &self.changed_partners {
+            writeln!(f, "  {}", changed)?;
+        }
+        writeln!(f, "CHANGED SCORE ({}):", self.deltas.len())?;
+        for delta in &self.deltas {
+            writeln!(f, "  {}", delta)?;
+        }
+        writeln!(f, "UNCHANGED: {}", self.unchanged)
+    }
+}
+
+/// Compare the function matches of two results, keyed by primary address.
+/// Every list is sorted by primary address.
+pub fn compare_matches(baseline: &[FunctionMatch], candidate: &[FunctionMatch]) -> Comparison {
+    let by_address: HashMap<i64, &FunctionMatch> =
+        candidate.iter().map(|m| (m.address1, m)).collect();
+    let baseline_addresses: HashSet<i64> = baseline.iter().map(|m| m.address1).collect();
+
+    let mut comparison = Comparison::default();
+    for old in baseline {
+        let Some(&new) = by_address.get(&old.address1) else {
+            comparison.baseline_only.push(old.clone());
+            continue;
+        };
+        if old.address2 != new.address2 {
+            comparison.changed_partners.push(ChangedPartner {
+                address1: old.address1 as u64,
+                name1: old.name1.clone(),
+                baseline: old.clone(),
+                candidate: new.clone(),
+            });
+        } else if old.similarity != new.similarity
+            || old.confidence != new.confidence
+            || old.algorithm != new.algorithm
+        {
+            comparison.deltas.push(MatchDelta {
+                address1: old.address1 as u64,
+                name1: old.name1.clone(),
+                address2: old.address2 as u64,
+                name2: old.name2.clone(),
+                baseline_similarity: old.similarity,
+                candidate_similarity: new.similarity,
+                baseline_confidence: old.confidence,
+                candidate_confidence: new.confidence,
+                baseline_algorithm: old.algorithm.clone(),
+                candidate_algorithm: new.algorithm.clone(),
+            });
+        } else {
+            comparison.unchanged += 1;
+        }
+    }
+    comparison.candidate_only = candidate
+        .iter()
+        .filter(|m| !baseline_addresses.contains(&m.address1))
+        .cloned()
+        .collect();
+
+    comparison.baseline_only.sort_by_key(|m| m.address1);
+    comparison.candidate_only.sort_by_key(|m| m.address1);
+    comparison.changed_partners.sort_by_key(|c| c.address1);
+    comparison.deltas.sort_by_key(|d| d.address1);
+    comparison
+}
+
+/// Compare the function matches of two BinDiff databases of the same binaries
+pub fn compare(baseline: &BinDiff, candidate: &BinDiff) -> Result<Comparison> {
+    Ok(compare_matches(
+        &baseline.read_function_matches()?,
+        &candidate.read_function_matches()?,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::function_match;
+
+    #[test]
+    fn test_compare_matches() {
+        let scored = |address1, address2, similarity, algorithm| FunctionMatch {
+            similarity,
+            algorithm,
+            ..function_match(address1, address2)
+        };
+        let baseline = [
+            scored(0x1000, 0x1100, 1.0, FunctionAlgorithm::HashMatching),
+            scored(
+                0x2000,
+                0x2100,
+                0.8,
+                FunctionAlgorithm::PrimeSignatureMatching,
+            ),
+            scored(
+                0x3000,
+                0x3100,
+                0.5,
+                FunctionAlgorithm::CallReferenceMatching,
+            ),
+            scored(0x4000, 0x4100, 0.4, FunctionAlgorithm::AddressSequence),
+        ];
+        let candidate = [
+            scored(0x5000, 0x5100, 0.9, FunctionAlgorithm::HashMatching),
+            scored(
+                0x3000,
+                0x3200,
+                0.6,
+                FunctionAlgorithm::CallReferenceMatching,
+            ),
+            scored(
+                0x2000,
+                0x2100,
+                0.7,
+                FunctionAlgorithm::RelaxedMdIndexMatching,
+            ),
+            scored(0x1000, 0x1100, 1.0, FunctionAlgorithm::HashMatching),
+        ];
+
+        let comparison = compare_matches(&baseline, &candidate);
+        assert_eq!(comparison.unchanged, 1);
+        assert_eq!(comparison.baseline_only.len(), 1);
+        assert_eq!(comparison.baseline_only[0].address1, 0x4000);
+        assert_eq!(comparison.candidate_only.len(), 1);
+        assert_eq!(comparison.candidate_only[0].address1, 0x5000);
+        assert_eq!(comparison.changed_partners.len(), 1);
+        assert_eq!(comparison.changed_partners[0].candidate.address2, 0x3200);
+        assert_eq!(comparison.deltas.len(), 1);
+        assert!((comparison.deltas[0].similarity_delta() + 0.1).abs() < 1e-9);
+        assert!(comparison.deltas[0].algorithm_changed());
+        assert!(compare_matches(&baseline, &baseline).is_empty());
+    }
+}
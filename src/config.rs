@@ -0,0 +1,412 @@
+//! Matching pipeline configuration: which function and basic block matching
+//! steps run in which order, their confidences, and the thresholds a function
+//! match has to reach to be kept. Read from TOML, JSON or BinDiff's own XML
+//! configuration format.
+
+use crate::basic_block_matching::{
+    default_basic_block_confidence, default_basic_block_steps, BasicBlockMatcher,
+};
+use crate::matching::{default_function_confidence, default_function_steps, FunctionMatcher};
+use crate::{BasicBlockAlgorithm, FunctionAlgorithm};
+use anyhow::{bail, Context, Result};
+use quick_xml::events::Event;
+use quick_xml::{Reader, XmlVersion};
+use serde::de::value::{self, StrDeserializer};
+use serde::de::{Error as _, IntoDeserializer};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::path::Path;
+
+/// A function matching step
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FunctionStep {
+    /// Variant name (`NameHashMatching`) or BinDiff step name
+    /// (`function: name hash matching`)
+    #[serde(deserialize_with = "deserialize_function_algorithm")]
+    pub algorithm: FunctionAlgorithm,
+    pub confidence: f64,
+    #[serde(default = "enabled")]
+    pub enabled: bool,
+}
+
+/// A basic block matching step
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BasicBlockStep {
+    /// Variant name (`EdgesPrimeProduct`) or BinDiff step name
+    /// (`basicBlock: edges prime product`)
+    #[serde(deserialize_with = "deserialize_basic_block_algorithm")]
+    pub algorithm: BasicBlockAlgorithm,
+    pub confidence: f64,
+    #[serde(default = "enabled")]
+    pub enabled: bool,
+}
+
+fn enabled() -> bool {
+    true
+}
+
+fn deserialize_function_algorithm<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> std::result::Result<FunctionAlgorithm, D::Error> {
+    let name = String::deserialize(deserializer)?;
+    let variant: StrDeserializer<value::Error> = name.as_str().into_deserializer();
+    FunctionAlgorithm::deserialize(variant)
+        .ok()
+        .or_else(|| function_algorithm(&name))
+        .ok_or_else(|| D::Error::custom(format!("unknown function matching step: {}", name)))
+}
+
+fn deserialize_basic_block_algorithm<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> std::result::Result<BasicBlockAlgorithm, D::Error> {
+    let name = String::deserialize(deserializer)?;
+    let variant: StrDeserializer<value::Error> = name.as_str().into_deserializer();
+    BasicBlockAlgorithm::deserialize(variant)
+        .ok()
+        .or_else(|| basic_block_algorithm(&name))
+        .ok_or_else(|| D::Error::custom(format!("unknown basic block matching step: {}", name)))
+}
+
+/// Ordered matching steps and match thresholds
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MatchingConfig {
+    /// Function matches below this similarity are dropped
+    pub min_similarity: f64,
+    /// Function matches below this confidence are dropped
+    pub min_confidence: f64,
+    pub function_matching: Vec<FunctionStep>,
+    pub basic_block_matching: Vec<BasicBlockStep>,
+}
+
+impl Default for MatchingConfig {
+    /// BinDiff's default steps and confidences, keeping every match
+    fn default() -> Self {
+        Self {
+            min_similarity: 0.0,
+            min_confidence: 0.0,
+            function_matching: default_function_steps()
+                .into_iter()
+                .map(|algorithm| FunctionStep {
+                    confidence: default_function_confidence(&algorithm),
+                    algorithm,
+                    enabled: true,
+                })
+                .collect(),
+            basic_block_matching: default_basic_block_steps()
+                .into_iter()
+                .map(|algorithm| BasicBlockStep {
+                    confidence: default_basic_block_confidence(&algorithm),
+                    algorithm,
+                    enabled: true,
+                })
+                .collect(),
+        }
+    }
+}
+
+impl MatchingConfig {
+    /// Read a config file, picking the format from its extension (`.toml`,
+    /// `.json` or `.xml`)
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config {}", path.display()))?;
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("toml") => Self::from_toml(&text),
+            Some("json") => Self::from_json(&text),
+            Some("xml") => Self::from_bindiff_xml(&text),
+            _ => bail!("Unknown config format: {}", path.display()),
+        }
+    }
+
+    pub fn from_toml(text: &str) -> Result<Self> {
+        toml::from_str(text).context("Failed to parse TOML config")
+    }
+
+    pub fn from_json(text: &str) -> Result<Self> {
+        serde_json::from_str(text).context("Failed to parse JSON config")
+    }
+
+    pub fn to_toml(&self) -> Result<String> {
+        toml::to_string(self).context("Failed to serialize config")
+    }
+
+    /// Parse BinDiff's XML configuration, taking the steps listed under
+    /// `<function-matching>` and `<basic-block-matching>` in order. Steps
+    /// missing from the file are disabled.
+    pub fn from_bindiff_xml(text: &str) -> Result<Self> {
+        #[derive(PartialEq)]
+        enum Section {
+            None,
+            Function,
+            BasicBlock,
+        }
+
+        let mut config = Self {
+            function_matching: Vec::new(),
+            basic_block_matching: Vec::new(),
+            ..Default::default()
+        };
+        let mut reader = Reader::from_str(text);
+        let mut section = Section::None;
+        loop {
+            let event = reader
+                .read_event()
+                .context("Failed to parse BinDiff XML config")?;
+            match event {
+                Event::Start(ref element) | Event::Empty(ref element) => {
+                    match element.name().as_ref() {
+                        "function-matching" => section = Section::Function,
+                        "basic-block-matching" => section = Section::BasicBlock,
+                        "step" if section != Section::None => {
+                            let mut name = None;
+                            let mut confidence = None;
+                            for attribute in element.attributes() {
+                                let attribute =
+                                    attribute.context("Invalid attribute in BinDiff XML config")?;
+                                let value = attribute
+                                    .normalized_value(XmlVersion::default())
+                                    .context("Invalid attribute in BinDiff XML config")?
+                                    .into_owned();
+                                match attribute.key.as_ref() {
+                                    "algorithm" => name = Some(value),
+                                    "confidence" => {
+                                        confidence =
+                                            Some(value.parse::<f64>().with_context(|| {
+                                                format!("Invalid step confidence: {}", value)
+                                            })?)
+                                    }
+                                    _ => {}
+                                }
+                            }
+                            let name = name.context("Matching step without algorithm")?;
+                            if section == Section::Function {
+                                let algorithm = function_algorithm(&name).with_context(|| {
+                                    format!("Unknown function matching step: {}", name)
+                                })?;
+                                config.function_matching.push(FunctionStep {
+                                    confidence: confidence
+                                        .unwrap_or_else(|| default_function_confidence(&algorithm)),
+                                    algorithm,
+                                    enabled: true,
+                                });
+                            } else {
+                                let algorithm =
+                                    basic_block_algorithm(&name).with_context(|| {
+                                        format!("Unknown basic block matching step: {}", name)
+                                    })?;
+                                config.basic_block_matching.push(BasicBlockStep {
+                                    confidence: confidence.unwrap_or_else(|| {
+                                        default_basic_block_confidence(&algorithm)
+                                    }),
+                                    algorithm,
+                                    enabled: true,
+                                });
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+                Event::End(ref element)
+                    if matches!(
+                        element.name().as_ref(),
+                        "function-matching" | "basic-block-matching"
+                    ) =>
+                {
+                    section = Section::None
+                }
+                Event::Eof => break,
+                _ => {}
+            }
+        }
+        Ok(config)
+    }
+
+    /// Configured confidence of a function matching step, falling back to
+    /// BinDiff's default for steps not in the config
+    pub fn function_confidence(&self, algorithm: &FunctionAlgorithm) -> f64 {
+        self.function_matching
+            .iter()
+            .find(|step| &step.algorithm == algorithm)
+            .map(|step| step.confidence)
+            .unwrap_or_else(|| default_function_confidence(algorithm))
+    }
+
+    /// Configured confidence of a basic block matching step, falling back to
+    /// BinDiff's default for steps not in the config
+    pub fn basic_block_confidence(&self, algorithm: &BasicBlockAlgorithm) -> f64 {
+        self.basic_block_matching
+            .iter()
+            .find(|step| &step.algorithm == algorithm)
+            .map(|step| step.confidence)
+            .unwrap_or_else(|| default_basic_block_confidence(algorithm))
+    }
+
+    /// Function matcher running the enabled function steps in order
+    pub fn function_matcher(&self) -> FunctionMatcher {
+        let mut matcher = FunctionMatcher::with_steps(
+            self.function_matching
+                .iter()
+                .filter(|step| step.enabled)
+                .map(|step| step.algorithm.clone())
+                .collect(),
+        );
+        for step in &self.function_matching {
+            matcher = matcher.with_confidence(step.algorithm.clone(), step.confidence);
+        }
+        matcher
+    }
+
+    /// Basic block matcher running the enabled basic block steps in order
+    pub fn basic_block_matcher(&self) -> BasicBlockMatcher {
+        BasicBlockMatcher::with_steps(
+            self.basic_block_matching
+                .iter()
+                .filter(|step| step.enabled)
+                .map(|step| step.algorithm.clone())
+                .collect(),
+        )
+    }
+}
+
+/// Lowercase alphanumerics only, so `call sequence matching(exact)` and
+/// `Call Sequence Matching (exact)` compare equal
+fn normalize(name: &str) -> String {
+    name.chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+/// Resolve a BinDiff function step name such as `function: name hash matching`
+pub fn function_algorithm(name: &str) -> Option<FunctionAlgorithm> {
+    let name = normalize(name.trim().trim_start_matches("function:"));
+    // BinDiff's config names that differ from the names in result databases
+    let alias = match name.as_str() {
+        "primesignaturematching" => Some(FunctionAlgorithm::PrimeSignatureMatching),
+        "relaxedmdindexmatching" => Some(FunctionAlgorithm::RelaxedMdIndexMatching),
+        "callreferencematching" => Some(FunctionAlgorithm::CallReferenceMatching),
+        _ => None,
+    };
+    alias.or_else(|| {
+        default_function_steps()
+            .into_iter()
+            .chain([FunctionAlgorithm::Manual])
+            .find(|algorithm| normalize(&algorithm.to_string()) == name)
+    })
+}
+
+/// Resolve a BinDiff basic block step name such as `basicBlock: edges prime product`
+pub fn basic_block_algorithm(name: &str) -> Option<BasicBlockAlgorithm> {
+    let name = normalize(name.trim().trim_start_matches("basicBlock:"));
+    let alias = match name.as_str() {
+        "stringreferencesmatching" => Some(BasicBlockAlgorithm::StringReferencesMatching),
+        _ => None,
+    };
+    alias.or_else(|| {
+        default_basic_block_steps()
+            .into_iter()
+            .chain([BasicBlockAlgorithm::Manual])
+            .find(|algorithm| normalize(&algorithm.to_string()) == name)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bindiff_xml() -> Result<()> {
+        let config = MatchingConfig::from_bindiff_xml(
+            r#"<?xml version="1.0"?>
+<bindiff config-version="6">
+  <function-matching>
+    <step confidence="1.0" algorithm="function: hash matching"/>
+    <step confidence="0.5" algorithm="function: name hash matching"/>
+    <step confidence="0.9" algorithm="function: prime signature matching"/>
+    <step confidence="0.0" algorithm="function: call sequence matching(exact)"/>
+  </function-matching>
+  <basic-block-matching>
+    <step confidence="0.8" algorithm="basicBlock: string references matching"/>
+    <step confidence="0.0" algorithm="basicBlock: propagation (size==1)"/>
+  </basic-block-matching>
+</bindiff>"#,
+        )?;
+
+        let steps: Vec<FunctionAlgorithm> = config
+            .function_matching
+            .iter()
+            .map(|step| step.algorithm.clone())
+            .collect();
+        assert_eq!(
+            steps,
+            [
+                FunctionAlgorithm::HashMatching,
+                FunctionAlgorithm::NameHashMatching,
+                FunctionAlgorithm::PrimeSignatureMatching,
+                FunctionAlgorithm::CallSequenceMatchingExact,
+            ]
+        );
+        assert_eq!(
+            config.function_confidence(&FunctionAlgorithm::NameHashMatching),
+            0.5
+        );
+        assert_eq!(
+            config.basic_block_matcher().steps(),
+            [
+                BasicBlockAlgorithm::StringReferencesMatching,
+                BasicBlockAlgorithm::PropagationSizeOne,
+            ]
+        );
+
+        assert!(MatchingConfig::from_bindiff_xml(
+            r#"<bindiff><function-matching><step algorithm="function: bogus"/></function-matching></bindiff>"#
+        )
+        .is_err());
+
+        // TOML round trip, with a disabled step dropped from the matcher
+        let mut config = MatchingConfig {
+            min_similarity: 0.5,
+            ..config
+        };
+        config.function_matching[1].enabled = false;
+        let parsed = MatchingConfig::from_toml(&config.to_toml()?)?;
+        assert_eq!(parsed, config);
+        assert_eq!(parsed.function_matcher().steps().len(), 3);
+
+        // TOML and JSON steps may use BinDiff's names as well
+        let parsed = MatchingConfig::from_toml(
+            r#"
+            [[function_matching]]
+            algorithm = "function: name hash matching"
+            confidence = 0.5
+
+            [[function_matching]]
+            algorithm = "HashMatching"
+            confidence = 1.0
+            "#,
+        )?;
+        assert_eq!(
+            parsed.function_matching[0].algorithm,
+            FunctionAlgorithm::NameHashMatching
+        );
+        assert_eq!(
+            parsed.function_matching[1].algorithm,
+            FunctionAlgorithm::HashMatching
+        );
+        let parsed = MatchingConfig::from_json(
+            r#"{"basic_block_matching": [
+                {"algorithm": "basicBlock: edges prime product", "confidence": 1.0}
+            ]}"#,
+        )?;
+        assert_eq!(
+            parsed.basic_block_matching[0].algorithm,
+            BasicBlockAlgorithm::EdgesPrimeProduct
+        );
+        assert!(MatchingConfig::from_json(
+            r#"{"basic_block_matching": [{"algorithm": "bogus", "confidence": 1.0}]}"#
+        )
+        .is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,379 @@
+//! Match quality against ground truth: when both binaries were exported from
+//! symbolized builds, functions sharing a real name are the expected matches,
+//! and every function match can be scored as correct or false.
+
+use crate::{is_generated_name, BinExport, FunctionAlgorithm, FunctionMatch};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+/// Precision, recall and F1 of the matches found by one matching step
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AlgorithmScore {
+    pub algorithm: FunctionAlgorithm,
+    /// Matches of this step that are ground truth pairs
+    pub true_matches: usize,
+    /// Matches of this step between two different real names
+    pub false_matches: usize,
+    /// Matches of this step between equal names that are not unique in both
+    /// binaries, so not ground truth
+    pub ambiguous_matches: usize,
+    /// Matches of this step involving a generated name on either side
+    pub unknown_matches: usize,
+    pub precision: f64,
+    /// Share of the ground truth found by this step
+    pub recall: f64,
+    pub f1: f64,
+}
+
+impl std::fmt::Display for AlgorithmScore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}\ttrue: {}, false: {}, ambiguous: {}, unknown: {}, precision: {:.3}, recall: {:.3}, f1: {:.3}",
+            self.algorithm,
+            self.true_matches,
+            self.false_matches,
+            self.ambiguous_matches,
+            self.unknown_matches,
+            self.precision,
+            self.recall,
+            self.f1
+        )
+    }
+}
+
+/// A function match between two functions with different real names
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FalseMatch {
+    pub address1: u64,
+    pub name1: String,
+    pub address2: u64,
+    pub name2: String,
+    pub algorithm: FunctionAlgorithm,
+    pub similarity: f64,
+    pub confidence: f64,
+}
+
+impl std::fmt::Display for FalseMatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{:#x} {} -> {:#x} {}\talgorithm: {}, similarity: {:.3}, confidence: {:.3}",
+            self.address1,
+            self.name1,
+            self.address2,
+            self.name2,
+            self.algorithm,
+            self.similarity,
+            self.confidence
+        )
+    }
+}
+
+/// A ground truth pair that was not matched to each other
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MissedMatch {
+    pub name: String,
+    pub address1: u64,
+    pub address2: u64,
+}
+
+impl std::fmt::Display for MissedMatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{:#x} -> {:#x} {}",
+            self.address1, self.address2, self.name
+        )
+    }
+}
+
+/// Match quality of a diff measured against function names
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Evaluation {
+    /// Functions whose real name appears exactly once in each binary
+    pub ground_truth: usize,
+    pub true_matches: usize,
+    /// Matches between equal names that are not unique in both binaries
+    pub ambiguous_matches: usize,
+    pub precision: f64,
+    pub recall: f64,
+    pub f1: f64,
+    /// Scores per matching step, in order of the step's first match
+    pub algorithms: Vec<AlgorithmScore>,
+    pub false_matches: Vec<FalseMatch>,
+    pub missed_matches: Vec<MissedMatch>,
+}
+
+impl std::fmt::Display for Evaluation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "ground truth: {}, true: {}, false: {}, ambiguous: {}, missed: {}, precision: {:.3}, recall: {:.3}, f1: {:.3}",
+            self.ground_truth,
+            self.true_matches,
+            self.false_matches.len(),
+            self.ambiguous_matches,
+            self.missed_matches.len(),
+            self.precision,
+            self.recall,
+            self.f1
+        )?;
+        writeln!(f, "ALGORITHMS ({}):", self.algorithms.len())?;
+        for algorithm in &self.algorithms {
+            writeln!(f, "  {}", algorithm)?;
+        }
+        writeln!(f, "FALSE MATCHES ({}):", self.false_matches.len())?;
+        for false_match in &self.false_matches {
+            writeln!(f, "  {}", false_match)?;
+        }
+        writeln!(f, "MISSED MATCHES ({}):", self.missed_matches.len())?;
+        for missed_match in &self.missed_matches {
+            writeln!(f, "  {}", missed_match)?;
+        }
+        Ok(())
+    }
+}
+
+fn f1(precision: f64, recall: f64) -> f64 {
+    if precision + recall == 0.0 {
+        0.0
+    } else {
+        2.0 * precision * recall / (precision + recall)
+    }
+}
+
+fn share(part: usize, total: usize) -> f64 {
+    if total == 0 {
+        0.0
+    } else {
+        part as f64 / total as f64
+    }
+}
+
+/// Real function names that occur exactly once, with their address
+fn unique_names(binexport: &BinExport) -> HashMap<&str, u64> {
+    let mut names: HashMap<&str, Option<u64>> = HashMap::new();
+    for function in binexport.functions() {
+        let Some(name) = function.name().filter(|name| !is_generated_name(name)) else {
+            continue;
+        };
+        names
+            .entry(name)
+            .and_modify(|address| *address = None)
+            .or_insert(Some(function.address()));
+    }
+    names
+        .into_iter()
+        .filter_map(|(name, address)| Some((name, address?)))
+        .collect()
+}
+
+/// Score `matches` against the names of two symbolized BinExports. A match is
+/// true when it pairs the two functions of a name that is unique in both
+/// binaries and false when the functions carry different real names. Matches
+/// between equal names that are not unique are counted as ambiguous, those
+/// involving a generated name (`sub_...`) as unknown; neither affects
+/// precision or recall.
+pub fn evaluate(
+    primary: &BinExport,
+    secondary: &BinExport,
+    matches: &[FunctionMatch],
+) -> Evaluation {
+    let names1 = unique_names(primary);
+    let names2 = unique_names(secondary);
+    let mut ground_truth: Vec<(&str, u64, u64)> = names1
+        .iter()
+        .filter_map(|(&name, &address1)| Some((name, address1, *names2.get(name)?)))
+        .collect();
+    ground_truth.sort_by_key(|&(_, address1, _)| address1);
+    let truth: HashSet<(u64, u64)> = ground_truth
+        .iter()
+        .map(|&(_, address1, address2)| (address1, address2))
+        .collect();
+
+    // Prefer the BinExport names, BinDiff may have stored placeholders
+    let name = |binexport: &BinExport, address: i64, fallback: &str| {
+        binexport
+            .function_name(address as u64)
+            .unwrap_or(fallback)
+            .to_string()
+    };
+
+    let mut algorithms: Vec<AlgorithmScore> = Vec::new();
+    let mut false_matches = Vec::new();
+    let mut found = HashSet::new();
+    for function_match in matches {
+        let name1 = name(primary, function_match.address1, &function_match.name1);
+        let name2 = name(secondary, function_match.address2, &function_match.name2);
+        let index = match algorithms
+            .iter()
+            .position(|score| score.algorithm == function_match.algorithm)
+        {
+            Some(index) => index,
+            None => {
+                algorithms.push(AlgorithmScore {
+                    algorithm: function_match.algorithm.clone(),
+                    true_matches: 0,
+                    false_matches: 0,
+                    ambiguous_matches: 0,
+                    unknown_matches: 0,
+                    precision: 0.0,
+                    recall: 0.0,
+                    f1: 0.0,
+                });
+                algorithms.len() - 1
+            }
+        };
+        let score = &mut algorithms[index];
+        let pair = (
+            function_match.address1 as u64,
+            function_match.address2 as u64,
+        );
+
+        if is_generated_name(&name1) || is_generated_name(&name2) {
+            score.unknown_matches += 1;
+        } else if name1 == name2 {
+            if truth.contains(&pair) {
+                score.true_matches += 1;
+                found.insert(pair);
+            } else {
+                score.ambiguous_matches += 1;
+            }
+        } else {
+            score.false_matches += 1;
+            false_matches.push(FalseMatch {
+                address1: function_match.address1 as u64,
+                name1,
+                address2: function_match.address2 as u64,
+                name2,
+                algorithm: function_match.algorithm.clone(),
+                similarity: function_match.similarity,
+                confidence: function_match.confidence,
+            });
+        }
+    }
+
+    for score in &mut algorithms {
+        score.precision = share(score.true_matches, score.true_matches + score.false_matches);
+        score.recall = share(score.true_matches, ground_truth.len());
+        score.f1 = f1(score.precision, score.recall);
+    }
+
+    let missed_matches: Vec<MissedMatch> = ground_truth
+        .iter()
+        .filter(|&&(_, address1, address2)| !found.contains(&(address1, address2)))
+        .map(|&(name, address1, address2)| MissedMatch {
+            name: name.to_string(),
+            address1,
+            address2,
+        })
+        .collect();
+
+    let true_matches = algorithms.iter().map(|score| score.true_matches).sum();
+    let ambiguous_matches = algorithms.iter().map(|score| score.ambiguous_matches).sum();
+    let precision = share(true_matches, true_matches + false_matches.len());
+    let recall = share(true_matches, ground_truth.len());
+    Evaluation {
+        ground_truth: ground_truth.len(),
+        true_matches,
+        ambiguous_matches,
+        precision,
+        recall,
+        f1: f1(precision, recall),
+        algorithms,
+        false_matches,
+        missed_matches,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::{binexport, function_match, FunctionSpec};
+
+    #[test]
+    fn test_evaluate() {
+        let primary = binexport(&[
+            FunctionSpec::new(0x1000, "alpha", vec![vec!["ret"]]),
+            FunctionSpec::new(0x2000, "beta", vec![vec!["nop", "ret"]]),
+            FunctionSpec::new(0x3000, "gamma", vec![vec!["b"]]),
+            FunctionSpec::new(0x4000, "sub_4000", vec![vec!["brk"]]),
+        ]);
+        let secondary = binexport(&[
+            FunctionSpec::new(0x1100, "alpha", vec![vec!["ret"]]),
+            FunctionSpec::new(0x2100, "beta", vec![vec!["nop", "ret"]]),
+            FunctionSpec::new(0x3100, "gamma", vec![vec!["b"]]),
+            FunctionSpec::new(0x4100, "delta", vec![vec!["brk"]]),
+        ]);
+        let hash_match = |address1, address2| FunctionMatch {
+            algorithm: FunctionAlgorithm::HashMatching,
+            ..function_match(address1, address2)
+        };
+        let matches = [
+            function_match(0x1000, 0x1100),
+            hash_match(0x2000, 0x3100),
+            hash_match(0x4000, 0x4100),
+        ];
+
+        let evaluation = evaluate(&primary, &secondary, &matches);
+        assert_eq!(evaluation.ground_truth, 3);
+        assert_eq!(evaluation.true_matches, 1);
+        assert_eq!(evaluation.precision, 0.5);
+        assert_eq!(evaluation.recall, 1.0 / 3.0);
+
+        assert_eq!(evaluation.false_matches.len(), 1);
+        assert_eq!(evaluation.false_matches[0].name1, "beta");
+        assert_eq!(evaluation.false_matches[0].name2, "gamma");
+        let missed: Vec<&str> = evaluation
+            .missed_matches
+            .iter()
+            .map(|m| m.name.as_str())
+            .collect();
+        assert_eq!(missed, ["beta", "gamma"]);
+
+        assert_eq!(evaluation.algorithms.len(), 2);
+        let hash = &evaluation.algorithms[1];
+        assert_eq!(hash.algorithm, FunctionAlgorithm::HashMatching);
+        assert_eq!(
+            (hash.true_matches, hash.false_matches, hash.unknown_matches),
+            (0, 1, 1)
+        );
+        assert_eq!(hash.f1, 0.0);
+        assert_eq!(evaluation.algorithms[0].precision, 1.0);
+    }
+
+    #[test]
+    fn test_evaluate_duplicate_names() {
+        let primary = binexport(&[
+            FunctionSpec::new(0x1000, "alpha", vec![vec!["ret"]]),
+            FunctionSpec::new(0x2000, "dup", vec![vec!["nop", "ret"]]),
+            FunctionSpec::new(0x3000, "dup", vec![vec!["b"]]),
+        ]);
+        let secondary = binexport(&[
+            FunctionSpec::new(0x1100, "alpha", vec![vec!["ret"]]),
+            FunctionSpec::new(0x2100, "dup", vec![vec!["nop", "ret"]]),
+            FunctionSpec::new(0x3100, "dup", vec![vec!["b"]]),
+        ]);
+        let matches = [
+            function_match(0x1000, 0x1100),
+            function_match(0x2000, 0x2100),
+            function_match(0x3000, 0x3100),
+        ];
+
+        let evaluation = evaluate(&primary, &secondary, &matches);
+        assert_eq!(evaluation.ground_truth, 1);
+        assert_eq!(
+            (evaluation.true_matches, evaluation.ambiguous_matches),
+            (1, 2)
+        );
+        assert!(evaluation.missed_matches.is_empty());
+        assert_eq!(evaluation.precision, 1.0);
+        assert!(evaluation.recall <= 1.0);
+        assert!(evaluation
+            .algorithms
+            .iter()
+            .all(|score| score.recall <= 1.0));
+        assert_eq!(evaluation.algorithms[0].recall, 1.0);
+    }
+}
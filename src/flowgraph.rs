@@ -0,0 +1,364 @@
+//! Read-only views over the functions, basic blocks and instructions stored in a
+//! BinExport file.
+
+use crate::binexport::bin_export2::{self, call_graph, expression, flow_graph};
+use crate::binexport::BinExport2;
+use crate::BinExport;
+
+/// A function (flow graph) of a BinExport file
+#[derive(Debug, Clone, Copy)]
+pub struct Function<'a> {
+    binexport: &'a BinExport,
+    flow_graph: &'a bin_export2::FlowGraph,
+    basic_blocks: &'a [(u64, usize)],
+}
+
+impl<'a> Function<'a> {
+    pub(crate) fn new(binexport: &'a BinExport, index: usize) -> Self {
+        Self {
+            binexport,
+            flow_graph: &binexport.binexport.flow_graph[index],
+            basic_blocks: binexport.sorted_basic_blocks(index),
+        }
+    }
+
+    /// The BinExport file this function belongs to
+    pub fn binexport(&self) -> &'a BinExport {
+        self.binexport
+    }
+
+    /// Entry point address of the function
+    pub fn address(&self) -> u64 {
+        self.entry_basic_block()
+            .map(|basic_block| basic_block.address())
+            .unwrap_or_default()
+    }
+
+    /// Function name from the call graph, if the function has a vertex
+    pub fn name(&self) -> Option<&'a str> {
+        self.binexport.function_name(self.address())
+    }
+
+    /// Call graph vertex type (normal, library, imported, thunk)
+    pub fn vertex_type(&self) -> call_graph::vertex::Type {
+        self.binexport
+            .vertex(self.address())
+            .map(|vertex| vertex.r#type())
+            .unwrap_or_default()
+    }
+
+    pub fn entry_basic_block(&self) -> Option<BasicBlock<'a>> {
+        let index = self.flow_graph.entry_basic_block_index?;
+        Some(BasicBlock::new(self.binexport, index as usize))
+    }
+
+    /// Basic blocks of the function, sorted by address
+    pub fn basic_blocks(&self) -> impl Iterator<Item = BasicBlock<'a>> + 'a {
+        let binexport = self.binexport;
+        self.basic_blocks
+            .iter()
+            .map(move |&(_, index)| BasicBlock::new(binexport, index))
+    }
+
+    /// Look up the basic block of this function that starts at `address`
+    pub fn basic_block(&self, address: u64) -> Option<BasicBlock<'a>> {
+        let position = self
+            .basic_blocks
+            .binary_search_by_key(&address, |&(address, _)| address)
+            .ok()?;
+        Some(BasicBlock::new(
+            self.binexport,
+            self.basic_blocks[position].1,
+        ))
+    }
+
+    pub fn edges(&self) -> impl Iterator<Item = Edge<'a>> + 'a {
+        let binexport = self.binexport;
+        self.flow_graph.edge.iter().map(move |edge| Edge {
+            source: BasicBlock::new(binexport, edge.source_basic_block_index() as usize),
+            target: BasicBlock::new(binexport, edge.target_basic_block_index() as usize),
+            kind: edge.r#type(),
+            is_back_edge: edge.is_back_edge(),
+        })
+    }
+
+    pub fn basic_block_count(&self) -> usize {
+        self.flow_graph.basic_block_index.len()
+    }
+
+    pub fn edge_count(&self) -> usize {
+        self.flow_graph.edge.len()
+    }
+
+    pub fn instruction_count(&self) -> usize {
+        self.flow_graph
+            .basic_block_index
+            .iter()
+            .map(|&index| BasicBlock::new(self.binexport, index as usize).instruction_count())
+            .sum()
+    }
+
+    /// The underlying protobuf flow graph
+    pub fn flow_graph(&self) -> &'a bin_export2::FlowGraph {
+        self.flow_graph
+    }
+}
+
+/// A flow graph edge between two basic blocks
+#[derive(Debug, Clone, Copy)]
+pub struct Edge<'a> {
+    pub source: BasicBlock<'a>,
+    pub target: BasicBlock<'a>,
+    pub kind: flow_graph::edge::Type,
+    pub is_back_edge: bool,
+}
+
+/// A basic block of a BinExport file
+#[derive(Debug, Clone, Copy)]
+pub struct BasicBlock<'a> {
+    binexport: &'a BinExport,
+    index: usize,
+}
+
+impl<'a> BasicBlock<'a> {
+    pub(crate) fn new(binexport: &'a BinExport, index: usize) -> Self {
+        Self { binexport, index }
+    }
+
+    /// Index into the BinExport basic block table
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    pub fn address(&self) -> u64 {
+        self.binexport
+            .basic_block_address(self.index)
+            .unwrap_or_default()
+    }
+
+    pub fn instructions(&self) -> impl Iterator<Item = Instruction<'a>> + 'a {
+        let binexport = self.binexport;
+        self.binexport
+            .binexport
+            .basic_block
+            .get(self.index)
+            .into_iter()
+            .flat_map(|basic_block| basic_block.instruction_index.iter())
+            .flat_map(|range| {
+                // A range without an end index holds a single instruction
+                let begin = range.begin_index();
+                begin..range.end_index.unwrap_or(begin + 1)
+            })
+            .map(move |index| Instruction::new(binexport, index as usize))
+    }
+
+    pub fn instruction_count(&self) -> usize {
+        self.instructions().count()
+    }
+}
+
+impl PartialEq for BasicBlock<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self.binexport, other.binexport) && self.index == other.index
+    }
+}
+
+impl Eq for BasicBlock<'_> {}
+
+/// An instruction of a BinExport file
+#[derive(Debug, Clone, Copy)]
+pub struct Instruction<'a> {
+    binexport: &'a BinExport,
+    index: usize,
+}
+
+impl<'a> Instruction<'a> {
+    pub(crate) fn new(binexport: &'a BinExport, index: usize) -> Self {
+        Self { binexport, index }
+    }
+
+    /// Index into the BinExport instruction table
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    pub fn address(&self) -> u64 {
+        self.binexport
+            .instruction_address(self.index)
+            .unwrap_or_default()
+    }
+
+    fn raw(&self) -> Option<&'a bin_export2::Instruction> {
+        self.binexport.binexport.instruction.get(self.index)
+    }
+
+    pub fn mnemonic(&self) -> &'a str {
+        self.raw()
+            .and_then(|instruction| {
+                self.binexport
+                    .binexport
+                    .mnemonic
+                    .get(instruction.mnemonic_index() as usize)
+            })
+            .map(|mnemonic| mnemonic.name())
+            .unwrap_or_default()
+    }
+
+    pub fn raw_bytes(&self) -> &'a [u8] {
+        self.raw()
+            .map(|instruction| instruction.raw_bytes())
+            .unwrap_or_default()
+    }
+
+    pub fn call_targets(&self) -> &'a [u64] {
+        self.raw()
+            .map(|instruction| instruction.call_target.as_slice())
+            .unwrap_or_default()
+    }
+
+    /// Strings this instruction references
+    pub fn string_references(&self) -> impl Iterator<Item = &'a str> + 'a {
+        self.binexport.string_references(self.index)
+    }
+
+    /// Render each operand's expression tree as text
+    pub fn operands(&self) -> Vec<String> {
+        let Some(instruction) = self.raw() else {
+            return Vec::new();
+        };
+        instruction
+            .operand_index
+            .iter()
+            .filter_map(|&index| self.binexport.binexport.operand.get(index as usize))
+            .map(|operand| render_operand(&self.binexport.binexport, operand))
+            .collect()
+    }
+}
+
+impl std::fmt::Display for Instruction<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let operands = self.operands();
+        if operands.is_empty() {
+            write!(f, "{}", self.mnemonic())
+        } else {
+            write!(f, "{} {}", self.mnemonic(), operands.join(", "))
+        }
+    }
+}
+
+/// Render an operand's expression tree, e.g. `b4 [ebx + 12]` becomes `[ebx+0xc]`
+fn render_operand(binexport: &BinExport2, operand: &bin_export2::Operand) -> String {
+    let indices = &operand.expression_index;
+    let mut text = String::new();
+    for &root in indices.iter().filter(|&&index| {
+        binexport
+            .expression
+            .get(index as usize)
+            .and_then(|expression| expression.parent_index)
+            .is_none_or(|parent| !indices.contains(&parent))
+    }) {
+        render_expression(binexport, indices, root, 0, &mut text);
+    }
+    text
+}
+
+/// Render the expression at `index` and its children; `depth` stops malformed
+/// files whose `parent_index` links form a cycle
+fn render_expression(
+    binexport: &BinExport2,
+    indices: &[i32],
+    index: i32,
+    depth: usize,
+    text: &mut String,
+) {
+    // A tree of the operand's expressions is never deeper than their count
+    if depth > indices.len() {
+        return;
+    }
+    let Some(expression) = binexport.expression.get(index as usize) else {
+        return;
+    };
+    // Children render in the order they are referenced by the operand
+    let children: Vec<i32> = indices
+        .iter()
+        .copied()
+        .filter(|&child| {
+            binexport
+                .expression
+                .get(child as usize)
+                .is_some_and(|e| e.parent_index == Some(index))
+        })
+        .collect();
+
+    match expression.r#type() {
+        expression::Type::SizePrefix => {
+            for child in children {
+                render_expression(binexport, indices, child, depth + 1, text);
+            }
+        }
+        expression::Type::Dereference => {
+            text.push('[');
+            for child in children {
+                render_expression(binexport, indices, child, depth + 1, text);
+            }
+            text.push(']');
+        }
+        expression::Type::Operator if !children.is_empty() => {
+            let separator = match expression.symbol() {
+                "," => ", ",
+                symbol => symbol,
+            };
+            if children.len() == 1 {
+                text.push_str(separator);
+            }
+            for (i, child) in children.into_iter().enumerate() {
+                if i > 0 {
+                    text.push_str(separator);
+                }
+                render_expression(binexport, indices, child, depth + 1, text);
+            }
+        }
+        expression::Type::ImmediateInt if expression.symbol().is_empty() => {
+            text.push_str(&format!("{:#x}", expression.immediate()));
+        }
+        _ => {
+            text.push_str(expression.symbol());
+            for child in children {
+                render_expression(binexport, indices, child, depth + 1, text);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::{binexport, FunctionSpec};
+
+    #[test]
+    fn test_malformed_flow_graphs() {
+        let mut proto = binexport(&[
+            FunctionSpec::new(0x1000, "f", vec![vec!["mov x0, x1"], vec!["ret"]]),
+            FunctionSpec::new(0x2000, "g", vec![vec!["ret"]]),
+        ])
+        .binexport;
+        // Blocks listed out of address order, a flow graph without an entry
+        // block and an operand expression that is its own parent
+        proto.flow_graph[0].basic_block_index.reverse();
+        proto.flow_graph[1].entry_basic_block_index = None;
+        proto.expression[0].parent_index = Some(0);
+        let binexport = BinExport::new(proto);
+
+        let function = binexport.function(0x1000).unwrap();
+        let addresses: Vec<u64> = function.basic_blocks().map(|b| b.address()).collect();
+        assert_eq!(addresses, [0x1000, 0x1004]);
+        assert_eq!(function.basic_block(0x1004).unwrap().address(), 0x1004);
+        assert!(function.basic_block(0x1002).is_none());
+        assert!(binexport.function(0x2000).is_none());
+        assert_eq!(binexport.functions().count(), 1);
+        assert!(binexport.function(0x1004).is_none());
+        let instruction = function.entry_basic_block().unwrap().instructions().next();
+        assert_eq!(instruction.unwrap().mnemonic(), "mov");
+        assert_eq!(instruction.unwrap().operands(), ["", "x1"]);
+    }
+}
@@ -0,0 +1,528 @@
+//! Self-contained HTML report of a BinDiff result: one static file with the
+//! metadata, statistics, a sortable and filterable function match table and,
+//! when the BinExport files are given, unmatched functions and per-function
+//! pages with side-by-side disassembly and flow graphs.
+
+use crate::instruction_diff::{DiffInstruction, InstructionDiff, LineKind};
+use crate::render::{escape_xml, FlowGraphDiff};
+use crate::stats::{self, Bucket, Stats};
+use crate::unmatched::{self, UnmatchedFunction};
+use crate::{BinDiff, BinExport, File, FunctionMatch, Metadata};
+use anyhow::Result;
+use std::collections::HashSet;
+use std::fmt::Write;
+
+const STYLE: &str = "
+body { font-family: sans-serif; margin: 2em; color: #222; }
+h1, h2, h3 { font-weight: 600; }
+table { border-collapse: collapse; margin: 1em 0; }
+th, td { padding: 2px 8px; text-align: left; border-bottom: 1px solid #ddd; }
+th { background: #f3f3f3; }
+.sortable th { cursor: pointer; user-select: none; }
+td.number, th.number { text-align: right; }
+.mono, .disasm td { font-family: monospace; white-space: pre; }
+.bar { display: inline-block; height: 0.8em; background: #6c9bd2; }
+.identical { color: #777; }
+section.page { display: none; }
+section.page:target { display: block; }
+body:has(section.page:target) main { display: none; }
+.disasm tr.changed td { background: #ffff80; }
+.disasm tr.removed td { background: #ff9999; }
+.disasm tr.added td { background: #b3e6b3; }
+.disasm tr.hunk td { border-top: 2px solid #999; }
+.graph { overflow-x: auto; }
+";
+
+const SCRIPT: &str = "
+for (const table of document.querySelectorAll('table.sortable')) {
+  table.querySelectorAll('th').forEach((th, column) => th.addEventListener('click', () => {
+    const descending = th.dataset.order !== 'desc';
+    th.dataset.order = descending ? 'desc' : 'asc';
+    const numeric = th.classList.contains('number');
+    const key = row => row.cells[column].dataset.value ?? row.cells[column].textContent;
+    const rows = [...table.tBodies[0].rows].sort((a, b) => numeric
+      ? parseFloat(key(a)) - parseFloat(key(b))
+      : key(a).localeCompare(key(b)));
+    if (descending) rows.reverse();
+    table.tBodies[0].append(...rows);
+  }));
+}
+const filter = document.getElementById('filter');
+const changed = document.getElementById('changed');
+function apply() {
+  const text = filter.value.toLowerCase();
+  for (const row of document.querySelectorAll('#matches tbody tr')) {
+    row.hidden = !row.dataset.names.includes(text)
+      || (changed.checked && row.dataset.changed !== '1');
+  }
+}
+filter.addEventListener('input', apply);
+changed.addEventListener('change', apply);
+";
+
+/// Options of an HTML report
+#[derive(Debug, Clone)]
+pub struct HtmlOptions {
+    /// Page title, "<primary> vs <secondary>" by default
+    pub title: Option<String>,
+    /// Most per-function pages to include, for the changed matches with the
+    /// lowest similarity. Pages need the BinExport files.
+    pub max_functions: usize,
+}
+
+impl Default for HtmlOptions {
+    fn default() -> Self {
+        Self {
+            title: None,
+            max_functions: 100,
+        }
+    }
+}
+
+/// Generate the HTML report of a BinDiff database, with unmatched functions
+/// and per-function pages if the `(primary, secondary)` BinExport files are
+/// given
+pub fn html_report(
+    bindiff: &BinDiff,
+    binexports: Option<(&BinExport, &BinExport)>,
+    options: &HtmlOptions,
+) -> Result<String> {
+    let metadata = bindiff.read_metadata()?;
+    let primary = bindiff.read_file_by_id(metadata.file1)?;
+    let secondary = bindiff.read_file_by_id(metadata.file2)?;
+    let stats = stats::stats(bindiff)?;
+    let mut matches = bindiff.read_function_matches()?;
+    matches.sort_by_key(|m| m.address1);
+
+    let pages: Vec<&FunctionMatch> = match binexports {
+        Some(_) => {
+            let mut changed: Vec<&FunctionMatch> =
+                matches.iter().filter(|m| m.similarity < 1.0).collect();
+            changed.sort_by(|a, b| a.similarity.total_cmp(&b.similarity));
+            changed.truncate(options.max_functions);
+            changed
+        }
+        None => Vec::new(),
+    };
+    let paged: HashSet<i64> = pages.iter().map(|m| m.id).collect();
+
+    let title = options
+        .title
+        .clone()
+        .unwrap_or_else(|| format!("{} vs {}", primary.filename, secondary.filename));
+    let mut out = String::new();
+    writeln!(
+        out,
+        "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n\
+         <title>{}</title>\n<style>{}</style>\n</head>\n<body>\n<main>",
+        escape_xml(&title),
+        STYLE
+    )
+    .unwrap();
+    writeln!(out, "<h1>{}</h1>", escape_xml(&title)).unwrap();
+    write_overview(&mut out, &metadata, &primary, &secondary);
+    write_stats(&mut out, &stats);
+    write_matches(&mut out, &matches, &paged);
+    if let Some((primary, secondary)) = binexports {
+        let unmatched = unmatched::unmatched_functions(primary, secondary, &matches);
+        write_unmatched(&mut out, "Removed (primary only)", &unmatched.primary);
+        write_unmatched(&mut out, "Added (secondary only)", &unmatched.secondary);
+    }
+    writeln!(out, "</main>").unwrap();
+
+    if let Some((primary, secondary)) = binexports {
+        for function_match in pages {
+            write_page(&mut out, bindiff, function_match, primary, secondary)?;
+        }
+    }
+    writeln!(out, "<script>{}</script>\n</body>\n</html>", SCRIPT).unwrap();
+    Ok(out)
+}
+
+fn write_overview(out: &mut String, metadata: &Metadata, primary: &File, secondary: &File) {
+    writeln!(out, "<table>").unwrap();
+    let rows = [
+        ("BinDiff", escape_xml(&metadata.version)),
+        ("Primary", file_summary(primary)),
+        ("Secondary", file_summary(secondary)),
+        (
+            "Created",
+            metadata.created.format("%Y-%m-%d %H:%M:%S").to_string(),
+        ),
+        ("Similarity", format!("{:.3}", metadata.similarity)),
+        ("Confidence", format!("{:.3}", metadata.confidence)),
+    ];
+    for (name, value) in rows {
+        writeln!(out, "<tr><th>{}</th><td>{}</td></tr>", name, value).unwrap();
+    }
+    if !metadata.description.is_empty() {
+        writeln!(
+            out,
+            "<tr><th>Description</th><td>{}</td></tr>",
+            escape_xml(&metadata.description)
+        )
+        .unwrap();
+    }
+    writeln!(out, "</table>").unwrap();
+}
+
+fn file_summary(file: &File) -> String {
+    format!(
+        "{} <span class=\"mono\">{}</span>",
+        escape_xml(&file.filename),
+        escape_xml(&file.hash)
+    )
+}
+
+fn write_stats(out: &mut String, stats: &Stats) {
+    writeln!(out, "<h2>Statistics</h2>").unwrap();
+    writeln!(
+        out,
+        "<p>{} function, {} basic block and {} instruction matches; \
+         {} identical, {} renamed functions.</p>",
+        stats.function_matches,
+        stats.basic_block_matches,
+        stats.instruction_matches,
+        stats.identical,
+        stats.renamed
+    )
+    .unwrap();
+
+    writeln!(
+        out,
+        "<table>\n<tr><th>Coverage</th><th>Functions</th><th>Basic blocks</th>\
+         <th>Instructions</th></tr>"
+    )
+    .unwrap();
+    for (side, coverage) in [("Primary", &stats.primary), ("Secondary", &stats.secondary)] {
+        writeln!(
+            out,
+            "<tr><td>{} ({})</td><td class=\"number\">{}</td><td class=\"number\">{}</td>\
+             <td class=\"number\">{}</td></tr>",
+            side,
+            escape_xml(&coverage.filename),
+            coverage.functions,
+            coverage.basic_blocks,
+            coverage.instructions
+        )
+        .unwrap();
+    }
+    writeln!(out, "</table>").unwrap();
+
+    write_histogram(out, "Similarity", &stats.similarity);
+    write_histogram(out, "Confidence", &stats.confidence);
+
+    writeln!(
+        out,
+        "<table>\n<tr><th>Function matching step</th><th class=\"number\">Matches</th></tr>"
+    )
+    .unwrap();
+    for algorithm in &stats.function_algorithms {
+        writeln!(
+            out,
+            "<tr><td>{}</td><td class=\"number\">{}</td></tr>",
+            escape_xml(&algorithm.algorithm.to_string()),
+            algorithm.count
+        )
+        .unwrap();
+    }
+    writeln!(out, "</table>").unwrap();
+    writeln!(
+        out,
+        "<table>\n<tr><th>Basic block matching step</th><th class=\"number\">Matches</th></tr>"
+    )
+    .unwrap();
+    for algorithm in &stats.basic_block_algorithms {
+        writeln!(
+            out,
+            "<tr><td>{}</td><td class=\"number\">{}</td></tr>",
+            escape_xml(&algorithm.algorithm.to_string()),
+            algorithm.count
+        )
+        .unwrap();
+    }
+    writeln!(out, "</table>").unwrap();
+}
+
+fn write_histogram(out: &mut String, name: &str, buckets: &[Bucket]) {
+    let largest = buckets.iter().map(|b| b.count).max().unwrap_or(0).max(1);
+    writeln!(
+        out,
+        "<table>\n<tr><th>{}</th><th class=\"number\">Matches</th><th></th></tr>",
+        name
+    )
+    .unwrap();
+    for bucket in buckets {
+        writeln!(
+            out,
+            "<tr><td>{:.1} - {:.1}</td><td class=\"number\">{}</td>\
+             <td><span class=\"bar\" style=\"width: {}px\"></span></td></tr>",
+            bucket.min,
+            bucket.max,
+            bucket.count,
+            bucket.count * 300 / largest
+        )
+        .unwrap();
+    }
+    writeln!(out, "</table>").unwrap();
+}
+
+fn write_matches(out: &mut String, matches: &[FunctionMatch], paged: &HashSet<i64>) {
+    writeln!(out, "<h2>Function matches ({})</h2>", matches.len()).unwrap();
+    writeln!(
+        out,
+        "<p><input id=\"filter\" type=\"search\" placeholder=\"Filter by name\"> \
+         <label><input id=\"changed\" type=\"checkbox\"> Changed only</label></p>"
+    )
+    .unwrap();
+    writeln!(
+        out,
+        "<table id=\"matches\" class=\"sortable\">\n<thead><tr>\
+         <th>Address 1</th><th>Name 1</th><th>Address 2</th><th>Name 2</th>\
+         <th class=\"number\">Similarity</th><th class=\"number\">Confidence</th>\
+         <th>Algorithm</th><th class=\"number\">Basic blocks</th>\
+         <th class=\"number\">Edges</th><th class=\"number\">Instructions</th>\
+         </tr></thead>\n<tbody>"
+    )
+    .unwrap();
+    for m in matches {
+        let name1 = if paged.contains(&m.id) {
+            format!("<a href=\"#f{}\">{}</a>", m.id, escape_xml(&m.name1))
+        } else {
+            escape_xml(&m.name1)
+        };
+        writeln!(
+            out,
+            "<tr data-names=\"{}\" data-changed=\"{}\"{}>\
+             <td class=\"mono\" data-value=\"{:016x}\">{:#x}</td><td>{}</td>\
+             <td class=\"mono\" data-value=\"{:016x}\">{:#x}</td><td>{}</td>\
+             <td class=\"number\">{:.3}</td><td class=\"number\">{:.3}</td><td>{}</td>\
+             <td class=\"number\">{}</td><td class=\"number\">{}</td><td class=\"number\">{}</td></tr>",
+            escape_xml(&format!("{} {}", m.name1, m.name2).to_lowercase()),
+            u8::from(m.similarity < 1.0),
+            if m.similarity < 1.0 {
+                ""
+            } else {
+                " class=\"identical\""
+            },
+            m.address1 as u64,
+            m.address1 as u64,
+            name1,
+            m.address2 as u64,
+            m.address2 as u64,
+            escape_xml(&m.name2),
+            m.similarity,
+            m.confidence,
+            escape_xml(&m.algorithm.to_string()),
+            m.basic_blocks,
+            m.edges,
+            m.instructions
+        )
+        .unwrap();
+    }
+    writeln!(out, "</tbody>\n</table>").unwrap();
+}
+
+fn write_unmatched(out: &mut String, title: &str, functions: &[UnmatchedFunction]) {
+    writeln!(out, "<h2>{} ({})</h2>", title, functions.len()).unwrap();
+    if functions.is_empty() {
+        return;
+    }
+    writeln!(
+        out,
+        "<table class=\"sortable\">\n<thead><tr><th>Address</th><th>Name</th><th>Type</th>\
+         <th class=\"number\">Basic blocks</th><th class=\"number\">Edges</th>\
+         <th class=\"number\">Instructions</th></tr></thead>\n<tbody>"
+    )
+    .unwrap();
+    for function in functions {
+        writeln!(
+            out,
+            "<tr><td class=\"mono\" data-value=\"{:016x}\">{:#x}</td><td>{}</td><td>{}</td>\
+             <td class=\"number\">{}</td><td class=\"number\">{}</td><td class=\"number\">{}</td></tr>",
+            function.address,
+            function.address,
+            escape_xml(&function.name),
+            function.function_type,
+            function.basic_blocks,
+            function.edges,
+            function.instructions
+        )
+        .unwrap();
+    }
+    writeln!(out, "</tbody>\n</table>").unwrap();
+}
+
+/// A hidden section shown when its anchor is the URL fragment
+fn write_page(
+    out: &mut String,
+    bindiff: &BinDiff,
+    function_match: &FunctionMatch,
+    primary: &BinExport,
+    secondary: &BinExport,
+) -> Result<()> {
+    writeln!(
+        out,
+        "<section class=\"page\" id=\"f{}\">\n<p><a href=\"#\">&larr; Overview</a></p>\n\
+         <h2>{} &rarr; {}</h2>\n<p>{:#x} &rarr; {:#x}, similarity {:.3}, confidence {:.3}, \
+         found by {}</p>",
+        function_match.id,
+        escape_xml(&function_match.name1),
+        escape_xml(&function_match.name2),
+        function_match.address1 as u64,
+        function_match.address2 as u64,
+        function_match.similarity,
+        function_match.confidence,
+        escape_xml(&function_match.algorithm.to_string())
+    )
+    .unwrap();
+
+    let basic_block_matches = bindiff.read_basic_block_matches_for_function(function_match.id)?;
+    let instruction_matches = bindiff.read_instruction_matches_for_function(function_match.id)?;
+    // A function missing from a BinExport only loses its page content
+    match FlowGraphDiff::new(function_match, &basic_block_matches, primary, secondary) {
+        Ok(graph) => writeln!(
+            out,
+            "<h3>Flow graphs</h3>\n<div class=\"graph\">{}</div>",
+            graph.to_svg()
+        )
+        .unwrap(),
+        Err(error) => writeln!(out, "<p>{}</p>", escape_xml(&format!("{:#}", error))).unwrap(),
+    }
+    match InstructionDiff::new(
+        function_match,
+        &basic_block_matches,
+        &instruction_matches,
+        primary,
+        secondary,
+    ) {
+        Ok(diff) => write_instruction_diff(out, &diff),
+        Err(error) => writeln!(out, "<p>{}</p>", escape_xml(&format!("{:#}", error))).unwrap(),
+    }
+    writeln!(out, "</section>").unwrap();
+    Ok(())
+}
+
+fn write_instruction_diff(out: &mut String, diff: &InstructionDiff) {
+    writeln!(out, "<h3>Disassembly</h3>\n<table class=\"disasm\">").unwrap();
+    for hunk in &diff.hunks {
+        for (index, line) in hunk.lines.iter().enumerate() {
+            let kind = match line.kind {
+                LineKind::Equal => "equal",
+                LineKind::Changed => "changed",
+                LineKind::Removed => "removed",
+                LineKind::Added => "added",
+            };
+            let class = if index == 0 {
+                format!("{} hunk", kind)
+            } else {
+                kind.to_string()
+            };
+            let side = |instruction: &Option<DiffInstruction>| {
+                instruction.as_ref().map_or_else(
+                    || "<td></td><td></td>".to_string(),
+                    |i| {
+                        format!(
+                            "<td>{:#x}</td><td>{}</td>",
+                            i.address,
+                            escape_xml(&i.to_string())
+                        )
+                    },
+                )
+            };
+            writeln!(
+                out,
+                "<tr class=\"{}\">{}{}</tr>",
+                class,
+                side(&line.left),
+                side(&line.right)
+            )
+            .unwrap();
+        }
+    }
+    writeln!(out, "</table>").unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::{binexport, function_match, FunctionSpec};
+    use crate::{BasicBlockAlgorithm, BasicBlockMatch};
+
+    #[test]
+    fn test_html_report() -> Result<()> {
+        let path =
+            std::env::temp_dir().join(format!("bindiff-rs-html-{}.BinDiff", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let db = BinDiff::create(&path)?;
+        let file = |id, filename: &str| File {
+            id,
+            filename: filename.to_string(),
+            exe_filename: String::new(),
+            hash: String::new(),
+            functions: 2,
+            lib_functions: 0,
+            calls: 0,
+            basic_blocks: 3,
+            lib_basic_blocks: 0,
+            edges: 1,
+            lib_edges: 0,
+            instructions: 4,
+            lib_instructions: 0,
+        };
+        db.write_file(&file(1, "old<1>"))?;
+        db.write_file(&file(2, "new"))?;
+        db.write_metadata(&Metadata {
+            version: "BinDiff 8".to_string(),
+            file1: 1,
+            file2: 2,
+            description: String::new(),
+            created: chrono::Utc::now(),
+            modified: chrono::Utc::now(),
+            similarity: 0.8,
+            confidence: 0.9,
+        })?;
+        db.write_function_matches(&[FunctionMatch {
+            name1: "foo".to_string(),
+            name2: "foo".to_string(),
+            similarity: 0.6,
+            ..function_match(0x1000, 0x2000)
+        }])?;
+        db.write_basic_block_matches(&[BasicBlockMatch {
+            id: 1,
+            function_id: 1,
+            address1: 0x1000,
+            address2: 0x2000,
+            algorithm: BasicBlockAlgorithm::EdgesPrimeProduct,
+            evaluate: false,
+        }])?;
+
+        let primary = binexport(&[
+            FunctionSpec::new(0x1000, "foo", vec![vec!["cmp x0, x1", "b.eq"], vec!["ret"]])
+                .edges(&[(0, 1)]),
+            FunctionSpec::new(0x3000, "removed", vec![vec!["ret"]]),
+        ]);
+        let secondary = binexport(&[FunctionSpec::new(
+            0x2000,
+            "foo",
+            vec![vec!["cmp x0, x2", "b.eq"]],
+        )]);
+
+        let html = html_report(&db, Some((&primary, &secondary)), &HtmlOptions::default())?;
+        assert!(html.starts_with("<!DOCTYPE html>"));
+        assert!(html.contains("<title>old&lt;1&gt; vs new</title>"));
+        assert!(html.contains("<a href=\"#f1\">foo</a>"));
+        assert!(html.contains("<section class=\"page\" id=\"f1\">"));
+        assert!(html.contains("<svg"));
+        assert!(html.contains("<tr class=\"removed hunk\"><td>0x1008</td><td>ret</td>"));
+        assert!(html.contains("Removed (primary only) (1)"));
+
+        let overview = html_report(&db, None, &HtmlOptions::default())?;
+        assert!(!overview.contains("<section"));
+        assert!(overview.contains("<td class=\"number\">0.600</td>"));
+
+        db.close()?;
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,484 @@
+//! Side-by-side instruction level diff of a matched function, aligned using
+//! BinDiff's basic block and instruction matches (like `diff -y`).
+
+use crate::flowgraph::{BasicBlock, Function};
+use crate::{BasicBlockMatch, BinExport, FunctionMatch, Instruction};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
+
+const RED: &str = "\x1b[31m";
+const GREEN: &str = "\x1b[32m";
+const YELLOW: &str = "\x1b[33m";
+const BOLD: &str = "\x1b[1m";
+const RESET: &str = "\x1b[0m";
+const MAX_COLUMN: usize = 60;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LineKind {
+    /// Matched instructions with identical text
+    Equal,
+    /// Matched instructions whose mnemonic or operands differ
+    Changed,
+    /// Primary instruction without a match
+    Removed,
+    /// Secondary instruction without a match
+    Added,
+}
+
+/// A disassembled instruction on one side of the diff
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DiffInstruction {
+    pub address: u64,
+    pub mnemonic: String,
+    pub operands: Vec<String>,
+}
+
+impl DiffInstruction {
+    fn new(instruction: &crate::flowgraph::Instruction) -> Self {
+        Self {
+            address: instruction.address(),
+            mnemonic: instruction.mnemonic().to_string(),
+            operands: instruction.operands(),
+        }
+    }
+}
+
+impl std::fmt::Display for DiffInstruction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.operands.is_empty() {
+            write!(f, "{}", self.mnemonic)
+        } else {
+            write!(f, "{} {}", self.mnemonic, self.operands.join(", "))
+        }
+    }
+}
+
+/// One row of the side-by-side diff
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DiffLine {
+    pub kind: LineKind,
+    pub left: Option<DiffInstruction>,
+    pub right: Option<DiffInstruction>,
+    pub mnemonic_changed: bool,
+    /// Positions of operands that differ between the two sides
+    pub changed_operands: Vec<usize>,
+}
+
+impl DiffLine {
+    fn removed(left: DiffInstruction) -> Self {
+        Self {
+            kind: LineKind::Removed,
+            left: Some(left),
+            right: None,
+            mnemonic_changed: false,
+            changed_operands: Vec::new(),
+        }
+    }
+
+    fn added(right: DiffInstruction) -> Self {
+        Self {
+            kind: LineKind::Added,
+            left: None,
+            right: Some(right),
+            mnemonic_changed: false,
+            changed_operands: Vec::new(),
+        }
+    }
+
+    fn paired(left: DiffInstruction, right: DiffInstruction) -> Self {
+        let mnemonic_changed = left.mnemonic != right.mnemonic;
+        let changed_operands: Vec<usize> = (0..left.operands.len().max(right.operands.len()))
+            .filter(|&i| left.operands.get(i) != right.operands.get(i))
+            .collect();
+        let kind = if mnemonic_changed || !changed_operands.is_empty() {
+            LineKind::Changed
+        } else {
+            LineKind::Equal
+        };
+        Self {
+            kind,
+            left: Some(left),
+            right: Some(right),
+            mnemonic_changed,
+            changed_operands,
+        }
+    }
+}
+
+/// The aligned instructions of a basic block pair, or of a single unmatched
+/// basic block
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Hunk {
+    pub address1: Option<u64>,
+    pub address2: Option<u64>,
+    pub lines: Vec<DiffLine>,
+}
+
+impl Hunk {
+    pub fn is_equal(&self) -> bool {
+        self.lines.iter().all(|line| line.kind == LineKind::Equal)
+    }
+}
+
+/// Side-by-side instruction diff of a function match
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InstructionDiff {
+    pub address1: u64,
+    pub name1: String,
+    pub address2: u64,
+    pub name2: String,
+    pub hunks: Vec<Hunk>,
+}
+
+impl InstructionDiff {
+    /// Align the instructions of `function_match` using its basic block and
+    /// instruction matches
+    pub fn new(
+        function_match: &FunctionMatch,
+        basic_block_matches: &[BasicBlockMatch],
+        instruction_matches: &[Instruction],
+        primary: &BinExport,
+        secondary: &BinExport,
+    ) -> Result<Self> {
+        let function1 = primary
+            .function(function_match.address1 as u64)
+            .context("Failed to find primary function in BinExport")?;
+        let function2 = secondary
+            .function(function_match.address2 as u64)
+            .context("Failed to find secondary function in BinExport")?;
+
+        let blocks: HashMap<u64, u64> = basic_block_matches
+            .iter()
+            .map(|m| (m.address1 as u64, m.address2 as u64))
+            .collect();
+        let instructions: HashMap<u64, u64> = instruction_matches
+            .iter()
+            .map(|m| (m.address1 as u64, m.address2 as u64))
+            .collect();
+
+        let mut hunks: Vec<Hunk> = function1
+            .basic_blocks()
+            .map(|block1| {
+                let partner = blocks
+                    .get(&block1.address())
+                    .and_then(|&address| function2.basic_block(address));
+                match partner {
+                    Some(block2) => Hunk {
+                        address1: Some(block1.address()),
+                        address2: Some(block2.address()),
+                        lines: align(&block1, &block2, &instructions),
+                    },
+                    None => Hunk {
+                        address1: Some(block1.address()),
+                        address2: None,
+                        lines: block1
+                            .instructions()
+                            .map(|i| DiffLine::removed(DiffInstruction::new(&i)))
+                            .collect(),
+                    },
+                }
+            })
+            .collect();
+
+        // Place each unmatched secondary block after the hunk holding the
+        // closest preceding secondary block
+        let matched2: HashSet<u64> = blocks.values().copied().collect();
+        for block2 in unmatched_blocks(&function2, &matched2) {
+            let hunk = Hunk {
+                address1: None,
+                address2: Some(block2.address()),
+                lines: block2
+                    .instructions()
+                    .map(|i| DiffLine::added(DiffInstruction::new(&i)))
+                    .collect(),
+            };
+            let position = hunks
+                .iter()
+                .rposition(|h| h.address2.is_some_and(|a| a < block2.address()))
+                .map_or(0, |i| i + 1);
+            hunks.insert(position, hunk);
+        }
+
+        Ok(Self {
+            address1: function1.address(),
+            name1: function_match.name1.clone(),
+            address2: function2.address(),
+            name2: function_match.name2.clone(),
+            hunks,
+        })
+    }
+
+    /// Render the diff as two columns, optionally with ANSI colors: removed
+    /// lines in red, added lines in green and changed lines in yellow with the
+    /// differing mnemonic/operands in bold
+    pub fn render(&self, color: bool) -> String {
+        let width = self
+            .hunks
+            .iter()
+            .flat_map(|hunk| &hunk.lines)
+            .filter_map(|line| line.left.as_ref())
+            .map(|left| format!("{:#x}  {}", left.address, left).len())
+            .max()
+            .unwrap_or(0)
+            .min(MAX_COLUMN);
+
+        let mut out = String::new();
+        let header = format!("{} ({:#x})", self.name1, self.address1);
+        writeln!(
+            out,
+            "{:<width$}   {} ({:#x})",
+            header, self.name2, self.address2
+        )
+        .unwrap();
+        for hunk in &self.hunks {
+            writeln!(out).unwrap();
+            for line in &hunk.lines {
+                let (marker, paint) = match line.kind {
+                    LineKind::Equal => (' ', ""),
+                    LineKind::Changed => ('|', YELLOW),
+                    LineKind::Removed => ('<', RED),
+                    LineKind::Added => ('>', GREEN),
+                };
+                let left = line
+                    .left
+                    .as_ref()
+                    .map(|i| format!("{:#x}  {}", i.address, i))
+                    .unwrap_or_default();
+                let padding = width.saturating_sub(left.len());
+                let left = line
+                    .left
+                    .as_ref()
+                    .map(|i| side(i, line, color))
+                    .unwrap_or_default();
+                let right = line
+                    .right
+                    .as_ref()
+                    .map(|i| side(i, line, color))
+                    .unwrap_or_default();
+                if color && !paint.is_empty() {
+                    writeln!(
+                        out,
+                        "{}{}{}{} {} {}{}{}",
+                        paint,
+                        left,
+                        RESET,
+                        " ".repeat(padding),
+                        marker,
+                        paint,
+                        right,
+                        RESET
+                    )
+                    .unwrap();
+                } else {
+                    writeln!(out, "{}{} {} {}", left, " ".repeat(padding), marker, right).unwrap();
+                }
+            }
+        }
+        out
+    }
+}
+
+impl std::fmt::Display for InstructionDiff {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.render(false))
+    }
+}
+
+fn side(instruction: &DiffInstruction, line: &DiffLine, color: bool) -> String {
+    if !color || line.kind != LineKind::Changed {
+        return format!("{:#x}  {}", instruction.address, instruction);
+    }
+    let highlight = |text: &str, changed: bool| {
+        if changed {
+            format!("{}{}{}{}", BOLD, text, RESET, YELLOW)
+        } else {
+            text.to_string()
+        }
+    };
+    let operands: Vec<String> = instruction
+        .operands
+        .iter()
+        .enumerate()
+        .map(|(i, operand)| highlight(operand, line.changed_operands.contains(&i)))
+        .collect();
+    let mnemonic = highlight(&instruction.mnemonic, line.mnemonic_changed);
+    if operands.is_empty() {
+        format!("{:#x}  {}", instruction.address, mnemonic)
+    } else {
+        format!(
+            "{:#x}  {} {}",
+            instruction.address,
+            mnemonic,
+            operands.join(", ")
+        )
+    }
+}
+
+fn unmatched_blocks<'a>(function: &Function<'a>, matched: &HashSet<u64>) -> Vec<BasicBlock<'a>> {
+    function
+        .basic_blocks()
+        .filter(|block| !matched.contains(&block.address()))
+        .collect()
+}
+
+/// Align the instructions of a matched basic block pair. Falls back to the
+/// longest common subsequence of mnemonics when the BinDiff result has no
+/// instruction matches for the pair.
+fn align(block1: &BasicBlock, block2: &BasicBlock, matches: &HashMap<u64, u64>) -> Vec<DiffLine> {
+    let left: Vec<DiffInstruction> = block1
+        .instructions()
+        .map(|i| DiffInstruction::new(&i))
+        .collect();
+    let right: Vec<DiffInstruction> = block2
+        .instructions()
+        .map(|i| DiffInstruction::new(&i))
+        .collect();
+    let right_index: HashMap<u64, usize> = right
+        .iter()
+        .enumerate()
+        .map(|(i, instruction)| (instruction.address, i))
+        .collect();
+
+    let mut pairs: Vec<(usize, usize)> = left
+        .iter()
+        .enumerate()
+        .filter_map(|(i, instruction)| {
+            let partner = matches.get(&instruction.address)?;
+            Some((i, *right_index.get(partner)?))
+        })
+        .collect();
+    if pairs.is_empty() {
+        let mnemonics = |instructions: &[DiffInstruction]| -> Vec<String> {
+            instructions.iter().map(|i| i.mnemonic.clone()).collect()
+        };
+        pairs = lcs(&mnemonics(&left), &mnemonics(&right));
+    }
+
+    let mut lines = Vec::new();
+    let mut j = 0;
+    let mut pairs = pairs.into_iter().peekable();
+    for (i, instruction) in left.iter().enumerate() {
+        match pairs.peek() {
+            Some(&(pi, pj)) if pi == i && pj >= j => {
+                pairs.next();
+                lines.extend(right[j..pj].iter().cloned().map(DiffLine::added));
+                lines.push(DiffLine::paired(instruction.clone(), right[pj].clone()));
+                j = pj + 1;
+            }
+            Some(&(pi, _)) if pi == i => {
+                // Crossing match, show it as a removal
+                pairs.next();
+                lines.push(DiffLine::removed(instruction.clone()));
+            }
+            _ => lines.push(DiffLine::removed(instruction.clone())),
+        }
+    }
+    lines.extend(right[j..].iter().cloned().map(DiffLine::added));
+    lines
+}
+
+/// Index pairs of the longest common subsequence of `left` and `right`
+pub(crate) fn lcs<T: PartialEq>(left: &[T], right: &[T]) -> Vec<(usize, usize)> {
+    let mut table = vec![vec![0usize; right.len() + 1]; left.len() + 1];
+    for i in (0..left.len()).rev() {
+        for j in (0..right.len()).rev() {
+            table[i][j] = if left[i] == right[j] {
+                table[i + 1][j + 1] + 1
+            } else {
+                table[i + 1][j].max(table[i][j + 1])
+            };
+        }
+    }
+
+    let mut pairs = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < left.len() && j < right.len() {
+        if left[i] == right[j] {
+            pairs.push((i, j));
+            i += 1;
+            j += 1;
+        } else if table[i + 1][j] >= table[i][j + 1] {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+    pairs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::{binexport, function_match, FunctionSpec};
+    use crate::BasicBlockAlgorithm;
+
+    #[test]
+    fn test_instruction_diff() -> Result<()> {
+        let primary = binexport(&[FunctionSpec::new(
+            0x1000,
+            "foo",
+            vec![vec!["cmp x0, #0x10", "b.hi", "ret"]],
+        )]);
+        let secondary = binexport(&[FunctionSpec::new(
+            0x2000,
+            "foo",
+            vec![
+                vec!["cmp x0, #0x20", "b.hi", "mov x0, xzr", "ret"],
+                vec!["brk"],
+            ],
+        )]);
+        let function_match = FunctionMatch {
+            similarity: 0.8,
+            confidence: 0.9,
+            instructions: 3,
+            ..function_match(0x1000, 0x2000)
+        };
+        let basic_block_matches = [BasicBlockMatch {
+            id: 1,
+            function_id: 1,
+            address1: 0x1000,
+            address2: 0x2000,
+            algorithm: BasicBlockAlgorithm::EdgesPrimeProduct,
+            evaluate: false,
+        }];
+        let instruction_matches =
+            [(0x1000, 0x2000), (0x1004, 0x2004), (0x1008, 0x200c)].map(|(address1, address2)| {
+                Instruction {
+                    id: 1,
+                    address1,
+                    address2,
+                }
+            });
+
+        let diff = InstructionDiff::new(
+            &function_match,
+            &basic_block_matches,
+            &instruction_matches,
+            &primary,
+            &secondary,
+        )?;
+        assert_eq!(diff.hunks.len(), 2);
+        let kinds: Vec<LineKind> = diff.hunks[0].lines.iter().map(|l| l.kind).collect();
+        assert_eq!(
+            kinds,
+            [
+                LineKind::Changed,
+                LineKind::Equal,
+                LineKind::Added,
+                LineKind::Equal
+            ]
+        );
+        assert_eq!(diff.hunks[0].lines[0].changed_operands, [1]);
+        assert!(!diff.hunks[0].lines[0].mnemonic_changed);
+        assert_eq!(diff.hunks[1].address2, Some(0x2010));
+        assert_eq!(diff.hunks[1].lines[0].kind, LineKind::Added);
+
+        let text = diff.to_string();
+        assert!(text.contains("0x1000  cmp x0, #0x10 | 0x2000  cmp x0, #0x20"));
+        assert!(text.contains("> 0x2008  mov x0, xzr"));
+
+        Ok(())
+    }
+}
@@ -1,15 +1,41 @@
 use anyhow::{Context, Result};
-use chrono;
 use prost::Message;
-use rusqlite::types::{FromSql, FromSqlResult, ValueRef};
-use rusqlite::{params, Connection};
+use rusqlite::types::{FromSql, FromSqlResult, ToSql, ToSqlOutput, ValueRef};
+use rusqlite::{params, Connection, OpenFlags};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::path::Path;
 
 pub mod binexport {
     include!(concat!(env!("OUT_DIR"), "/binexport.rs"));
 }
 
+pub mod basic_block_matching;
+pub mod check;
+pub mod comments;
+pub mod compare;
+pub mod config;
+pub mod evaluation;
+pub mod flowgraph;
+pub mod html;
+pub mod instruction_diff;
+pub mod markdown;
+pub mod matching;
+pub mod md_index;
+pub mod output;
+pub mod prime;
+pub mod render;
+pub mod session;
+pub mod similarity;
+pub mod stats;
+pub mod symbols;
+pub mod timeline;
+pub mod triage;
+pub mod unmatched;
+
+#[cfg(test)]
+mod testutil;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct File {
     pub id: i64,
@@ -27,6 +53,27 @@ pub struct File {
     pub lib_instructions: i64,
 }
 
+impl File {
+    /// Map a `SELECT *` row of the `file` table
+    fn from_row(row: &rusqlite::Row) -> rusqlite::Result<Self> {
+        Ok(Self {
+            id: row.get(0)?,
+            filename: row.get(1)?,
+            exe_filename: row.get(2)?,
+            hash: row.get(3)?,
+            functions: row.get(4)?,
+            lib_functions: row.get(5)?,
+            calls: row.get(6)?,
+            basic_blocks: row.get(7)?,
+            lib_basic_blocks: row.get(8)?,
+            edges: row.get(9)?,
+            lib_edges: row.get(10)?,
+            instructions: row.get(11)?,
+            lib_instructions: row.get(12)?,
+        })
+    }
+}
+
 impl std::fmt::Display for File {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
@@ -100,7 +147,7 @@ impl std::fmt::Display for Metadata {
 }
 
 /// Enum representing the different function matching algorithms used in BinDiff
-#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum FunctionAlgorithm {
     None,
     NameHashMatching,
@@ -197,6 +244,41 @@ impl FromSql for FunctionAlgorithm {
     }
 }
 
+impl FunctionAlgorithm {
+    /// Numeric id of the algorithm as stored in the `function` table
+    pub fn id(&self) -> i64 {
+        match self {
+            FunctionAlgorithm::None => 0,
+            FunctionAlgorithm::NameHashMatching => 1,
+            FunctionAlgorithm::HashMatching => 2,
+            FunctionAlgorithm::EdgesFlowgraphMdIndex => 3,
+            FunctionAlgorithm::EdgesCallgraphMdIndex => 4,
+            FunctionAlgorithm::MdIndexMatchingFlowgraphTopDown => 5,
+            FunctionAlgorithm::MdIndexMatchingFlowgraphBottomUp => 6,
+            FunctionAlgorithm::PrimeSignatureMatching => 7,
+            FunctionAlgorithm::MdIndexMatchingCallGraphTopDown => 8,
+            FunctionAlgorithm::MdIndexMatchingCallGraphBottomUp => 9,
+            FunctionAlgorithm::RelaxedMdIndexMatching => 10,
+            FunctionAlgorithm::InstructionCount => 11,
+            FunctionAlgorithm::AddressSequence => 12,
+            FunctionAlgorithm::StringReferences => 13,
+            FunctionAlgorithm::LoopCountMatching => 14,
+            FunctionAlgorithm::CallSequenceMatchingExact => 15,
+            FunctionAlgorithm::CallSequenceMatchingTopology => 16,
+            FunctionAlgorithm::CallSequenceMatchingSequence => 17,
+            FunctionAlgorithm::CallReferenceMatching => 18,
+            FunctionAlgorithm::Manual => 19,
+            FunctionAlgorithm::Other(s) => s.parse().unwrap_or(0),
+        }
+    }
+}
+
+impl ToSql for FunctionAlgorithm {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(self.id()))
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct FunctionMatch {
     pub id: i64,
@@ -215,6 +297,28 @@ pub struct FunctionMatch {
     pub instructions: i64,
 }
 
+impl FunctionMatch {
+    /// Map a `SELECT *` row of the `function` table
+    fn from_row(row: &rusqlite::Row) -> rusqlite::Result<Self> {
+        Ok(Self {
+            id: row.get(0)?,
+            address1: row.get(1)?,
+            name1: row.get(2)?,
+            address2: row.get(3)?,
+            name2: row.get(4)?,
+            similarity: row.get(5)?,
+            confidence: row.get(6)?,
+            flags: row.get(7)?,
+            algorithm: row.get(8)?,
+            evaluate: row.get(9)?,
+            comment_supported: row.get(10)?,
+            basic_blocks: row.get(11)?,
+            edges: row.get(12)?,
+            instructions: row.get(13)?,
+        })
+    }
+}
+
 impl std::fmt::Display for FunctionMatch {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         if self.name1 != self.name2 {
@@ -233,8 +337,62 @@ impl std::fmt::Display for FunctionMatch {
     }
 }
 
+/// Column to order function matches by
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FunctionSort {
+    Similarity,
+    Confidence,
+    Instructions,
+    /// Primary address
+    Address,
+}
+
+impl FunctionSort {
+    fn column(&self) -> &'static str {
+        match self {
+            FunctionSort::Similarity => "similarity",
+            FunctionSort::Confidence => "confidence",
+            FunctionSort::Instructions => "instructions",
+            FunctionSort::Address => "address1",
+        }
+    }
+}
+
+impl std::str::FromStr for FunctionSort {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "similarity" => Ok(FunctionSort::Similarity),
+            "confidence" => Ok(FunctionSort::Confidence),
+            "instructions" => Ok(FunctionSort::Instructions),
+            "address" => Ok(FunctionSort::Address),
+            _ => anyhow::bail!("Unknown sort key: {}", s),
+        }
+    }
+}
+
+/// Conditions on the `function` table, all of which a match has to meet
+#[derive(Debug, Clone, Default)]
+pub struct FunctionFilter {
+    pub min_similarity: Option<f64>,
+    pub max_similarity: Option<f64>,
+    pub min_confidence: Option<f64>,
+    pub max_confidence: Option<f64>,
+    pub algorithm: Option<FunctionAlgorithm>,
+    /// SQLite GLOB pattern (`*`, `?`, `[...]`) matched against either name
+    pub name: Option<String>,
+    /// Only matches with a similarity below 1.0
+    pub changed: bool,
+    /// Only matches whose primary and secondary names differ
+    pub renamed: bool,
+    pub sort: Option<FunctionSort>,
+    pub descending: bool,
+    pub limit: Option<usize>,
+}
+
 /// Enum representing the different basic block matching algorithms used in BinDiff
-#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum BasicBlockAlgorithm {
     None,
     EdgesPrimeProduct,
@@ -334,6 +492,42 @@ impl FromSql for BasicBlockAlgorithm {
     }
 }
 
+impl BasicBlockAlgorithm {
+    /// Numeric id of the algorithm as stored in the `basicblock` table
+    pub fn id(&self) -> i64 {
+        match self {
+            BasicBlockAlgorithm::None => 0,
+            BasicBlockAlgorithm::EdgesPrimeProduct => 1,
+            BasicBlockAlgorithm::HashMatchingFourInstMin => 2,
+            BasicBlockAlgorithm::PrimeMatchingFourInstMin => 3,
+            BasicBlockAlgorithm::CallReferenceMatching => 4,
+            BasicBlockAlgorithm::StringReferencesMatching => 5,
+            BasicBlockAlgorithm::EdgesMdIndexTopDown => 6,
+            BasicBlockAlgorithm::MdIndexMatchingTopDown => 7,
+            BasicBlockAlgorithm::EdgesMdIndexBottomUp => 8,
+            BasicBlockAlgorithm::MdIndexMatchingBottomUp => 9,
+            BasicBlockAlgorithm::RelaxedMdIndexMatching => 10,
+            BasicBlockAlgorithm::PrimeMatchingNoInstMin => 11,
+            BasicBlockAlgorithm::EdgesLengauerTarjanDominated => 12,
+            BasicBlockAlgorithm::LoopEntryMatching => 13,
+            BasicBlockAlgorithm::SelfLoopMatching => 14,
+            BasicBlockAlgorithm::EntryPointMatching => 15,
+            BasicBlockAlgorithm::ExitPointMatching => 16,
+            BasicBlockAlgorithm::InstructionCountMatching => 17,
+            BasicBlockAlgorithm::JumpSequenceMatching => 18,
+            BasicBlockAlgorithm::PropagationSizeOne => 19,
+            BasicBlockAlgorithm::Manual => 20,
+            BasicBlockAlgorithm::Other(s) => s.parse().unwrap_or(0),
+        }
+    }
+}
+
+impl ToSql for BasicBlockAlgorithm {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(self.id()))
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct BasicBlockMatch {
     pub id: i64,
@@ -344,6 +538,20 @@ pub struct BasicBlockMatch {
     pub evaluate: bool,
 }
 
+impl BasicBlockMatch {
+    /// Map a `SELECT *` row of the `basicblock` table
+    fn from_row(row: &rusqlite::Row) -> rusqlite::Result<Self> {
+        Ok(Self {
+            id: row.get(0)?,
+            function_id: row.get(1)?,
+            address1: row.get(2)?,
+            address2: row.get(3)?,
+            algorithm: row.get(4)?,
+            evaluate: row.get(5)?,
+        })
+    }
+}
+
 impl std::fmt::Display for BasicBlockMatch {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
@@ -361,21 +569,96 @@ pub struct Instruction {
     pub address2: i64,
 }
 
+impl Instruction {
+    /// Map a `SELECT *` row of the `instruction` table
+    fn from_row(row: &rusqlite::Row) -> rusqlite::Result<Self> {
+        Ok(Self {
+            id: row.get(0)?,
+            address1: row.get(1)?,
+            address2: row.get(2)?,
+        })
+    }
+}
+
 impl std::fmt::Display for Instruction {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{} -> {}", self.address1, self.address2)
     }
 }
 
+/// Schema of a BinDiff 8 result database
+const SCHEMA: &str = "
+CREATE TABLE file (id INTEGER PRIMARY KEY, filename TEXT, exefilename TEXT, hash CHARACTER(40),
+    functions INT, libfunctions INT, calls INT, basicblocks INT, libbasicblocks INT, edges INT,
+    libedges INT, instructions INT, libinstructions INT);
+CREATE TABLE metadata (version TEXT, file1 INTEGER, file2 INTEGER, description TEXT,
+    created DATE, modified DATE, similarity DOUBLE PRECISION, confidence DOUBLE PRECISION,
+    FOREIGN KEY(file1) REFERENCES file(id), FOREIGN KEY(file2) REFERENCES file(id));
+CREATE TABLE functionalgorithm (id SMALLINT PRIMARY KEY, name TEXT);
+CREATE TABLE function (id INTEGER PRIMARY KEY, address1 BIGINT, name1 TEXT, address2 BIGINT,
+    name2 TEXT, similarity DOUBLE PRECISION, confidence DOUBLE PRECISION, flags INTEGER,
+    algorithm SMALLINT, evaluate BOOLEAN, commentsported BOOLEAN, basicblocks INTEGER,
+    edges INTEGER, instructions INTEGER, UNIQUE(address1, address2),
+    FOREIGN KEY(algorithm) REFERENCES functionalgorithm(id));
+CREATE TABLE basicblockalgorithm (id INTEGER PRIMARY KEY, name TEXT);
+CREATE TABLE basicblock (id INTEGER, functionid INT, address1 BIGINT, address2 BIGINT,
+    algorithm SMALLINT, evaluate BOOLEAN, PRIMARY KEY(id),
+    FOREIGN KEY(functionid) REFERENCES function(id),
+    FOREIGN KEY(algorithm) REFERENCES basicblockalgorithm(id));
+CREATE TABLE instruction (basicblockid INT, address1 BIGINT, address2 BIGINT,
+    FOREIGN KEY(basicblockid) REFERENCES basicblock(id));
+";
+
 /// Struct to handle SQLite database operations
 pub struct BinDiff {
     connection: Connection,
 }
 
 impl BinDiff {
-    /// Open a connection to the SQLite database
+    /// Open a connection to an existing SQLite database
     pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
-        let connection = Connection::open(path).context("Failed to open SQLite database")?;
+        let path = path.as_ref();
+        if !path.exists() {
+            anyhow::bail!("No such file: {}", path.display());
+        }
+        // Without SQLITE_OPEN_CREATE a typo cannot leave an empty database behind
+        let flags = OpenFlags::default().difference(OpenFlags::SQLITE_OPEN_CREATE);
+        let connection =
+            Connection::open_with_flags(path, flags).context("Failed to open SQLite database")?;
+        Ok(Self { connection })
+    }
+
+    /// Create a new BinDiff database with an empty schema, refusing to touch
+    /// an existing file
+    pub fn create<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        if path.exists() {
+            anyhow::bail!("File exists: {}", path.display());
+        }
+        let connection = Connection::open(path).context("Failed to create SQLite database")?;
+        connection
+            .execute_batch(SCHEMA)
+            .context("Failed to create BinDiff schema")?;
+
+        for id in 0..=19 {
+            let algorithm = FunctionAlgorithm::column_result(ValueRef::Integer(id))?;
+            connection
+                .execute(
+                    "INSERT INTO functionalgorithm VALUES (?1, ?2)",
+                    params![id, format!("function: {}", algorithm)],
+                )
+                .context("Failed to insert function algorithm")?;
+        }
+        for id in 0..=20 {
+            let algorithm = BasicBlockAlgorithm::column_result(ValueRef::Integer(id))?;
+            connection
+                .execute(
+                    "INSERT INTO basicblockalgorithm VALUES (?1, ?2)",
+                    params![id, format!("basicBlock: {}", algorithm)],
+                )
+                .context("Failed to insert basic block algorithm")?;
+        }
+
         Ok(Self { connection })
     }
 
@@ -411,24 +694,19 @@ impl BinDiff {
             .prepare("SELECT * FROM file")
             .context("Failed to prepare file statement")?;
 
-        stmt.query_row(params![], |row| {
-            Ok(File {
-                id: row.get(0)?,
-                filename: row.get(1)?,
-                exe_filename: row.get(2)?,
-                hash: row.get(3)?,
-                functions: row.get(4)?,
-                lib_functions: row.get(5)?,
-                calls: row.get(6)?,
-                basic_blocks: row.get(7)?,
-                lib_basic_blocks: row.get(8)?,
-                edges: row.get(9)?,
-                lib_edges: row.get(10)?,
-                instructions: row.get(11)?,
-                lib_instructions: row.get(12)?,
-            })
-        })
-        .context("Failed to query file row")
+        stmt.query_row(params![], File::from_row)
+            .context("Failed to query file row")
+    }
+
+    /// Read the file row with the given `id` (see `Metadata::file1`/`file2`)
+    pub fn read_file_by_id(&self, id: i64) -> Result<File> {
+        let mut stmt = self
+            .connection
+            .prepare("SELECT * FROM file WHERE id = ?1")
+            .context("Failed to prepare file statement")?;
+
+        stmt.query_row(params![id], File::from_row)
+            .with_context(|| format!("Failed to query file row {}", id))
     }
 
     /// Count the number of function matches
@@ -448,30 +726,137 @@ impl BinDiff {
             .context("Failed to prepare function statement")?;
 
         let matches = stmt
-            .query_map(params![], |row| {
-                Ok(FunctionMatch {
-                    id: row.get(0)?,
-                    address1: row.get(1)?,
-                    name1: row.get(2)?,
-                    address2: row.get(3)?,
-                    name2: row.get(4)?,
-                    similarity: row.get(5)?,
-                    confidence: row.get(6)?,
-                    flags: row.get(7)?,
-                    algorithm: row.get(8)?,
-                    evaluate: row.get(9)?,
-                    comment_supported: row.get(10)?,
-                    basic_blocks: row.get(11)?,
-                    edges: row.get(12)?,
-                    instructions: row.get(13)?,
-                })
-            })
+            .query_map(params![], FunctionMatch::from_row)
             .context("Failed to query function row")?
             .collect::<Result<Vec<FunctionMatch>, _>>()?;
 
         Ok(matches)
     }
 
+    /// Read the function match of the primary function at `address1`
+    pub fn read_function_match(&self, address1: u64) -> Result<Option<FunctionMatch>> {
+        let mut stmt = self
+            .connection
+            .prepare("SELECT * FROM function WHERE address1 = ?1")
+            .context("Failed to prepare function statement")?;
+
+        let function_match = stmt
+            .query_map(params![address1 as i64], FunctionMatch::from_row)
+            .context("Failed to query function row")?
+            .next()
+            .transpose()?;
+
+        Ok(function_match)
+    }
+
+    /// Look up a function match by primary or secondary address (`0x`
+    /// prefixed hex) or by primary or secondary name; queries that start with
+    /// `0x` but are no hex address, such as `0x_init`, are looked up as names
+    pub fn find_function_match(&self, query: &str) -> Result<Option<FunctionMatch>> {
+        let address = query
+            .strip_prefix("0x")
+            .and_then(|hex| u64::from_str_radix(hex, 16).ok());
+        let (sql, value): (&str, Box<dyn ToSql>) = match address {
+            Some(address) => (
+                "SELECT * FROM function WHERE address1 = ?1 OR address2 = ?1 \
+                 ORDER BY address1 != ?1 LIMIT 1",
+                Box::new(address as i64),
+            ),
+            None => (
+                "SELECT * FROM function WHERE name1 = ?1 OR name2 = ?1 \
+                 ORDER BY name1 != ?1 LIMIT 1",
+                Box::new(query.to_string()),
+            ),
+        };
+        let mut stmt = self
+            .connection
+            .prepare(sql)
+            .context("Failed to prepare function statement")?;
+
+        let function_match = stmt
+            .query_map(params![value], FunctionMatch::from_row)
+            .context("Failed to query function row")?
+            .next()
+            .transpose()?;
+
+        Ok(function_match)
+    }
+
+    /// Read the function matches that pass `filter`, filtered, ordered and
+    /// limited by SQLite
+    pub fn query_function_matches(&self, filter: &FunctionFilter) -> Result<Vec<FunctionMatch>> {
+        let mut matches = Vec::new();
+        self.for_each_function_match(filter, |function_match| {
+            matches.push(function_match);
+            Ok(())
+        })?;
+        Ok(matches)
+    }
+
+    /// Call `f` with every function match that passes `filter` as it is read,
+    /// without collecting them
+    pub fn for_each_function_match<F>(&self, filter: &FunctionFilter, mut f: F) -> Result<()>
+    where
+        F: FnMut(FunctionMatch) -> Result<()>,
+    {
+        let mut sql = String::from("SELECT * FROM function WHERE 1 = 1");
+        let mut values: Vec<Box<dyn ToSql>> = Vec::new();
+        let bounds = [
+            ("similarity >=", filter.min_similarity),
+            ("similarity <=", filter.max_similarity),
+            ("confidence >=", filter.min_confidence),
+            ("confidence <=", filter.max_confidence),
+        ];
+        for (condition, value) in bounds {
+            if let Some(value) = value {
+                sql.push_str(&format!(" AND {} ?", condition));
+                values.push(Box::new(value));
+            }
+        }
+        if let Some(algorithm) = &filter.algorithm {
+            sql.push_str(" AND algorithm = ?");
+            values.push(Box::new(algorithm.id()));
+        }
+        if let Some(name) = &filter.name {
+            sql.push_str(" AND (name1 GLOB ? OR name2 GLOB ?)");
+            values.push(Box::new(name.clone()));
+            values.push(Box::new(name.clone()));
+        }
+        if filter.changed {
+            sql.push_str(" AND similarity < 1.0");
+        }
+        if filter.renamed {
+            sql.push_str(" AND name1 != name2");
+        }
+        if let Some(sort) = filter.sort {
+            // Break ties by id so equal keys keep a stable order under LIMIT
+            sql.push_str(&format!(
+                " ORDER BY {} {}, id",
+                sort.column(),
+                if filter.descending { "DESC" } else { "ASC" }
+            ));
+        }
+        if let Some(limit) = filter.limit {
+            sql.push_str(" LIMIT ?");
+            values.push(Box::new(limit as i64));
+        }
+
+        let mut stmt = self
+            .connection
+            .prepare(&sql)
+            .context("Failed to prepare function statement")?;
+        let rows = stmt
+            .query_map(
+                rusqlite::params_from_iter(values.iter()),
+                FunctionMatch::from_row,
+            )
+            .context("Failed to query function row")?;
+        for row in rows {
+            f(row?)?;
+        }
+        Ok(())
+    }
+
     /// Count the number of basic block matches
     pub fn count_basic_block_matches(&self) -> Result<usize> {
         let count: i64 = self
@@ -484,6 +869,26 @@ impl BinDiff {
         Ok(count as usize)
     }
 
+    /// Call `f` with every basic block match as it is read, without
+    /// collecting them
+    pub fn for_each_basic_block_match<F>(&self, mut f: F) -> Result<()>
+    where
+        F: FnMut(BasicBlockMatch) -> Result<()>,
+    {
+        let mut stmt = self
+            .connection
+            .prepare("SELECT * FROM basicblock")
+            .context("Failed to prepare basicblock statement")?;
+
+        let rows = stmt
+            .query_map(params![], BasicBlockMatch::from_row)
+            .context("Failed to query basicblock row")?;
+        for row in rows {
+            f(row?)?;
+        }
+        Ok(())
+    }
+
     pub fn read_basic_block_matches(&self) -> Result<Vec<BasicBlockMatch>> {
         let mut stmt = self
             .connection
@@ -491,16 +896,25 @@ impl BinDiff {
             .context("Failed to prepare basicblock statement")?;
 
         let matches = stmt
-            .query_map(params![], |row| {
-                Ok(BasicBlockMatch {
-                    id: row.get(0)?,
-                    function_id: row.get(1)?,
-                    address1: row.get(2)?,
-                    address2: row.get(3)?,
-                    algorithm: row.get(4)?,
-                    evaluate: row.get(5)?,
-                })
-            })
+            .query_map(params![], BasicBlockMatch::from_row)
+            .context("Failed to query basicblock row")?
+            .collect::<Result<Vec<BasicBlockMatch>, _>>()?;
+
+        Ok(matches)
+    }
+
+    /// Read the basic block matches belonging to the function match `function_id`
+    pub fn read_basic_block_matches_for_function(
+        &self,
+        function_id: i64,
+    ) -> Result<Vec<BasicBlockMatch>> {
+        let mut stmt = self
+            .connection
+            .prepare("SELECT * FROM basicblock WHERE functionid = ?1 ORDER BY address1")
+            .context("Failed to prepare basicblock statement")?;
+
+        let matches = stmt
+            .query_map(params![function_id], BasicBlockMatch::from_row)
             .context("Failed to query basicblock row")?
             .collect::<Result<Vec<BasicBlockMatch>, _>>()?;
 
@@ -519,6 +933,26 @@ impl BinDiff {
         Ok(count as usize)
     }
 
+    /// Call `f` with every instruction match as it is read, without
+    /// collecting them
+    pub fn for_each_instruction_match<F>(&self, mut f: F) -> Result<()>
+    where
+        F: FnMut(Instruction) -> Result<()>,
+    {
+        let mut stmt = self
+            .connection
+            .prepare("SELECT * FROM instruction")
+            .context("Failed to prepare instruction statement")?;
+
+        let rows = stmt
+            .query_map(params![], Instruction::from_row)
+            .context("Failed to query instruction row")?;
+        for row in rows {
+            f(row?)?;
+        }
+        Ok(())
+    }
+
     pub fn read_instruction_matches(&self) -> Result<Vec<Instruction>> {
         let mut stmt = self
             .connection
@@ -526,23 +960,161 @@ impl BinDiff {
             .context("Failed to prepare instruction statement")?;
 
         let matches = stmt
-            .query_map(params![], |row| {
-                Ok(Instruction {
-                    id: row.get(0)?,
-                    address1: row.get(1)?,
-                    address2: row.get(2)?,
-                })
-            })
+            .query_map(params![], Instruction::from_row)
             .context("Failed to query instruction row")?
             .collect::<Result<Vec<Instruction>, _>>()?;
 
         Ok(matches)
     }
+
+    /// Read the instruction matches of all basic blocks of the function match
+    /// `function_id`
+    pub fn read_instruction_matches_for_function(
+        &self,
+        function_id: i64,
+    ) -> Result<Vec<Instruction>> {
+        let mut stmt = self
+            .connection
+            .prepare(
+                "SELECT instruction.* FROM instruction \
+                 JOIN basicblock ON instruction.basicblockid = basicblock.id \
+                 WHERE basicblock.functionid = ?1 ORDER BY instruction.address1",
+            )
+            .context("Failed to prepare instruction statement")?;
+
+        let matches = stmt
+            .query_map(params![function_id], Instruction::from_row)
+            .context("Failed to query instruction row")?
+            .collect::<Result<Vec<Instruction>, _>>()?;
+
+        Ok(matches)
+    }
+
+    pub fn write_file(&self, file: &File) -> Result<()> {
+        self.connection
+            .execute(
+                "INSERT INTO file VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)",
+                params![
+                    file.id,
+                    file.filename,
+                    file.exe_filename,
+                    file.hash,
+                    file.functions,
+                    file.lib_functions,
+                    file.calls,
+                    file.basic_blocks,
+                    file.lib_basic_blocks,
+                    file.edges,
+                    file.lib_edges,
+                    file.instructions,
+                    file.lib_instructions
+                ],
+            )
+            .context("Failed to insert file row")?;
+        Ok(())
+    }
+
+    pub fn write_metadata(&self, metadata: &Metadata) -> Result<()> {
+        self.connection
+            .execute(
+                "INSERT INTO metadata VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+                params![
+                    metadata.version,
+                    metadata.file1,
+                    metadata.file2,
+                    metadata.description,
+                    metadata.created,
+                    metadata.modified,
+                    metadata.similarity,
+                    metadata.confidence
+                ],
+            )
+            .context("Failed to insert metadata row")?;
+        Ok(())
+    }
+
+    pub fn write_function_matches(&self, matches: &[FunctionMatch]) -> Result<()> {
+        let tx = self.connection.unchecked_transaction()?;
+        {
+            let mut stmt = tx
+                .prepare(
+                    "INSERT INTO function VALUES \
+                     (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14)",
+                )
+                .context("Failed to prepare function statement")?;
+            for m in matches {
+                stmt.execute(params![
+                    m.id,
+                    m.address1,
+                    m.name1,
+                    m.address2,
+                    m.name2,
+                    m.similarity,
+                    m.confidence,
+                    m.flags,
+                    m.algorithm,
+                    m.evaluate,
+                    m.comment_supported,
+                    m.basic_blocks,
+                    m.edges,
+                    m.instructions
+                ])
+                .context("Failed to insert function row")?;
+            }
+        }
+        tx.commit().context("Failed to commit function matches")?;
+        Ok(())
+    }
+
+    pub fn write_basic_block_matches(&self, matches: &[BasicBlockMatch]) -> Result<()> {
+        let tx = self.connection.unchecked_transaction()?;
+        {
+            let mut stmt = tx
+                .prepare("INSERT INTO basicblock VALUES (?1, ?2, ?3, ?4, ?5, ?6)")
+                .context("Failed to prepare basic block statement")?;
+            for m in matches {
+                stmt.execute(params![
+                    m.id,
+                    m.function_id,
+                    m.address1,
+                    m.address2,
+                    m.algorithm,
+                    m.evaluate
+                ])
+                .context("Failed to insert basic block row")?;
+            }
+        }
+        tx.commit()
+            .context("Failed to commit basic block matches")?;
+        Ok(())
+    }
+
+    pub fn write_instruction_matches(&self, matches: &[Instruction]) -> Result<()> {
+        let tx = self.connection.unchecked_transaction()?;
+        {
+            let mut stmt = tx
+                .prepare("INSERT INTO instruction VALUES (?1, ?2, ?3)")
+                .context("Failed to prepare instruction statement")?;
+            for m in matches {
+                stmt.execute(params![m.id, m.address1, m.address2])
+                    .context("Failed to insert instruction row")?;
+            }
+        }
+        tx.commit()
+            .context("Failed to commit instruction matches")?;
+        Ok(())
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct BinExport {
     pub binexport: binexport::BinExport2,
+    instruction_addresses: Vec<u64>,
+    flow_graphs_by_address: HashMap<u64, usize>,
+    /// `(address, index)` of each flow graph's basic blocks, sorted by address
+    basic_blocks_by_flow_graph: Vec<Vec<(u64, usize)>>,
+    vertices_by_address: HashMap<u64, usize>,
+    strings_by_instruction: HashMap<usize, Vec<usize>>,
 }
 
 impl BinExport {
@@ -552,7 +1124,78 @@ impl BinExport {
         })?;
         let binexport = binexport::BinExport2::decode(&file[..])
             .context("Failed to decode BinExport protobuf")?;
-        Ok(Self { binexport })
+        Ok(Self::new(binexport))
+    }
+
+    /// Encode the BinExport2 message to `path`
+    pub fn write<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        std::fs::write(&path, self.binexport.encode_to_vec()).with_context(|| {
+            format!(
+                "Failed to write BinExport file: {}",
+                path.as_ref().display()
+            )
+        })
+    }
+
+    /// Wrap a decoded BinExport2 message, resolving instruction addresses and
+    /// indexing its flow graphs and call graph vertices by address
+    pub fn new(binexport: binexport::BinExport2) -> Self {
+        // Only instructions that do not flow from their predecessor carry an
+        // explicit address; the rest follow the previous instruction's bytes.
+        let mut instruction_addresses = Vec::with_capacity(binexport.instruction.len());
+        let mut next_address = 0u64;
+        for instruction in &binexport.instruction {
+            let address = instruction.address.unwrap_or(next_address);
+            instruction_addresses.push(address);
+            next_address = address.wrapping_add(instruction.raw_bytes().len() as u64);
+        }
+
+        let mut binexport = Self {
+            binexport,
+            instruction_addresses,
+            flow_graphs_by_address: HashMap::new(),
+            basic_blocks_by_flow_graph: Vec::new(),
+            vertices_by_address: HashMap::new(),
+            strings_by_instruction: HashMap::new(),
+        };
+
+        for (index, flow_graph) in binexport.binexport.flow_graph.iter().enumerate() {
+            let mut basic_blocks: Vec<(u64, usize)> = flow_graph
+                .basic_block_index
+                .iter()
+                .map(|&index| {
+                    let index = index as usize;
+                    let address = binexport.basic_block_address(index).unwrap_or_default();
+                    (address, index)
+                })
+                .collect();
+            basic_blocks.sort_unstable();
+            binexport.basic_blocks_by_flow_graph.push(basic_blocks);
+
+            // Without an entry block the flow graph has no address to be found by
+            let Some(entry) = flow_graph.entry_basic_block_index else {
+                continue;
+            };
+            if let Some(address) = binexport.basic_block_address(entry as usize) {
+                binexport.flow_graphs_by_address.insert(address, index);
+            }
+        }
+        if let Some(call_graph) = &binexport.binexport.call_graph {
+            for (index, vertex) in call_graph.vertex.iter().enumerate() {
+                binexport
+                    .vertices_by_address
+                    .insert(vertex.address(), index);
+            }
+        }
+        for reference in &binexport.binexport.string_reference {
+            binexport
+                .strings_by_instruction
+                .entry(reference.instruction_index() as usize)
+                .or_default()
+                .push(reference.string_table_index() as usize);
+        }
+
+        binexport
     }
 
     pub fn executable_name(&self) -> Result<String> {
@@ -567,13 +1210,109 @@ impl BinExport {
         Ok(executable_name)
     }
 
-    // TODO: Add more methods to handle the BinExport protobuf
+    /// The executable's hash as recorded by the exporter (usually SHA256)
+    pub fn executable_id(&self) -> Result<String> {
+        let executable_id = self
+            .binexport
+            .meta_information
+            .as_ref()
+            .context("No meta information available")?
+            .executable_id()
+            .to_string();
+        Ok(executable_id)
+    }
+
+    pub fn architecture_name(&self) -> Result<String> {
+        let architecture_name = self
+            .binexport
+            .meta_information
+            .as_ref()
+            .context("No meta information available")?
+            .architecture_name()
+            .to_string();
+        Ok(architecture_name)
+    }
+
+    /// Address of the instruction at `index` in the instruction table
+    pub fn instruction_address(&self, index: usize) -> Option<u64> {
+        self.instruction_addresses.get(index).copied()
+    }
+
+    /// Address of the basic block at `index`, i.e. of its first instruction
+    pub fn basic_block_address(&self, index: usize) -> Option<u64> {
+        let basic_block = self.binexport.basic_block.get(index)?;
+        let range = basic_block.instruction_index.first()?;
+        self.instruction_address(range.begin_index() as usize)
+    }
+
+    /// Look up the function whose entry point is `address`
+    pub fn function(&self, address: u64) -> Option<flowgraph::Function<'_>> {
+        let index = *self.flow_graphs_by_address.get(&address)?;
+        Some(flowgraph::Function::new(self, index))
+    }
+
+    /// Basic blocks of the flow graph at `index` as `(address, index)` pairs,
+    /// sorted by address
+    pub(crate) fn sorted_basic_blocks(&self, index: usize) -> &[(u64, usize)] {
+        &self.basic_blocks_by_flow_graph[index]
+    }
+
+    /// Iterate over all functions that have a flow graph with an entry block
+    pub fn functions(&self) -> impl Iterator<Item = flowgraph::Function<'_>> {
+        (0..self.binexport.flow_graph.len())
+            .filter(move |&index| {
+                self.binexport.flow_graph[index]
+                    .entry_basic_block_index
+                    .is_some()
+            })
+            .map(move |index| flowgraph::Function::new(self, index))
+    }
+
+    /// Look up the call graph vertex for the function at `address`
+    pub fn vertex(&self, address: u64) -> Option<&binexport::bin_export2::call_graph::Vertex> {
+        let index = *self.vertices_by_address.get(&address)?;
+        self.binexport.call_graph.as_ref()?.vertex.get(index)
+    }
+
+    /// Strings referenced by the instruction at `index`
+    pub fn string_references(&self, index: usize) -> impl Iterator<Item = &str> {
+        self.strings_by_instruction
+            .get(&index)
+            .into_iter()
+            .flatten()
+            .filter_map(|&string| self.binexport.string_table.get(string))
+            .map(String::as_str)
+    }
+
+    /// Name of the function at `address`, preferring the demangled name
+    pub fn function_name(&self, address: u64) -> Option<&str> {
+        let vertex = self.vertex(address)?;
+        match vertex.demangled_name() {
+            "" => vertex.mangled_name.as_deref(),
+            name => Some(name),
+        }
+    }
+}
+
+/// Whether `name` is a placeholder a disassembler generated for an unnamed
+/// function rather than a real symbol
+pub fn is_generated_name(name: &str) -> bool {
+    const PREFIXES: &[&str] = &[
+        "sub_",
+        "j_sub_",
+        "nullsub_",
+        "FUN_",
+        "fcn.",
+        "unknown_libname_",
+    ];
+    name.is_empty() || PREFIXES.iter().any(|prefix| name.starts_with(prefix))
 }
 
 // Example usage demonstration
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::testutil::function_match;
 
     #[test]
     fn test_database_operations() -> Result<()> {
@@ -625,6 +1364,111 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_query_function_matches() -> Result<()> {
+        let path =
+            std::env::temp_dir().join(format!("bindiff-rs-query-{}.BinDiff", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let db = BinDiff::create(&path)?;
+        let named = |id: i64, name1: &str, name2: &str, similarity, instructions| FunctionMatch {
+            id,
+            name1: name1.to_string(),
+            name2: name2.to_string(),
+            similarity,
+            algorithm: if id == 1 {
+                FunctionAlgorithm::NameHashMatching
+            } else {
+                FunctionAlgorithm::HashMatching
+            },
+            instructions,
+            ..function_match(0x1000 * id, 0x1000 * id + 0x100)
+        };
+        db.write_function_matches(&[
+            named(1, "_vm_map_copyin", "_vm_map_copyin", 0.7, 30),
+            named(2, "_vm_map_enter", "_vm_map_enter", 1.0, 20),
+            named(3, "_ipc_kmsg_send", "sub_3100", 0.5, 10),
+        ])?;
+
+        let addresses = |filter: FunctionFilter| -> Result<Vec<i64>> {
+            Ok(db
+                .query_function_matches(&filter)?
+                .iter()
+                .map(|m| m.address1)
+                .collect())
+        };
+        assert_eq!(addresses(FunctionFilter::default())?.len(), 3);
+        assert_eq!(
+            addresses(FunctionFilter {
+                changed: true,
+                sort: Some(FunctionSort::Similarity),
+                ..Default::default()
+            })?,
+            [0x3000, 0x1000]
+        );
+        assert_eq!(
+            addresses(FunctionFilter {
+                name: Some("_vm_map_*".to_string()),
+                sort: Some(FunctionSort::Instructions),
+                descending: true,
+                limit: Some(1),
+                ..Default::default()
+            })?,
+            [0x1000]
+        );
+        // Equal confidences fall back to id order
+        assert_eq!(
+            addresses(FunctionFilter {
+                sort: Some(FunctionSort::Confidence),
+                descending: true,
+                limit: Some(2),
+                ..Default::default()
+            })?,
+            [0x1000, 0x2000]
+        );
+        assert_eq!(
+            addresses(FunctionFilter {
+                renamed: true,
+                ..Default::default()
+            })?,
+            [0x3000]
+        );
+        assert_eq!(
+            addresses(FunctionFilter {
+                algorithm: Some(FunctionAlgorithm::HashMatching),
+                min_similarity: Some(0.6),
+                ..Default::default()
+            })?,
+            [0x2000]
+        );
+
+        let found = |query: &str| -> Result<Option<i64>> {
+            Ok(db.find_function_match(query)?.map(|m| m.address1))
+        };
+        assert_eq!(found("sub_3100")?, Some(0x3000));
+        assert_eq!(found("0x2000")?, Some(0x2000));
+        assert_eq!(found("0x1100")?, Some(0x1000));
+        assert_eq!(found("_vm_map_*")?, None);
+        assert_eq!(found("0xnope")?, None);
+        db.write_function_matches(&[named(4, "0x_init", "0x_init", 1.0, 5)])?;
+        assert_eq!(found("0x_init")?, Some(0x4000));
+
+        db.close()?;
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_open_missing() {
+        let path =
+            std::env::temp_dir().join(format!("bindiff-rs-missing-{}.BinDiff", std::process::id()));
+        let error = BinDiff::open(&path).err().unwrap();
+        assert_eq!(
+            error.to_string(),
+            format!("No such file: {}", path.display())
+        );
+        assert!(!path.exists());
+    }
+
     #[test]
     fn test_read_binexport() -> Result<()> {
         let test_file_path = "tests/kernel.release.t6020.BinExport";
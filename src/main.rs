@@ -1,66 +1,992 @@
-use bindiff_rs::BinDiff;
+use anyhow::{Context, Result};
+use bindiff_rs::check::{self, Thresholds};
+use bindiff_rs::comments::{self, CommentFormat};
+use bindiff_rs::compare;
+use bindiff_rs::config::{self, MatchingConfig};
+use bindiff_rs::html::{self, HtmlOptions};
+use bindiff_rs::markdown::{self, MarkdownOptions};
+use bindiff_rs::matching;
+use bindiff_rs::output::{OutputFormat, Record, RecordWriter};
+use bindiff_rs::session::DiffSession;
+use bindiff_rs::similarity;
+use bindiff_rs::stats;
+use bindiff_rs::symbols::{self, SymbolFormat};
+use bindiff_rs::timeline::Timeline;
+use bindiff_rs::triage;
+use bindiff_rs::unmatched;
+use bindiff_rs::{
+    BasicBlockMatch, BinDiff, BinExport, File, FunctionFilter, FunctionMatch, FunctionSort,
+    Instruction, Metadata,
+};
 use calm_io::*;
+use clap::parser::ValueSource;
+use clap::{value_parser, Arg, ArgAction, ArgMatches, Command};
 use scopeguard::guard;
-use serde_json;
-
-#[pipefail]
-fn main() -> std::io::Result<()> {
-    let args: Vec<String> = std::env::args().skip(1).collect();
-    let json_output = args.contains(&"--json".to_string());
-    let info_output = args.contains(&"--info".to_string());
-
-    // Get the non-flag argument as the path
-    let input_path = args
-        .iter()
-        .find(|&arg| arg != "--json" && arg != "--info")
-        .ok_or_else(|| {
-            std::io::Error::new(
-                std::io::ErrorKind::InvalidInput,
-                "Please provide a path to a BinDiff file",
-            )
-        })?;
+use serde::Serialize;
+use std::io::IsTerminal;
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+/// Exit status of `check` when a threshold is violated, apart from errors (1)
+/// and usage errors (2)
+const CHECK_FAILED: u8 = 3;
+
+fn path_arg(name: &'static str, value_name: &'static str, help: &'static str) -> Arg {
+    Arg::new(name)
+        .value_name(value_name)
+        .help(help)
+        .required(true)
+        .value_parser(value_parser!(PathBuf))
+}
+
+fn bindiff_arg() -> Arg {
+    path_arg("bindiff", "BINDIFF", "BinDiff result database")
+}
+
+/// The BinDiff database and both BinExport files it was created from
+fn session_args() -> [Arg; 3] {
+    [
+        bindiff_arg(),
+        path_arg("primary", "PRIMARY", "Primary BinExport file"),
+        path_arg("secondary", "SECONDARY", "Secondary BinExport file"),
+    ]
+}
+
+/// Optional `--primary` and `--secondary` BinExport files, given together
+fn binexport_options(primary_help: &'static str) -> [Arg; 2] {
+    [
+        Arg::new("primary")
+            .long("primary")
+            .value_name("BINEXPORT")
+            .requires("secondary")
+            .help(primary_help)
+            .value_parser(value_parser!(PathBuf)),
+        Arg::new("secondary")
+            .long("secondary")
+            .value_name("BINEXPORT")
+            .requires("primary")
+            .help("Secondary BinExport file")
+            .value_parser(value_parser!(PathBuf)),
+    ]
+}
+
+fn config_arg() -> Arg {
+    Arg::new("config")
+        .long("config")
+        .value_name("PATH")
+        .help("Matching config (.toml, .json or BinDiff .xml)")
+        .value_parser(value_parser!(PathBuf))
+}
+
+fn format_arg() -> Arg {
+    Arg::new("format")
+        .long("format")
+        .default_value("text")
+        .value_parser(["text", "json", "ndjson", "csv", "table"])
+        .conflicts_with("json")
+        .help("Output format, --json is short for --format json")
+}
+
+fn function_arg() -> Arg {
+    Arg::new("function")
+        .long("function")
+        .value_name("FUNCTION")
+        .help("Only the matches of this function, by name or 0x-prefixed address")
+}
+
+fn score_arg(name: &'static str, help: &'static str) -> Arg {
+    Arg::new(name)
+        .long(name)
+        .value_name("SCORE")
+        .help(help)
+        .value_parser(value_parser!(f64))
+}
+
+fn cli() -> Command {
+    Command::new("bindiff-rs")
+        .version(env!("CARGO_PKG_VERSION"))
+        .about("Read, produce and analyze BinDiff results")
+        .subcommand_required(true)
+        .arg_required_else_help(true)
+        .arg(
+            Arg::new("json")
+                .long("json")
+                .global(true)
+                .action(ArgAction::SetTrue)
+                .help("Print JSON instead of text"),
+        )
+        .subcommand(
+            Command::new("info")
+                .about("Show the file and metadata of a BinDiff database")
+                .arg(bindiff_arg()),
+        )
+        .subcommand(
+            Command::new("stats")
+                .about("Show score histograms, matches per step and match coverage")
+                .arg(bindiff_arg()),
+        )
+        .subcommand(
+            Command::new("functions")
+                .about("List the function matches")
+                .arg(bindiff_arg())
+                .arg(score_arg(
+                    "min-similarity",
+                    "Only matches with at least this similarity",
+                ))
+                .arg(score_arg(
+                    "max-similarity",
+                    "Only matches with at most this similarity",
+                ))
+                .arg(score_arg(
+                    "min-confidence",
+                    "Only matches with at least this confidence",
+                ))
+                .arg(score_arg(
+                    "max-confidence",
+                    "Only matches with at most this confidence",
+                ))
+                .arg(
+                    Arg::new("algorithm")
+                        .long("algorithm")
+                        .value_name("NAME")
+                        .help("Only matches found by this step, e.g. \"name hash matching\""),
+                )
+                .arg(
+                    Arg::new("name")
+                        .long("name")
+                        .value_name("PATTERN")
+                        .help("Only matches where either name matches this glob pattern"),
+                )
+                .arg(
+                    Arg::new("changed")
+                        .long("changed")
+                        .action(ArgAction::SetTrue)
+                        .help("Only matches with a similarity below 1.0"),
+                )
+                .arg(
+                    Arg::new("renamed")
+                        .long("renamed")
+                        .action(ArgAction::SetTrue)
+                        .help("Only matches whose names differ"),
+                )
+                .arg(
+                    Arg::new("sort")
+                        .long("sort")
+                        .value_parser(["similarity", "confidence", "instructions", "address"])
+                        .help("Order by this column, ascending"),
+                )
+                .arg(
+                    Arg::new("desc")
+                        .long("desc")
+                        .action(ArgAction::SetTrue)
+                        .requires("sort")
+                        .help("Sort descending"),
+                )
+                .arg(
+                    Arg::new("limit")
+                        .long("limit")
+                        .value_name("N")
+                        .value_parser(value_parser!(usize))
+                        .help("Print at most N matches"),
+                )
+                .arg(format_arg()),
+        )
+        .subcommand(
+            Command::new("blocks")
+                .about("List the basic block matches")
+                .arg(bindiff_arg())
+                .arg(function_arg())
+                .arg(format_arg()),
+        )
+        .subcommand(
+            Command::new("instructions")
+                .about("List the instruction matches")
+                .arg(bindiff_arg())
+                .arg(function_arg())
+                .arg(format_arg()),
+        )
+        .subcommand(
+            Command::new("export")
+                .about("Export all tables of a BinDiff database as one JSON document")
+                .arg(bindiff_arg())
+                .arg(
+                    Arg::new("output")
+                        .long("output")
+                        .short('o')
+                        .value_name("PATH")
+                        .help("Write to a file instead of stdout")
+                        .value_parser(value_parser!(PathBuf)),
+                ),
+        )
+        .subcommand(
+            Command::new("check")
+                .about("Fail when a diff exceeds thresholds, for CI pipelines")
+                .after_help(
+                    "Exits with status 3 when any threshold is violated, \
+                     use --json for a machine-readable summary",
+                )
+                .arg(bindiff_arg())
+                .args(binexport_options(
+                    "Primary BinExport file, to count unmatched functions exactly",
+                ))
+                .arg(score_arg(
+                    "min-similarity",
+                    "Fail if the overall similarity is below this",
+                ))
+                .arg(score_arg(
+                    "min-confidence",
+                    "Fail if the overall confidence is below this",
+                ))
+                .arg(
+                    Arg::new("max-changed")
+                        .long("max-changed")
+                        .value_name("N")
+                        .value_parser(value_parser!(usize))
+                        .help("Fail if more than N matches have a similarity below 1.0"),
+                )
+                .arg(
+                    Arg::new("max-unmatched")
+                        .long("max-unmatched")
+                        .value_name("N")
+                        .value_parser(value_parser!(usize))
+                        .help("Fail if more than N functions of both binaries are unmatched"),
+                )
+                .arg(
+                    Arg::new("deny")
+                        .long("deny")
+                        .value_name("NAMES")
+                        .action(ArgAction::Append)
+                        .value_delimiter(',')
+                        .help("Fail if any of these functions changed or is unmatched"),
+                )
+                .arg(
+                    Arg::new("allow")
+                        .long("allow")
+                        .value_name("NAMES")
+                        .action(ArgAction::Append)
+                        .value_delimiter(',')
+                        .help("Fail if any function but these changed"),
+                ),
+        )
+        .subcommand(
+            Command::new("report")
+                .about("Write a self-contained HTML or a Markdown report")
+                .arg(bindiff_arg())
+                .args(binexport_options(
+                    "Primary BinExport file, for unmatched functions and function pages",
+                ))
+                .arg(
+                    Arg::new("output")
+                        .long("output")
+                        .short('o')
+                        .value_name("PATH")
+                        .help("Write to a file instead of stdout")
+                        .value_parser(value_parser!(PathBuf)),
+                )
+                .arg(
+                    Arg::new("title")
+                        .long("title")
+                        .value_name("TITLE")
+                        .help("Report title, \"<primary> vs <secondary>\" by default"),
+                )
+                .arg(
+                    Arg::new("format")
+                        .long("format")
+                        .default_value("html")
+                        .value_parser(["html", "markdown", "md"])
+                        .help("Report format"),
+                )
+                .arg(
+                    Arg::new("max-functions")
+                        .long("max-functions")
+                        .value_name("N")
+                        .value_parser(value_parser!(usize))
+                        .help(
+                            "At most N changed functions, lowest similarity first \
+                             [default: 100 pages for HTML, 20 rows for Markdown]",
+                        ),
+                )
+                .arg(
+                    Arg::new("max-unmatched")
+                        .long("max-unmatched")
+                        .value_name("N")
+                        .value_parser(value_parser!(usize))
+                        .help(
+                            "At most N added and N removed functions, Markdown only [default: 20]",
+                        ),
+                )
+                .arg(
+                    Arg::new("max-name-length")
+                        .long("max-name-length")
+                        .value_name("N")
+                        .value_parser(value_parser!(usize))
+                        .help("Cut longer function names, Markdown only [default: 60]"),
+                ),
+        )
+        .subcommand(
+            Command::new("unmatched")
+                .about("List the functions BinDiff could not match")
+                .args(session_args()),
+        )
+        .subcommand(
+            Command::new("triage")
+                .about("Rank changed functions, most interesting first")
+                .args(session_args()),
+        )
+        .subcommand(
+            Command::new("diff")
+                .about("Show a side-by-side instruction diff of a matched function")
+                .args(session_args())
+                .arg(
+                    Arg::new("function")
+                        .value_name("FUNCTION")
+                        .required(true)
+                        .help("Function name or 0x-prefixed primary address"),
+                ),
+        )
+        .subcommand(
+            Command::new("rescore")
+                .about("Recompute similarity and confidence from the match counts")
+                .args(session_args())
+                .arg(config_arg()),
+        )
+        .subcommand(
+            Command::new("evaluate")
+                .about("Score the matches of symbolized builds against function names")
+                .args(session_args()),
+        )
+        .subcommand(
+            Command::new("comments")
+                .about("Port the primary comments to the matched secondary addresses")
+                .args(session_args())
+                .arg(
+                    Arg::new("output")
+                        .long("output")
+                        .short('o')
+                        .value_name("PATH")
+                        .help("Write the secondary BinExport with the ported comments")
+                        .value_parser(value_parser!(PathBuf)),
+                )
+                .arg(
+                    Arg::new("format")
+                        .long("format")
+                        .default_value("json")
+                        .value_parser(["json", "ida", "ghidra"])
+                        .conflicts_with("json")
+                        .help("Output format"),
+                ),
+        )
+        .subcommand(
+            Command::new("symbols")
+                .about("Port primary names to unnamed secondary functions")
+                .arg(bindiff_arg())
+                .arg(
+                    Arg::new("min-confidence")
+                        .long("min-confidence")
+                        .value_name("CONFIDENCE")
+                        .default_value("0.0")
+                        .value_parser(value_parser!(f64))
+                        .help("Skip matches below this confidence"),
+                )
+                .arg(
+                    Arg::new("format")
+                        .long("format")
+                        .default_value("json")
+                        .value_parser(["json", "csv", "ida", "ghidra", "r2"])
+                        .conflicts_with("json")
+                        .help("Output format"),
+                ),
+        )
+        .subcommand(
+            Command::new("compare")
+                .about("Compare two BinDiff results of the same binary pair")
+                .arg(path_arg(
+                    "baseline",
+                    "BASELINE",
+                    "Baseline BinDiff database",
+                ))
+                .arg(path_arg(
+                    "candidate",
+                    "CANDIDATE",
+                    "Candidate BinDiff database",
+                )),
+        )
+        .subcommand(
+            Command::new("timeline")
+                .about("Follow functions through diffs of consecutive versions")
+                .arg(
+                    Arg::new("bindiffs")
+                        .required(true)
+                        .num_args(1..)
+                        .value_name("BINDIFF")
+                        .help("BinDiff databases of consecutive versions, in order")
+                        .value_parser(value_parser!(PathBuf)),
+                )
+                .arg(
+                    Arg::new("function")
+                        .long("function")
+                        .value_name("NAME")
+                        .help("Only functions that carried this name in any version"),
+                ),
+        )
+        .subcommand(
+            Command::new("match")
+                .about("Match two BinExport files and write a BinDiff database")
+                .arg(path_arg("primary", "PRIMARY", "Primary BinExport file"))
+                .arg(path_arg(
+                    "secondary",
+                    "SECONDARY",
+                    "Secondary BinExport file",
+                ))
+                .arg(path_arg("output", "OUTPUT", "BinDiff database to create"))
+                .arg(config_arg())
+                .arg(
+                    Arg::new("force")
+                        .long("force")
+                        .short('f')
+                        .action(ArgAction::SetTrue)
+                        .help("Overwrite OUTPUT if it exists"),
+                ),
+        )
+        .subcommand(
+            Command::new("binexport")
+                .about("Inspect a BinExport file")
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("info")
+                        .about("Show the executable and its size")
+                        .arg(path_arg("binexport", "BINEXPORT", "BinExport file")),
+                )
+                .subcommand(
+                    Command::new("functions")
+                        .about("List the functions of the call graph")
+                        .arg(path_arg("binexport", "BINEXPORT", "BinExport file")),
+                ),
+        )
+}
+
+/// Print `value` as pretty JSON or with its `Display` impl
+fn print<T: Serialize + std::fmt::Display>(value: &T, json: bool) -> Result<()> {
+    if json {
+        stdoutln!("{}", serde_json::to_string_pretty(value)?)?;
+    } else {
+        stdout!("{}", value)?;
+    }
+    Ok(())
+}
+
+/// Print `values` as a pretty JSON array or one per line
+fn print_lines<T: Serialize + std::fmt::Display>(values: &[T], json: bool) -> Result<()> {
+    if json {
+        stdoutln!("{}", serde_json::to_string_pretty(values)?)?;
+    } else {
+        for value in values {
+            stdoutln!("{}", value)?;
+        }
+    }
+    Ok(())
+}
+
+/// The `--format` of `matches`, or `json_format` for `--json`. clap rejects
+/// both on the subcommand but not a `--json` given before it, so check again.
+fn output_format<T>(matches: &ArgMatches, json: bool, json_format: T) -> Result<T>
+where
+    T: std::str::FromStr<Err = anyhow::Error>,
+{
+    if !json {
+        return matches
+            .get_one::<String>("format")
+            .expect("default")
+            .parse();
+    }
+    if matches.value_source("format") == Some(ValueSource::CommandLine) {
+        anyhow::bail!("--json cannot be used with --format");
+    }
+    Ok(json_format)
+}
+
+/// Buffered stdout writer for the `--format` (or `--json`) of `matches`
+fn record_writer<T: Record>(
+    matches: &ArgMatches,
+    json: bool,
+) -> Result<RecordWriter<std::io::BufWriter<std::io::StdoutLock<'static>>, T>> {
+    let format = output_format(matches, json, OutputFormat::Json)?;
+    Ok(RecordWriter::new(
+        std::io::BufWriter::new(std::io::stdout().lock()),
+        format,
+    ))
+}
+
+fn path<'a>(matches: &'a ArgMatches, name: &str) -> &'a PathBuf {
+    matches.get_one::<PathBuf>(name).expect("required argument")
+}
 
-    let bd = BinDiff::open(&input_path)
-        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+fn open_session(matches: &ArgMatches) -> Result<DiffSession> {
+    DiffSession::open(
+        path(matches, "bindiff"),
+        path(matches, "primary"),
+        path(matches, "secondary"),
+    )
+}
+
+fn open_config(matches: &ArgMatches) -> Result<MatchingConfig> {
+    match matches.get_one::<PathBuf>("config") {
+        Some(path) => MatchingConfig::open(path),
+        None => Ok(MatchingConfig::default()),
+    }
+}
 
-    let bd = guard(bd, |bd| {
+/// Open a BinDiff database, closing it when the guard is dropped
+fn open_bindiff(matches: &ArgMatches) -> Result<impl std::ops::Deref<Target = BinDiff>> {
+    let bd = BinDiff::open(path(matches, "bindiff"))?;
+    Ok(guard(bd, |bd| {
         bd.close()
             .unwrap_or_else(|e| eprintln!("Error closing database: {}", e));
-    });
-
-    if info_output {
-        // Read file
-        let file = bd
-            .read_file()
-            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
-        if json_output {
-            stdoutln!("{}", serde_json::to_string_pretty(&file)?)?;
-        } else {
-            stdoutln!("{}", file)?;
-        }
-
-        // Read metadata
-        let metadata = bd
-            .read_metadata()
-            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
-        if json_output {
-            stdoutln!("{}", serde_json::to_string_pretty(&metadata)?)?;
-        } else {
-            stdoutln!("{}", metadata)?;
+    }))
+}
+
+/// The function match named by the `--function` option, if given
+fn find_function_match(bd: &BinDiff, matches: &ArgMatches) -> Result<Option<FunctionMatch>> {
+    matches
+        .get_one::<String>("function")
+        .map(|query| {
+            bd.find_function_match(query)?
+                .with_context(|| format!("No function match found for {}", query))
+        })
+        .transpose()
+}
+
+/// Every table of a BinDiff database
+#[derive(Serialize)]
+struct Export {
+    metadata: Metadata,
+    primary: File,
+    secondary: File,
+    function_matches: Vec<FunctionMatch>,
+    basic_block_matches: Vec<BasicBlockMatch>,
+    instruction_matches: Vec<Instruction>,
+}
+
+/// Executable and size of a BinExport file
+#[derive(Serialize)]
+struct BinExportInfo {
+    executable_name: String,
+    executable_id: String,
+    architecture: String,
+    functions: usize,
+    calls: usize,
+    basic_blocks: usize,
+    instructions: usize,
+}
+
+impl std::fmt::Display for BinExportInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "BINEXPORT:\n  \
+            executable_name: {}\n  \
+            executable_id:   {}\n  \
+            architecture:    {}\n  \
+            functions:       {}\n  \
+            calls:           {}\n  \
+            basic_blocks:    {}\n  \
+            instructions:    {}\n",
+            self.executable_name,
+            self.executable_id,
+            self.architecture,
+            self.functions,
+            self.calls,
+            self.basic_blocks,
+            self.instructions
+        )
+    }
+}
+
+fn run_binexport(matches: &ArgMatches, json: bool) -> Result<()> {
+    match matches.subcommand() {
+        Some(("info", matches)) => {
+            let binexport = BinExport::open(path(matches, "binexport"))?;
+            let call_graph = binexport.binexport.call_graph.as_ref();
+            let info = BinExportInfo {
+                executable_name: binexport.executable_name()?,
+                executable_id: binexport.executable_id()?,
+                architecture: binexport.architecture_name()?,
+                functions: call_graph.map_or(0, |c| c.vertex.len()),
+                calls: call_graph.map_or(0, |c| c.edge.len()),
+                basic_blocks: binexport.binexport.basic_block.len(),
+                instructions: binexport.binexport.instruction.len(),
+            };
+            print(&info, json)
         }
-    } else {
-        // Read function matches
-        let func_matches = bd
-            .read_function_matches()
-            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
-        if json_output {
-            stdoutln!("{}", serde_json::to_string_pretty(&func_matches)?)?;
-        } else {
-            for func_match in func_matches {
-                stdoutln!("{}", func_match)?;
-            }
+        Some(("functions", matches)) => {
+            let binexport = BinExport::open(path(matches, "binexport"))?;
+            let functions = unmatched::functions(&binexport);
+            print_lines(&functions, json)
         }
+        _ => unreachable!("subcommand_required"),
     }
+}
 
-    Ok(())
+fn run(matches: &ArgMatches) -> Result<ExitCode> {
+    let json = matches.get_flag("json");
+    let (name, matches) = matches.subcommand().expect("subcommand_required");
+    match name {
+        "info" => {
+            let bd = open_bindiff(matches)?;
+            let file = bd.read_file()?;
+            let metadata = bd.read_metadata()?;
+            if json {
+                stdoutln!("{}", serde_json::to_string_pretty(&file)?)?;
+                stdoutln!("{}", serde_json::to_string_pretty(&metadata)?)?;
+            } else {
+                stdoutln!("{}", file)?;
+                stdoutln!("{}", metadata)?;
+            }
+            Ok(())
+        }
+        "stats" => print(&stats::stats(&*open_bindiff(matches)?)?, json),
+        "functions" => {
+            let algorithm = matches
+                .get_one::<String>("algorithm")
+                .map(|name| {
+                    config::function_algorithm(name)
+                        .with_context(|| format!("Unknown function matching step: {}", name))
+                })
+                .transpose()?;
+            let filter = FunctionFilter {
+                min_similarity: matches.get_one::<f64>("min-similarity").copied(),
+                max_similarity: matches.get_one::<f64>("max-similarity").copied(),
+                min_confidence: matches.get_one::<f64>("min-confidence").copied(),
+                max_confidence: matches.get_one::<f64>("max-confidence").copied(),
+                algorithm,
+                name: matches.get_one::<String>("name").cloned(),
+                changed: matches.get_flag("changed"),
+                renamed: matches.get_flag("renamed"),
+                sort: matches
+                    .get_one::<String>("sort")
+                    .map(|sort| sort.parse::<FunctionSort>())
+                    .transpose()?,
+                descending: matches.get_flag("desc"),
+                limit: matches.get_one::<usize>("limit").copied(),
+            };
+            let mut writer = record_writer(matches, json)?;
+            open_bindiff(matches)?
+                .for_each_function_match(&filter, |function_match| writer.write(&function_match))?;
+            writer.finish()?;
+            Ok(())
+        }
+        "blocks" => {
+            let bd = open_bindiff(matches)?;
+            let mut writer = record_writer(matches, json)?;
+            match find_function_match(&bd, matches)? {
+                Some(function_match) => {
+                    for basic_block_match in
+                        bd.read_basic_block_matches_for_function(function_match.id)?
+                    {
+                        writer.write(&basic_block_match)?;
+                    }
+                }
+                None => bd.for_each_basic_block_match(|basic_block_match| {
+                    writer.write(&basic_block_match)
+                })?,
+            }
+            writer.finish()?;
+            Ok(())
+        }
+        "instructions" => {
+            let bd = open_bindiff(matches)?;
+            let mut writer = record_writer(matches, json)?;
+            match find_function_match(&bd, matches)? {
+                Some(function_match) => {
+                    for instruction in
+                        bd.read_instruction_matches_for_function(function_match.id)?
+                    {
+                        writer.write(&instruction)?;
+                    }
+                }
+                None => bd.for_each_instruction_match(|instruction| writer.write(&instruction))?,
+            }
+            writer.finish()?;
+            Ok(())
+        }
+        "export" => {
+            let bd = open_bindiff(matches)?;
+            let metadata = bd.read_metadata()?;
+            let export = Export {
+                primary: bd.read_file_by_id(metadata.file1)?,
+                secondary: bd.read_file_by_id(metadata.file2)?,
+                metadata,
+                function_matches: bd.read_function_matches()?,
+                basic_block_matches: bd.read_basic_block_matches()?,
+                instruction_matches: bd.read_instruction_matches()?,
+            };
+            let text = serde_json::to_string_pretty(&export)?;
+            match matches.get_one::<PathBuf>("output") {
+                Some(output) => std::fs::write(output, text)
+                    .with_context(|| format!("Failed to write {}", output.display()))?,
+                None => stdoutln!("{}", text)?,
+            }
+            Ok(())
+        }
+        "check" => {
+            let names = |name: &str| -> Vec<String> {
+                matches
+                    .get_many::<String>(name)
+                    .map(|names| names.cloned().collect())
+                    .unwrap_or_default()
+            };
+            let thresholds = Thresholds {
+                min_similarity: matches.get_one::<f64>("min-similarity").copied(),
+                min_confidence: matches.get_one::<f64>("min-confidence").copied(),
+                max_changed: matches.get_one::<usize>("max-changed").copied(),
+                max_unmatched: matches.get_one::<usize>("max-unmatched").copied(),
+                deny: names("deny"),
+                allow: names("allow"),
+            };
+            let report = match matches.get_one::<PathBuf>("primary") {
+                Some(primary) => check::check_session(
+                    &DiffSession::open(
+                        path(matches, "bindiff"),
+                        primary,
+                        path(matches, "secondary"),
+                    )?,
+                    &thresholds,
+                )?,
+                None => check::check(&*open_bindiff(matches)?, &thresholds)?,
+            };
+            print(&report, json)?;
+            if !report.passed {
+                return Ok(ExitCode::from(CHECK_FAILED));
+            }
+            Ok(())
+        }
+        "report" => {
+            let title = matches.get_one::<String>("title").cloned();
+            let limit = |name: &str| matches.get_one::<usize>(name).copied();
+            let markdown = matches.get_one::<String>("format").expect("default") != "html";
+            let render = |bindiff: &BinDiff, binexports| -> Result<String> {
+                if markdown {
+                    let defaults = MarkdownOptions::default();
+                    let options = MarkdownOptions {
+                        title: title.clone(),
+                        max_changed: limit("max-functions").unwrap_or(defaults.max_changed),
+                        max_unmatched: limit("max-unmatched").unwrap_or(defaults.max_unmatched),
+                        max_name_length: limit("max-name-length")
+                            .unwrap_or(defaults.max_name_length),
+                    };
+                    markdown::markdown_report(bindiff, binexports, &options)
+                } else {
+                    let options = HtmlOptions {
+                        title: title.clone(),
+                        max_functions: limit("max-functions")
+                            .unwrap_or(HtmlOptions::default().max_functions),
+                    };
+                    html::html_report(bindiff, binexports, &options)
+                }
+            };
+            let report = match matches.get_one::<PathBuf>("primary") {
+                Some(primary) => {
+                    let session = DiffSession::open(
+                        path(matches, "bindiff"),
+                        primary,
+                        path(matches, "secondary"),
+                    )?;
+                    render(
+                        session.bindiff(),
+                        Some((session.primary(), session.secondary())),
+                    )?
+                }
+                None => render(&*open_bindiff(matches)?, None)?,
+            };
+            match matches.get_one::<PathBuf>("output") {
+                Some(output) => std::fs::write(output, report)
+                    .with_context(|| format!("Failed to write {}", output.display()))?,
+                None => stdout!("{}", report)?,
+            }
+            Ok(())
+        }
+        "unmatched" => print(&open_session(matches)?.unmatched_functions()?, json),
+        "triage" => {
+            let session = open_session(matches)?;
+            let unresolved = session.function_matches()?.unresolved.len();
+            if unresolved > 0 {
+                eprintln!(
+                    "Warning: skipped {} function matches not found in the BinExport files",
+                    unresolved
+                );
+            }
+            print_lines(&triage::rank(&session)?, json)
+        }
+        "diff" => {
+            let session = open_session(matches)?;
+            let query = matches.get_one::<String>("function").expect("required");
+            let matched = session
+                .find_function_match(query)?
+                .with_context(|| format!("No function match found for {}", query))?;
+            let diff = matched.instruction_diff()?;
+            if json {
+                stdoutln!("{}", serde_json::to_string_pretty(&diff)?)?;
+            } else {
+                stdout!("{}", diff.render(std::io::stdout().is_terminal()))?;
+            }
+            Ok(())
+        }
+        "rescore" => {
+            let config = open_config(matches)?;
+            print(
+                &similarity::rescore(&open_session(matches)?, &config)?,
+                json,
+            )
+        }
+        "evaluate" => print(&open_session(matches)?.evaluate()?, json),
+        "comments" => {
+            let session = open_session(matches)?;
+            let ported = session.port_comments()?;
+            if let Some(output) = matches.get_one::<PathBuf>("output") {
+                let mut binexport = session.secondary().binexport.clone();
+                comments::apply_comments(session.secondary(), &mut binexport, &ported);
+                BinExport::new(binexport).write(output)?;
+            }
+            let format = output_format(matches, json, CommentFormat::Json)?;
+            stdout!("{}", comments::render_comments(&ported, format)?)?;
+            Ok(())
+        }
+        "symbols" => {
+            let function_matches = open_bindiff(matches)?.read_function_matches()?;
+            let min_confidence = *matches.get_one::<f64>("min-confidence").expect("default");
+            let format = output_format(matches, json, SymbolFormat::Json)?;
+            let symbols = symbols::port_symbols(&function_matches, min_confidence);
+            stdout!("{}", symbols::render_symbols(&symbols, format)?)?;
+            Ok(())
+        }
+        "compare" => {
+            let baseline = BinDiff::open(path(matches, "baseline"))?;
+            let candidate = BinDiff::open(path(matches, "candidate"))?;
+            print(&compare::compare(&baseline, &candidate)?, json)
+        }
+        "timeline" => {
+            let paths: Vec<&PathBuf> = matches
+                .get_many::<PathBuf>("bindiffs")
+                .expect("required")
+                .collect();
+            let timeline = Timeline::open(&paths)?;
+            let functions: Vec<_> = match matches.get_one::<String>("function") {
+                Some(name) => timeline.find_by_name(name).collect(),
+                None => timeline.functions.iter().collect(),
+            };
+            if json {
+                stdoutln!("{}", serde_json::to_string_pretty(&functions)?)?;
+            } else {
+                for function in functions {
+                    stdout!("{}", function)?;
+                }
+            }
+            Ok(())
+        }
+        "match" => {
+            let output = path(matches, "output");
+            let force = matches.get_flag("force");
+            if output.exists() && !force {
+                anyhow::bail!("{} exists, pass --force to overwrite", output.display());
+            }
+            let primary = BinExport::open(path(matches, "primary"))?;
+            let secondary = BinExport::open(path(matches, "secondary"))?;
+            let config = open_config(matches)?;
+            let result = matching::diff_with_config(&primary, &secondary, &config)?;
+            // Only drop the old output once there is a result to replace it
+            if output.exists() && force {
+                std::fs::remove_file(output)
+                    .with_context(|| format!("Failed to remove {}", output.display()))?;
+            }
+            result.write(output)?;
+            if json {
+                stdoutln!("{}", serde_json::to_string_pretty(&result.metadata)?)?;
+            } else {
+                stdoutln!("{}", result.metadata)?;
+            }
+            Ok(())
+        }
+        "binexport" => run_binexport(matches, json),
+        _ => unreachable!("subcommand_required"),
+    }?;
+    Ok(ExitCode::SUCCESS)
+}
+
+fn main() -> ExitCode {
+    let matches = cli().get_matches();
+    run(&matches).unwrap_or_else(|error| {
+        // Let a closed stdout pipe end the program quietly
+        let broken_pipe = error.chain().any(|cause| {
+            cause
+                .downcast_ref::<std::io::Error>()
+                .is_some_and(|cause| cause.kind() == std::io::ErrorKind::BrokenPipe)
+        });
+        if broken_pipe {
+            return ExitCode::SUCCESS;
+        }
+        eprintln!("Error: {:#}", error);
+        ExitCode::FAILURE
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cli() {
+        cli().debug_assert();
+        assert!(cli()
+            .try_get_matches_from(["bindiff-rs", "info", "a.BinDiff", "typo"])
+            .is_err());
+        let matches = cli()
+            .try_get_matches_from(["bindiff-rs", "functions", "a.BinDiff", "--json"])
+            .unwrap();
+        assert!(matches.get_flag("json"));
+        for args in [
+            [
+                "bindiff-rs",
+                "functions",
+                "a.BinDiff",
+                "--json",
+                "--format",
+                "csv",
+            ],
+            [
+                "bindiff-rs",
+                "symbols",
+                "a.BinDiff",
+                "--format",
+                "ida",
+                "--json",
+            ],
+        ] {
+            assert!(cli().try_get_matches_from(args).is_err());
+        }
+        let matches = cli()
+            .try_get_matches_from([
+                "bindiff-rs",
+                "--json",
+                "functions",
+                "a.BinDiff",
+                "--format",
+                "csv",
+            ])
+            .unwrap();
+        let (_, functions) = matches.subcommand().unwrap();
+        assert!(output_format(functions, true, OutputFormat::Json).is_err());
+        let matches = cli()
+            .try_get_matches_from(["bindiff-rs", "functions", "a.BinDiff"])
+            .unwrap();
+        let (_, functions) = matches.subcommand().unwrap();
+        assert!(output_format(functions, true, OutputFormat::Json).is_ok());
+        let matches = cli()
+            .try_get_matches_from(["bindiff-rs", "blocks", "a.BinDiff", "--function", "0x1000"])
+            .unwrap();
+        let (_, blocks) = matches.subcommand().unwrap();
+        assert_eq!(
+            blocks.get_one::<String>("function").map(String::as_str),
+            Some("0x1000")
+        );
+    }
 }
@@ -0,0 +1,299 @@
+//! Markdown summary of a BinDiff result, short enough to paste into issues
+//! and pull requests: the compared files, the most changed functions, added
+//! and removed functions and matches per matching step.
+
+use crate::stats;
+use crate::unmatched::{self, UnmatchedFunction};
+use crate::{BinDiff, BinExport, File, FunctionMatch};
+use anyhow::Result;
+use std::fmt::Write;
+
+/// Options of a Markdown report
+#[derive(Debug, Clone)]
+pub struct MarkdownOptions {
+    /// Heading, "<primary> vs <secondary>" by default
+    pub title: Option<String>,
+    /// Most changed functions to list, lowest similarity first
+    pub max_changed: usize,
+    /// Most added and most removed functions to list
+    pub max_unmatched: usize,
+    /// Longer function names are cut off with an ellipsis
+    pub max_name_length: usize,
+}
+
+impl Default for MarkdownOptions {
+    fn default() -> Self {
+        Self {
+            title: None,
+            max_changed: 20,
+            max_unmatched: 20,
+            max_name_length: 60,
+        }
+    }
+}
+
+/// Generate the Markdown report of a BinDiff database, with added and removed
+/// functions if the `(primary, secondary)` BinExport files are given
+pub fn markdown_report(
+    bindiff: &BinDiff,
+    binexports: Option<(&BinExport, &BinExport)>,
+    options: &MarkdownOptions,
+) -> Result<String> {
+    let metadata = bindiff.read_metadata()?;
+    let primary = bindiff.read_file_by_id(metadata.file1)?;
+    let secondary = bindiff.read_file_by_id(metadata.file2)?;
+    let stats = stats::stats(bindiff)?;
+    let matches = bindiff.read_function_matches()?;
+    let name = |name: &str| escape(&truncate(name, options.max_name_length));
+
+    let mut out = String::new();
+    let title = options
+        .title
+        .clone()
+        .unwrap_or_else(|| format!("{} vs {}", primary.filename, secondary.filename));
+    writeln!(out, "# {}\n", escape(&title)).unwrap();
+    writeln!(
+        out,
+        "**Similarity** {:.3} · **Confidence** {:.3} · {} · {}\n",
+        metadata.similarity,
+        metadata.confidence,
+        escape(&metadata.version),
+        metadata.created.format("%Y-%m-%d %H:%M:%S")
+    )
+    .unwrap();
+    write_files(&mut out, &primary, &secondary);
+
+    let mut changed: Vec<&FunctionMatch> = matches.iter().filter(|m| m.similarity < 1.0).collect();
+    changed.sort_by(|a, b| {
+        a.similarity
+            .total_cmp(&b.similarity)
+            .then(a.address1.cmp(&b.address1))
+    });
+    writeln!(
+        out,
+        "## Changed functions ({} of {})\n",
+        changed.len(),
+        matches.len()
+    )
+    .unwrap();
+    if !changed.is_empty() {
+        writeln!(
+            out,
+            "| Primary | Secondary | Similarity | Confidence | Algorithm |\n\
+             | --- | --- | ---: | ---: | --- |"
+        )
+        .unwrap();
+        for m in changed.iter().take(options.max_changed) {
+            writeln!(
+                out,
+                "| `{:#x}` {} | `{:#x}` {} | {:.3} | {:.3} | {} |",
+                m.address1 as u64,
+                name(&m.name1),
+                m.address2 as u64,
+                name(&m.name2),
+                m.similarity,
+                m.confidence,
+                m.algorithm
+            )
+            .unwrap();
+        }
+        write_more(&mut out, changed.len(), options.max_changed);
+        writeln!(out).unwrap();
+    }
+
+    if let Some((primary, secondary)) = binexports {
+        let unmatched = unmatched::unmatched_functions(primary, secondary, &matches);
+        let sections = [
+            ("Removed functions", &unmatched.primary),
+            ("Added functions", &unmatched.secondary),
+        ];
+        for (heading, functions) in sections {
+            write_unmatched(&mut out, heading, functions, options, &name);
+        }
+    }
+
+    writeln!(
+        out,
+        "## Matching steps\n\n| Step | Matches |\n| --- | ---: |"
+    )
+    .unwrap();
+    for algorithm in &stats.function_algorithms {
+        writeln!(out, "| {} | {} |", algorithm.algorithm, algorithm.count).unwrap();
+    }
+    Ok(out)
+}
+
+fn write_files(out: &mut String, primary: &File, secondary: &File) {
+    writeln!(
+        out,
+        "| | Primary | Secondary |\n| --- | --- | --- |\n| File | {} | {} |\n\
+         | Hash | {} | {} |\n| Functions | {} | {} |\n| Basic blocks | {} | {} |\n\
+         | Instructions | {} | {} |\n",
+        escape(&primary.filename),
+        escape(&secondary.filename),
+        code(&primary.hash),
+        code(&secondary.hash),
+        primary.functions,
+        secondary.functions,
+        primary.basic_blocks,
+        secondary.basic_blocks,
+        primary.instructions,
+        secondary.instructions
+    )
+    .unwrap();
+}
+
+fn write_unmatched(
+    out: &mut String,
+    heading: &str,
+    functions: &[UnmatchedFunction],
+    options: &MarkdownOptions,
+    name: &dyn Fn(&str) -> String,
+) {
+    writeln!(out, "## {} ({})\n", heading, functions.len()).unwrap();
+    if functions.is_empty() {
+        return;
+    }
+    // Largest functions first, they are the most likely to matter
+    let mut functions: Vec<&UnmatchedFunction> = functions.iter().collect();
+    functions.sort_by_key(|f| (std::cmp::Reverse(f.instructions), f.address));
+    writeln!(
+        out,
+        "| Address | Name | Basic blocks | Instructions |\n| --- | --- | ---: | ---: |"
+    )
+    .unwrap();
+    for function in functions.iter().take(options.max_unmatched) {
+        writeln!(
+            out,
+            "| `{:#x}` | {} | {} | {} |",
+            function.address,
+            name(&function.name),
+            function.basic_blocks,
+            function.instructions
+        )
+        .unwrap();
+    }
+    write_more(out, functions.len(), options.max_unmatched);
+    writeln!(out).unwrap();
+}
+
+fn write_more(out: &mut String, total: usize, limit: usize) {
+    if total > limit {
+        writeln!(out, "\n_… and {} more_", total - limit).unwrap();
+    }
+}
+
+/// Wrap `text` in a code span, nothing for empty text
+fn code(text: &str) -> String {
+    if text.is_empty() {
+        String::new()
+    } else {
+        format!("`{}`", text)
+    }
+}
+
+/// Cut `text` to at most `length` characters, ending in an ellipsis
+fn truncate(text: &str, length: usize) -> String {
+    if text.chars().count() <= length {
+        return text.to_string();
+    }
+    let mut text: String = text.chars().take(length.saturating_sub(1)).collect();
+    text.push('…');
+    text
+}
+
+/// Escape characters that break table cells or start Markdown formatting
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if matches!(c, '|' | '\\' | '`' | '*' | '_' | '<' | '>' | '[' | ']') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::{binexport, function_match, FunctionSpec};
+    use crate::Metadata;
+
+    #[test]
+    fn test_markdown_report() -> Result<()> {
+        let path =
+            std::env::temp_dir().join(format!("bindiff-rs-md-{}.BinDiff", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let db = BinDiff::create(&path)?;
+        let file = |id, filename: &str| File {
+            id,
+            filename: filename.to_string(),
+            exe_filename: String::new(),
+            hash: "0123456789abcdef".to_string(),
+            functions: 4,
+            lib_functions: 0,
+            calls: 0,
+            basic_blocks: 4,
+            lib_basic_blocks: 0,
+            edges: 0,
+            lib_edges: 0,
+            instructions: 4,
+            lib_instructions: 0,
+        };
+        db.write_file(&file(1, "kernel_1"))?;
+        db.write_file(&file(2, "kernel_2"))?;
+        db.write_metadata(&Metadata {
+            version: "BinDiff 8".to_string(),
+            file1: 1,
+            file2: 2,
+            description: String::new(),
+            created: chrono::Utc::now(),
+            modified: chrono::Utc::now(),
+            similarity: 0.8,
+            confidence: 0.9,
+        })?;
+        let named = |id: i64, name: &str, similarity| FunctionMatch {
+            id,
+            name1: name.to_string(),
+            name2: name.to_string(),
+            similarity,
+            ..function_match(0x1000 * id, 0x1000 * id + 0x100)
+        };
+        db.write_function_matches(&[
+            named(1, "_vm_map_copyin", 0.7),
+            named(2, "oper|ator_new", 0.5),
+            named(3, "_bzero", 1.0),
+        ])?;
+
+        let primary = binexport(&[FunctionSpec::new(0x5000, "removed", vec![vec!["ret"]])]);
+        let secondary = binexport(&[
+            FunctionSpec::new(0x6000, "added_small", vec![vec!["ret"]]),
+            FunctionSpec::new(0x7000, "added_large", vec![vec!["nop", "ret"]]),
+        ]);
+        let options = MarkdownOptions {
+            max_changed: 1,
+            max_unmatched: 1,
+            max_name_length: 8,
+            ..Default::default()
+        };
+        let markdown = markdown_report(&db, Some((&primary, &secondary)), &options)?;
+
+        assert!(markdown.starts_with("# kernel\\_1 vs kernel\\_2\n"));
+        assert!(markdown.contains("## Changed functions (2 of 3)"));
+        assert!(markdown.contains("| `0x2000` oper\\|at… | `0x2100` oper\\|at… | 0.500 |"));
+        assert!(!markdown.contains("`0x1000`"));
+        assert!(markdown.contains("_… and 1 more_"));
+        assert!(markdown.contains("## Removed functions (1)"));
+        assert!(markdown.contains("## Added functions (2)\n\n| Address"));
+        assert!(markdown.contains("| `0x7000` | added\\_l… | 1 | 2 |"));
+        assert!(markdown.contains("| name hash matching | 3 |"));
+
+        let summary = markdown_report(&db, None, &MarkdownOptions::default())?;
+        assert!(!summary.contains("## Added functions"));
+
+        db.close()?;
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+}
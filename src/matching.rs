@@ -0,0 +1,868 @@
+//! Native function matching between two BinExport files, using the algorithm
+//! families BinDiff names in `FunctionAlgorithm`.
+
+use crate::binexport::bin_export2::call_graph::vertex;
+use crate::config::MatchingConfig;
+use crate::md_index::{self, Direction};
+use crate::prime::prime_product;
+use crate::similarity::{self, confidence_sigmoid};
+use crate::{
+    is_generated_name, BasicBlockMatch, BinDiff, BinExport, File, FunctionAlgorithm, FunctionMatch,
+    Instruction, Metadata,
+};
+use anyhow::Result;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::hash::{Hash, Hasher};
+use std::path::Path;
+
+/// Upper bound on passes over the matching steps; later passes only find
+/// matches that became unique or reachable through earlier ones
+const MAX_ROUNDS: usize = 4;
+
+/// Function matching steps in the order BinDiff runs them by default
+pub fn default_function_steps() -> Vec<FunctionAlgorithm> {
+    vec![
+        FunctionAlgorithm::NameHashMatching,
+        FunctionAlgorithm::HashMatching,
+        FunctionAlgorithm::EdgesFlowgraphMdIndex,
+        FunctionAlgorithm::EdgesCallgraphMdIndex,
+        FunctionAlgorithm::MdIndexMatchingFlowgraphTopDown,
+        FunctionAlgorithm::MdIndexMatchingFlowgraphBottomUp,
+        FunctionAlgorithm::PrimeSignatureMatching,
+        FunctionAlgorithm::MdIndexMatchingCallGraphTopDown,
+        FunctionAlgorithm::MdIndexMatchingCallGraphBottomUp,
+        FunctionAlgorithm::RelaxedMdIndexMatching,
+        FunctionAlgorithm::InstructionCount,
+        FunctionAlgorithm::AddressSequence,
+        FunctionAlgorithm::StringReferences,
+        FunctionAlgorithm::LoopCountMatching,
+        FunctionAlgorithm::CallSequenceMatchingExact,
+        FunctionAlgorithm::CallSequenceMatchingTopology,
+        FunctionAlgorithm::CallSequenceMatchingSequence,
+        FunctionAlgorithm::CallReferenceMatching,
+    ]
+}
+
+/// Confidence BinDiff's default configuration assigns to a function matching step
+pub fn default_function_confidence(algorithm: &FunctionAlgorithm) -> f64 {
+    match algorithm {
+        FunctionAlgorithm::NameHashMatching
+        | FunctionAlgorithm::HashMatching
+        | FunctionAlgorithm::EdgesFlowgraphMdIndex
+        | FunctionAlgorithm::Manual => 1.0,
+        FunctionAlgorithm::EdgesCallgraphMdIndex
+        | FunctionAlgorithm::MdIndexMatchingFlowgraphTopDown
+        | FunctionAlgorithm::MdIndexMatchingFlowgraphBottomUp
+        | FunctionAlgorithm::PrimeSignatureMatching => 0.9,
+        FunctionAlgorithm::MdIndexMatchingCallGraphTopDown
+        | FunctionAlgorithm::MdIndexMatchingCallGraphBottomUp => 0.8,
+        FunctionAlgorithm::RelaxedMdIndexMatching | FunctionAlgorithm::StringReferences => 0.7,
+        FunctionAlgorithm::InstructionCount
+        | FunctionAlgorithm::AddressSequence
+        | FunctionAlgorithm::LoopCountMatching
+        | FunctionAlgorithm::CallReferenceMatching => 0.4,
+        FunctionAlgorithm::CallSequenceMatchingExact
+        | FunctionAlgorithm::CallSequenceMatchingTopology
+        | FunctionAlgorithm::CallSequenceMatchingSequence
+        | FunctionAlgorithm::None
+        | FunctionAlgorithm::Other(_) => 0.0,
+    }
+}
+
+/// Matches the functions of two BinExport files by running a sequence of
+/// matching steps, each only considering functions left unmatched by the
+/// steps before it
+#[derive(Debug, Clone)]
+pub struct FunctionMatcher {
+    steps: Vec<FunctionAlgorithm>,
+    confidences: HashMap<FunctionAlgorithm, f64>,
+}
+
+impl Default for FunctionMatcher {
+    fn default() -> Self {
+        Self::with_steps(default_function_steps())
+    }
+}
+
+impl FunctionMatcher {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Run `steps` in the given order instead of the default ones
+    pub fn with_steps(steps: Vec<FunctionAlgorithm>) -> Self {
+        Self {
+            steps,
+            confidences: HashMap::new(),
+        }
+    }
+
+    /// Override the confidence of matches found by `algorithm`
+    pub fn with_confidence(mut self, algorithm: FunctionAlgorithm, confidence: f64) -> Self {
+        self.confidences.insert(algorithm, confidence);
+        self
+    }
+
+    pub fn steps(&self) -> &[FunctionAlgorithm] {
+        &self.steps
+    }
+
+    /// Confidence of matches found by `algorithm`, before the sigmoid
+    pub fn confidence(&self, algorithm: &FunctionAlgorithm) -> f64 {
+        self.confidences
+            .get(algorithm)
+            .copied()
+            .unwrap_or_else(|| default_function_confidence(algorithm))
+    }
+
+    /// Match the functions of `primary` and `secondary`, returning one
+    /// `FunctionMatch` per pair sorted by primary address
+    pub fn match_functions(
+        &self,
+        primary: &BinExport,
+        secondary: &BinExport,
+    ) -> Vec<FunctionMatch> {
+        let mut state = State::new(Side::new(primary), Side::new(secondary));
+        for _ in 0..MAX_ROUNDS {
+            let before = state.pairs.len();
+            for step in &self.steps {
+                state.run(step);
+            }
+            if state.pairs.len() == before {
+                break;
+            }
+        }
+        state.into_matches(|algorithm| self.confidence(algorithm))
+    }
+}
+
+/// Per-function features the matching steps key on
+struct Features {
+    address: u64,
+    /// Real (not auto-generated) name
+    name: Option<String>,
+    display_name: String,
+    hash: u64,
+    prime: u64,
+    basic_blocks: usize,
+    edges: usize,
+    instructions: usize,
+    loops: usize,
+    md_top_down: f64,
+    md_bottom_up: f64,
+    call_graph_md_top_down: f64,
+    call_graph_md_bottom_up: f64,
+    strings: Option<u64>,
+    /// Called functions (indices into `Side::functions`) in call order
+    callees: Vec<usize>,
+    callers: Vec<usize>,
+}
+
+/// The functions of one BinExport, sorted by address
+struct Side {
+    functions: Vec<Features>,
+    call_edges: Vec<(usize, usize)>,
+}
+
+impl Side {
+    fn new(binexport: &BinExport) -> Self {
+        let mut functions: Vec<_> = binexport
+            .functions()
+            .filter(|f| f.vertex_type() != vertex::Type::Imported)
+            .collect();
+        functions.sort_by_key(|f| f.address());
+        functions.dedup_by_key(|f| f.address());
+        let index: HashMap<u64, usize> = functions
+            .iter()
+            .enumerate()
+            .map(|(i, f)| (f.address(), i))
+            .collect();
+
+        // Call graph MD indices over all vertices, so they match BinDiff's view
+        let call_graph_top_down = md_index::call_graph_md_indices(binexport, Direction::TopDown);
+        let call_graph_bottom_up = md_index::call_graph_md_indices(binexport, Direction::BottomUp);
+
+        let mut call_edges: Vec<(usize, usize)> = Vec::new();
+        if let Some(call_graph) = &binexport.binexport.call_graph {
+            let vertices: Vec<u64> = call_graph.vertex.iter().map(|v| v.address()).collect();
+            for edge in &call_graph.edge {
+                let source = vertices.get(edge.source_vertex_index() as usize);
+                let target = vertices.get(edge.target_vertex_index() as usize);
+                if let (Some(Some(&s)), Some(Some(&t))) =
+                    (source.map(|a| index.get(a)), target.map(|a| index.get(a)))
+                {
+                    call_edges.push((s, t));
+                }
+            }
+        }
+        let mut call_targets: Vec<Vec<usize>> = vec![Vec::new(); functions.len()];
+        for &(source, target) in &call_edges {
+            call_targets[source].push(target);
+        }
+
+        let mut features: Vec<Features> = functions
+            .iter()
+            .enumerate()
+            .map(|(i, function)| {
+                let address = function.address();
+                let blocks: Vec<_> = function.basic_blocks().collect();
+                let md_top_down = md_index::function_md_index(function, Direction::TopDown);
+                let md_bottom_up = md_index::function_md_index(function, Direction::BottomUp);
+
+                let mut hasher = DefaultHasher::new();
+                let mut mnemonics = Vec::new();
+                let mut callees = Vec::new();
+                let mut strings = Vec::new();
+                for block in &blocks {
+                    for instruction in block.instructions() {
+                        instruction.raw_bytes().hash(&mut hasher);
+                        mnemonics.push(instruction.mnemonic());
+                        strings.extend(instruction.string_references());
+                        for target in instruction.call_targets() {
+                            if let Some(&callee) = index.get(target) {
+                                if callees.last() != Some(&callee) {
+                                    callees.push(callee);
+                                }
+                            }
+                        }
+                    }
+                }
+                if callees.is_empty() {
+                    callees = call_targets[i].clone();
+                }
+
+                let name = function.name().filter(|name| !is_generated_name(name));
+                Features {
+                    address,
+                    name: name.map(str::to_string),
+                    display_name: name
+                        .map(str::to_string)
+                        .unwrap_or_else(|| format!("sub_{:X}", address)),
+                    hash: hasher.finish(),
+                    prime: prime_product(mnemonics.iter().copied()),
+                    basic_blocks: blocks.len(),
+                    edges: function.edge_count(),
+                    instructions: mnemonics.len(),
+                    loops: function.edges().filter(|edge| edge.is_back_edge).count(),
+                    md_top_down,
+                    md_bottom_up,
+                    call_graph_md_top_down: call_graph_top_down
+                        .get(&address)
+                        .copied()
+                        .unwrap_or_default(),
+                    call_graph_md_bottom_up: call_graph_bottom_up
+                        .get(&address)
+                        .copied()
+                        .unwrap_or_default(),
+                    strings: hash_unordered(strings),
+                    callees,
+                    callers: Vec::new(),
+                }
+            })
+            .collect();
+        for &(source, target) in &call_edges {
+            features[target].callers.push(source);
+        }
+
+        Self {
+            functions: features,
+            call_edges,
+        }
+    }
+}
+
+struct State {
+    primary: Side,
+    secondary: Side,
+    pairs: Vec<(usize, usize, FunctionAlgorithm)>,
+    matched1: HashMap<usize, usize>,
+    matched2: HashMap<usize, usize>,
+}
+
+impl State {
+    fn new(primary: Side, secondary: Side) -> Self {
+        Self {
+            primary,
+            secondary,
+            pairs: Vec::new(),
+            matched1: HashMap::new(),
+            matched2: HashMap::new(),
+        }
+    }
+
+    fn try_match(&mut self, i: usize, j: usize, algorithm: &FunctionAlgorithm) -> bool {
+        if self.matched1.contains_key(&i) || self.matched2.contains_key(&j) {
+            return false;
+        }
+        self.matched1.insert(i, j);
+        self.matched2.insert(j, i);
+        self.pairs.push((i, j, algorithm.clone()));
+        true
+    }
+
+    fn run(&mut self, algorithm: &FunctionAlgorithm) {
+        match algorithm {
+            FunctionAlgorithm::NameHashMatching => self.match_unique(algorithm, |f| f.name.clone()),
+            FunctionAlgorithm::HashMatching => self.match_unique(algorithm, |f| Some(f.hash)),
+            FunctionAlgorithm::EdgesFlowgraphMdIndex => {
+                self.match_edges(algorithm, |f| f.md_top_down)
+            }
+            FunctionAlgorithm::EdgesCallgraphMdIndex => {
+                self.match_edges(algorithm, |f| f.call_graph_md_top_down)
+            }
+            FunctionAlgorithm::MdIndexMatchingFlowgraphTopDown => self
+                .match_unique(algorithm, |f| {
+                    (f.edges > 0).then(|| f.md_top_down.to_bits())
+                }),
+            FunctionAlgorithm::MdIndexMatchingFlowgraphBottomUp => self
+                .match_unique(algorithm, |f| {
+                    (f.edges > 0).then(|| f.md_bottom_up.to_bits())
+                }),
+            FunctionAlgorithm::PrimeSignatureMatching => {
+                self.match_unique(algorithm, |f| (f.instructions > 0).then_some(f.prime))
+            }
+            FunctionAlgorithm::MdIndexMatchingCallGraphTopDown => self
+                .match_unique(algorithm, |f| {
+                    (f.call_graph_md_top_down > 0.0).then(|| f.call_graph_md_top_down.to_bits())
+                }),
+            FunctionAlgorithm::MdIndexMatchingCallGraphBottomUp => self
+                .match_unique(algorithm, |f| {
+                    (f.call_graph_md_bottom_up > 0.0).then(|| f.call_graph_md_bottom_up.to_bits())
+                }),
+            FunctionAlgorithm::RelaxedMdIndexMatching => self.match_unique(algorithm, |f| {
+                Some((f.md_top_down.to_bits(), f.md_bottom_up.to_bits()))
+            }),
+            FunctionAlgorithm::InstructionCount => self.match_unique(algorithm, |f| {
+                (f.instructions > 0).then_some(f.instructions)
+            }),
+            FunctionAlgorithm::AddressSequence => self.match_address_sequence(algorithm),
+            FunctionAlgorithm::StringReferences => self.match_unique(algorithm, |f| f.strings),
+            FunctionAlgorithm::LoopCountMatching => {
+                self.match_unique(algorithm, |f| (f.loops > 0).then_some(f.loops))
+            }
+            FunctionAlgorithm::CallSequenceMatchingExact
+            | FunctionAlgorithm::CallSequenceMatchingTopology
+            | FunctionAlgorithm::CallSequenceMatchingSequence => {
+                self.match_call_sequences(algorithm)
+            }
+            FunctionAlgorithm::CallReferenceMatching => self.match_call_references(algorithm),
+            FunctionAlgorithm::None | FunctionAlgorithm::Manual | FunctionAlgorithm::Other(_) => {}
+        }
+    }
+
+    /// Match unmatched functions whose key is unique on both sides
+    fn match_unique<K, F>(&mut self, algorithm: &FunctionAlgorithm, key: F)
+    where
+        K: Hash + Eq,
+        F: Fn(&Features) -> Option<K>,
+    {
+        let groups1 = group(&self.primary.functions, &self.matched1, &key);
+        let groups2 = group(&self.secondary.functions, &self.matched2, &key);
+        for (i, j) in unique_pairs(&groups1, &groups2) {
+            self.try_match(i, j, algorithm);
+        }
+    }
+
+    /// Match the endpoints of call graph edges whose (caller, callee) feature
+    /// pair is unique on both sides
+    fn match_edges<F>(&mut self, algorithm: &FunctionAlgorithm, feature: F)
+    where
+        F: Fn(&Features) -> f64,
+    {
+        let edge_groups = |side: &Side, matched: &HashMap<usize, usize>| {
+            let mut groups: HashMap<(u64, u64), Vec<(usize, usize)>> = HashMap::new();
+            for &(s, t) in &side.call_edges {
+                if matched.contains_key(&s) && matched.contains_key(&t) {
+                    continue;
+                }
+                let (fs, ft) = (feature(&side.functions[s]), feature(&side.functions[t]));
+                if fs > 0.0 && ft > 0.0 {
+                    groups
+                        .entry((fs.to_bits(), ft.to_bits()))
+                        .or_default()
+                        .push((s, t));
+                }
+            }
+            groups
+        };
+        let groups1 = edge_groups(&self.primary, &self.matched1);
+        let groups2 = edge_groups(&self.secondary, &self.matched2);
+        for ((s1, t1), (s2, t2)) in unique_pairs(&groups1, &groups2) {
+            self.try_match(s1, s2, algorithm);
+            self.try_match(t1, t2, algorithm);
+        }
+    }
+
+    /// Match the single unmatched function between two consecutive matches
+    /// on both sides
+    fn match_address_sequence(&mut self, algorithm: &FunctionAlgorithm) {
+        let mut anchors: Vec<(usize, usize)> =
+            self.matched1.iter().map(|(&i, &j)| (i, j)).collect();
+        anchors.sort_unstable();
+        let mut last = (None::<usize>, None::<usize>);
+        let mut gaps = Vec::new();
+        for &(i, j) in &anchors {
+            if last.1.is_some_and(|lj| j <= lj) {
+                // Crossing match, not usable as a sequence anchor
+                continue;
+            }
+            gaps.push((last, (Some(i), Some(j))));
+            last = (Some(i), Some(j));
+        }
+        gaps.push((last, (None, None)));
+
+        for ((start1, start2), (end1, end2)) in gaps {
+            let range = |start: Option<usize>, end: Option<usize>, len: usize| {
+                start.map_or(0, |s| s + 1)..end.unwrap_or(len)
+            };
+            let unmatched1: Vec<usize> = range(start1, end1, self.primary.functions.len())
+                .filter(|i| !self.matched1.contains_key(i))
+                .collect();
+            let unmatched2: Vec<usize> = range(start2, end2, self.secondary.functions.len())
+                .filter(|j| !self.matched2.contains_key(j))
+                .collect();
+            if let ([i], [j]) = (unmatched1.as_slice(), unmatched2.as_slice()) {
+                self.try_match(*i, *j, algorithm);
+            }
+        }
+    }
+
+    /// Match the callees of already matched functions by their position in
+    /// the call sequence
+    fn match_call_sequences(&mut self, algorithm: &FunctionAlgorithm) {
+        let mut pairs: Vec<(usize, usize)> = self.matched1.iter().map(|(&i, &j)| (i, j)).collect();
+        pairs.sort_unstable();
+        for (i, j) in pairs {
+            let calls1 = self.primary.functions[i].callees.clone();
+            let calls2 = self.secondary.functions[j].callees.clone();
+            match algorithm {
+                FunctionAlgorithm::CallSequenceMatchingExact => {
+                    if calls1.len() == calls2.len() {
+                        for (&c1, &c2) in calls1.iter().zip(&calls2) {
+                            self.try_match(c1, c2, algorithm);
+                        }
+                    }
+                }
+                FunctionAlgorithm::CallSequenceMatchingTopology => {
+                    let unmatched1 = self.unmatched(&calls1, true);
+                    let unmatched2 = self.unmatched(&calls2, false);
+                    if let ([c1], [c2]) = (unmatched1.as_slice(), unmatched2.as_slice()) {
+                        self.try_match(*c1, *c2, algorithm);
+                    }
+                }
+                _ => {
+                    // Split both sequences at callees matched to each other and
+                    // pair up gaps holding a single unmatched callee
+                    let mut last = (0, 0);
+                    let mut gaps = Vec::new();
+                    for (a, c1) in calls1.iter().enumerate() {
+                        let Some(&partner) = self.matched1.get(c1) else {
+                            continue;
+                        };
+                        if let Some(b) = calls2[last.1..].iter().position(|&c2| c2 == partner) {
+                            let b = last.1 + b;
+                            gaps.push((last.0..a, last.1..b));
+                            last = (a + 1, b + 1);
+                        }
+                    }
+                    gaps.push((last.0..calls1.len(), last.1..calls2.len()));
+                    for (range1, range2) in gaps {
+                        let unmatched1 = self.unmatched(&calls1[range1], true);
+                        let unmatched2 = self.unmatched(&calls2[range2], false);
+                        if let ([c1], [c2]) = (unmatched1.as_slice(), unmatched2.as_slice()) {
+                            self.try_match(*c1, *c2, algorithm);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Match unmatched callers and callees of already matched functions whose
+    /// instruction signature is unique within the neighborhood
+    fn match_call_references(&mut self, algorithm: &FunctionAlgorithm) {
+        let mut pairs: Vec<(usize, usize)> = self.matched1.iter().map(|(&i, &j)| (i, j)).collect();
+        pairs.sort_unstable();
+        let key = |f: &Features| Some((f.prime, f.basic_blocks));
+        for (i, j) in pairs {
+            let neighbors = |f: &Features| -> Vec<usize> {
+                let mut neighbors: Vec<usize> =
+                    f.callees.iter().chain(&f.callers).copied().collect();
+                neighbors.sort_unstable();
+                neighbors.dedup();
+                neighbors
+            };
+            let neighbors1 = neighbors(&self.primary.functions[i]);
+            let neighbors2 = neighbors(&self.secondary.functions[j]);
+            let groups1 = group_within(&self.primary.functions, neighbors1, &self.matched1, &key);
+            let groups2 = group_within(&self.secondary.functions, neighbors2, &self.matched2, &key);
+            for (c1, c2) in unique_pairs(&groups1, &groups2) {
+                self.try_match(c1, c2, algorithm);
+            }
+        }
+    }
+
+    /// Distinct unmatched functions of `calls`, in order
+    fn unmatched(&self, calls: &[usize], primary: bool) -> Vec<usize> {
+        let matched = if primary {
+            &self.matched1
+        } else {
+            &self.matched2
+        };
+        let mut unmatched: Vec<usize> = Vec::new();
+        for &call in calls {
+            if !matched.contains_key(&call) && !unmatched.contains(&call) {
+                unmatched.push(call);
+            }
+        }
+        unmatched
+    }
+
+    fn into_matches<F: Fn(&FunctionAlgorithm) -> f64>(self, confidence: F) -> Vec<FunctionMatch> {
+        let mut pairs = self.pairs;
+        pairs.sort_by_key(|&(i, _, _)| self.primary.functions[i].address);
+        pairs
+            .into_iter()
+            .enumerate()
+            .map(|(id, (i, j, algorithm))| {
+                let f1 = &self.primary.functions[i];
+                let f2 = &self.secondary.functions[j];
+                FunctionMatch {
+                    id: id as i64 + 1,
+                    address1: f1.address as i64,
+                    name1: f1.display_name.clone(),
+                    address2: f2.address as i64,
+                    name2: f2.display_name.clone(),
+                    similarity: estimate_similarity(f1, f2),
+                    confidence: confidence_sigmoid(confidence(&algorithm)),
+                    flags: 0,
+                    algorithm,
+                    evaluate: false,
+                    comment_supported: false,
+                    basic_blocks: f1.basic_blocks.min(f2.basic_blocks) as i64,
+                    edges: f1.edges.min(f2.edges) as i64,
+                    instructions: f1.instructions.min(f2.instructions) as i64,
+                }
+            })
+            .collect()
+    }
+}
+
+/// Group unmatched items by key
+pub(crate) fn group<T, K, F>(
+    items: &[T],
+    matched: &HashMap<usize, usize>,
+    key: &F,
+) -> HashMap<K, Vec<usize>>
+where
+    K: Hash + Eq,
+    F: Fn(&T) -> Option<K>,
+{
+    group_within(items, 0..items.len(), matched, key)
+}
+
+/// Group the unmatched items among the distinct `indices` by key
+pub(crate) fn group_within<T, K, F>(
+    items: &[T],
+    indices: impl IntoIterator<Item = usize>,
+    matched: &HashMap<usize, usize>,
+    key: &F,
+) -> HashMap<K, Vec<usize>>
+where
+    K: Hash + Eq,
+    F: Fn(&T) -> Option<K>,
+{
+    let mut groups: HashMap<K, Vec<usize>> = HashMap::new();
+    for i in indices {
+        if matched.contains_key(&i) {
+            continue;
+        }
+        if let Some(k) = key(&items[i]) {
+            groups.entry(k).or_default().push(i);
+        }
+    }
+    groups
+}
+
+/// Pairs of items whose key occurs exactly once in both groupings, sorted
+pub(crate) fn unique_pairs<K: Hash + Eq, V: Copy + Ord>(
+    groups1: &HashMap<K, Vec<V>>,
+    groups2: &HashMap<K, Vec<V>>,
+) -> Vec<(V, V)> {
+    let mut pairs: Vec<(V, V)> = groups1
+        .iter()
+        .filter(|(_, v)| v.len() == 1)
+        .filter_map(|(k, v)| match groups2.get(k) {
+            Some(w) if w.len() == 1 => Some((v[0], w[0])),
+            _ => None,
+        })
+        .collect();
+    pairs.sort_unstable();
+    pairs
+}
+
+/// Order-independent hash of a set of strings, `None` if there are none
+pub(crate) fn hash_unordered(mut strings: Vec<&str>) -> Option<u64> {
+    if strings.is_empty() {
+        return None;
+    }
+    strings.sort_unstable();
+    let mut hasher = DefaultHasher::new();
+    strings.hash(&mut hasher);
+    Some(hasher.finish())
+}
+
+/// Structural similarity of two functions before basic block matching, from
+/// the ratio of their sizes and the distance of their MD indices
+fn estimate_similarity(f1: &Features, f2: &Features) -> f64 {
+    if f1.hash == f2.hash {
+        return 1.0;
+    }
+    let ratio = |a: usize, b: usize| {
+        if a.max(b) == 0 {
+            1.0
+        } else {
+            a.min(b) as f64 / a.max(b) as f64
+        }
+    };
+    let size = similarity::weighted_share(
+        ratio(f1.edges, f2.edges),
+        ratio(f1.basic_blocks, f2.basic_blocks),
+        ratio(f1.instructions, f2.instructions),
+    );
+    let structure = similarity::md_index_similarity(f1.md_top_down, f2.md_top_down);
+    ((size + structure) / 2.0).min(1.0)
+}
+
+/// A complete native diff, ready to be written as a BinDiff database
+#[derive(Debug, Clone)]
+pub struct DiffResult {
+    pub primary: File,
+    pub secondary: File,
+    pub metadata: Metadata,
+    pub function_matches: Vec<FunctionMatch>,
+    pub basic_block_matches: Vec<BasicBlockMatch>,
+    pub instruction_matches: Vec<Instruction>,
+}
+
+impl DiffResult {
+    /// Match the basic blocks of every function match, updating its matched
+    /// counts, similarity and confidence, drop matches below the thresholds of
+    /// `config` and assemble file rows and metadata
+    pub fn new(
+        primary: &BinExport,
+        secondary: &BinExport,
+        mut function_matches: Vec<FunctionMatch>,
+        config: &MatchingConfig,
+    ) -> Result<Self> {
+        let primary_file = file_row(primary, 1)?;
+        let secondary_file = file_row(secondary, 2)?;
+        let basic_block_matcher = config.basic_block_matcher();
+
+        let mut basic_block_matches = Vec::new();
+        let mut instruction_matches = Vec::new();
+        for function_match in &mut function_matches {
+            let (Some(function1), Some(function2)) = (
+                primary.function(function_match.address1 as u64),
+                secondary.function(function_match.address2 as u64),
+            ) else {
+                continue;
+            };
+            let matches = basic_block_matcher.match_basic_blocks(
+                &function1,
+                &function2,
+                function_match.id,
+                basic_block_matches.len() as i64 + 1,
+            );
+            function_match.basic_blocks = matches.basic_blocks.len() as i64;
+            function_match.edges = matches.edges as i64;
+            function_match.instructions = matches.instructions.len() as i64;
+            function_match.similarity =
+                similarity::function_similarity(function_match, &function1, &function2);
+            function_match.confidence =
+                similarity::function_confidence(function_match, &matches.basic_blocks, config);
+            basic_block_matches.extend(matches.basic_blocks);
+            instruction_matches.extend(matches.instructions);
+        }
+
+        function_matches.retain(|m| {
+            m.similarity >= config.min_similarity && m.confidence >= config.min_confidence
+        });
+        let kept: HashSet<i64> = function_matches.iter().map(|m| m.id).collect();
+        basic_block_matches.retain(|m| kept.contains(&m.function_id));
+        let kept: HashSet<i64> = basic_block_matches.iter().map(|m| m.id).collect();
+        instruction_matches.retain(|m| kept.contains(&m.id));
+
+        let similarity =
+            similarity::overall_similarity(&primary_file, &secondary_file, &function_matches);
+        let confidence = similarity::overall_confidence(&function_matches);
+
+        let now = chrono::Utc::now();
+        Ok(Self {
+            primary: primary_file,
+            secondary: secondary_file,
+            metadata: Metadata {
+                version: format!("bindiff-rs {}", env!("CARGO_PKG_VERSION")),
+                file1: 1,
+                file2: 2,
+                description: String::new(),
+                created: now,
+                modified: now,
+                similarity,
+                confidence,
+            },
+            function_matches,
+            basic_block_matches,
+            instruction_matches,
+        })
+    }
+    /// Write the result as a new `.BinDiff` database at `path`, which must not
+    /// exist yet
+    pub fn write<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let bindiff = BinDiff::create(path)?;
+        bindiff.write_file(&self.primary)?;
+        bindiff.write_file(&self.secondary)?;
+        bindiff.write_metadata(&self.metadata)?;
+        bindiff.write_function_matches(&self.function_matches)?;
+        bindiff.write_basic_block_matches(&self.basic_block_matches)?;
+        bindiff.write_instruction_matches(&self.instruction_matches)?;
+        bindiff.close()?;
+        Ok(())
+    }
+}
+
+/// Diff two BinExport files with BinDiff's default matching steps
+pub fn diff(primary: &BinExport, secondary: &BinExport) -> Result<DiffResult> {
+    diff_with_config(primary, secondary, &MatchingConfig::default())
+}
+
+/// Diff two BinExport files with the steps and thresholds of `config`
+pub fn diff_with_config(
+    primary: &BinExport,
+    secondary: &BinExport,
+    config: &MatchingConfig,
+) -> Result<DiffResult> {
+    let function_matches = config
+        .function_matcher()
+        .match_functions(primary, secondary);
+    DiffResult::new(primary, secondary, function_matches, config)
+}
+
+/// Build the `file` table row describing a BinExport
+fn file_row(binexport: &BinExport, id: i64) -> Result<File> {
+    let executable_name = binexport.executable_name()?;
+    let mut file = File {
+        id,
+        filename: Path::new(&executable_name)
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_else(|| executable_name.clone()),
+        exe_filename: executable_name,
+        hash: binexport.executable_id()?,
+        functions: 0,
+        lib_functions: 0,
+        calls: binexport
+            .binexport
+            .call_graph
+            .as_ref()
+            .map_or(0, |call_graph| call_graph.edge.len() as i64),
+        basic_blocks: 0,
+        lib_basic_blocks: 0,
+        edges: 0,
+        lib_edges: 0,
+        instructions: 0,
+        lib_instructions: 0,
+    };
+    for function in binexport.functions() {
+        let (functions, basic_blocks, edges, instructions) =
+            if function.vertex_type() == vertex::Type::Library {
+                (
+                    &mut file.lib_functions,
+                    &mut file.lib_basic_blocks,
+                    &mut file.lib_edges,
+                    &mut file.lib_instructions,
+                )
+            } else {
+                (
+                    &mut file.functions,
+                    &mut file.basic_blocks,
+                    &mut file.edges,
+                    &mut file.instructions,
+                )
+            };
+        *functions += 1;
+        *basic_blocks += function.basic_block_count() as i64;
+        *edges += function.edge_count() as i64;
+        *instructions += function.instruction_count() as i64;
+    }
+    Ok(file)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::{binexport, FunctionSpec};
+
+    #[test]
+    fn test_match_functions() {
+        let primary = binexport(&[
+            FunctionSpec::new(0x1000, "main", vec![vec!["bl foo", "bl bar", "ret"]])
+                .calls(&[0x1100, 0x1200]),
+            FunctionSpec::new(
+                0x1100,
+                "sub_1100",
+                vec![vec!["cbz x0"], vec!["mov x0, #0x1"], vec!["ret"]],
+            )
+            .edges(&[(0, 1), (0, 2), (1, 2)]),
+            FunctionSpec::new(0x1200, "bar", vec![vec!["add x0, x0, #0x1", "ret"]]),
+        ]);
+        let secondary = binexport(&[
+            FunctionSpec::new(0x2000, "main", vec![vec!["bl foo", "bl bar", "ret"]])
+                .calls(&[0x2100, 0x2200]),
+            FunctionSpec::new(
+                0x2100,
+                "sub_2100",
+                vec![vec!["cbz x0"], vec!["mov x0, #0x2", "nop"], vec!["ret"]],
+            )
+            .edges(&[(0, 1), (0, 2), (1, 2)]),
+            FunctionSpec::new(0x2200, "bar", vec![vec!["add x0, x0, #0x1", "ret"]]),
+        ]);
+
+        let matches = FunctionMatcher::new().match_functions(&primary, &secondary);
+        let pairs: Vec<(i64, i64, FunctionAlgorithm)> = matches
+            .iter()
+            .map(|m| (m.address1, m.address2, m.algorithm.clone()))
+            .collect();
+        assert_eq!(
+            pairs,
+            [
+                (0x1000, 0x2000, FunctionAlgorithm::NameHashMatching),
+                (0x1100, 0x2100, FunctionAlgorithm::EdgesCallgraphMdIndex),
+                (0x1200, 0x2200, FunctionAlgorithm::NameHashMatching),
+            ]
+        );
+        assert_eq!(matches[0].similarity, 1.0);
+        assert!(matches[1].similarity < 1.0);
+        assert_eq!(matches[0].confidence, 0.9933071490757153);
+
+        let result =
+            DiffResult::new(&primary, &secondary, matches, &MatchingConfig::default()).unwrap();
+        assert_eq!(result.primary.functions, 3);
+        assert_eq!(result.function_matches[2].similarity, 1.0);
+        assert_eq!(result.function_matches[1].basic_blocks, 3);
+        assert_eq!(result.function_matches[1].instructions, 3);
+        assert_eq!(result.primary.calls, 2);
+
+        let path = std::env::temp_dir().join(format!("bindiff-rs-{}.BinDiff", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        result.write(&path).unwrap();
+        assert!(result.write(&path).is_err());
+        let bindiff = BinDiff::open(&path).unwrap();
+        assert_eq!(bindiff.read_function_matches().unwrap().len(), 3);
+        assert_eq!(bindiff.read_basic_block_matches().unwrap().len(), 5);
+        assert_eq!(bindiff.read_metadata().unwrap().file2, 2);
+        bindiff.close().unwrap();
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,250 @@
+//! MD index, a structural graph hash built from the in/out degrees and
+//! breadth-first depth of each edge's endpoints. BinDiff's papers call the
+//! depth a topological level, but it is the BFS distance from the entry (or
+//! exit) nodes, not a position in a topological sort.
+
+use crate::flowgraph::Function;
+use crate::BinExport;
+use std::collections::{HashMap, VecDeque};
+
+/// Which way breadth-first levels are counted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// Distance from the entry/root nodes
+    TopDown,
+    /// Distance from the exit/leaf nodes
+    BottomUp,
+}
+
+/// A directed graph with nodes `0..nodes`
+pub struct Graph {
+    nodes: usize,
+    edges: Vec<(usize, usize)>,
+    in_degree: Vec<usize>,
+    out_degree: Vec<usize>,
+}
+
+impl Graph {
+    pub fn new(nodes: usize, edges: Vec<(usize, usize)>) -> Self {
+        let mut in_degree = vec![0; nodes];
+        let mut out_degree = vec![0; nodes];
+        for &(source, target) in &edges {
+            out_degree[source] += 1;
+            in_degree[target] += 1;
+        }
+        Self {
+            nodes,
+            edges,
+            in_degree,
+            out_degree,
+        }
+    }
+
+    /// Breadth-first level of every node, starting from `roots` when counting
+    /// top down and from nodes without successors when counting bottom up.
+    /// Unreachable nodes get level 0.
+    fn levels(&self, roots: &[usize], direction: Direction) -> Vec<usize> {
+        let mut adjacency = vec![Vec::new(); self.nodes];
+        let starts: Vec<usize> = match direction {
+            Direction::TopDown => {
+                for &(source, target) in &self.edges {
+                    adjacency[source].push(target);
+                }
+                roots.to_vec()
+            }
+            Direction::BottomUp => {
+                for &(source, target) in &self.edges {
+                    adjacency[target].push(source);
+                }
+                (0..self.nodes)
+                    .filter(|&n| self.out_degree[n] == 0)
+                    .collect()
+            }
+        };
+
+        let mut levels = vec![usize::MAX; self.nodes];
+        let mut queue = VecDeque::new();
+        for start in starts {
+            if levels[start] == usize::MAX {
+                levels[start] = 0;
+                queue.push_back(start);
+            }
+        }
+        while let Some(node) = queue.pop_front() {
+            for &next in &adjacency[node] {
+                if levels[next] == usize::MAX {
+                    levels[next] = levels[node] + 1;
+                    queue.push_back(next);
+                }
+            }
+        }
+        levels
+            .into_iter()
+            .map(|level| if level == usize::MAX { 0 } else { level })
+            .collect()
+    }
+
+    /// MD index of every edge, in edge order
+    pub fn edge_md_indices(&self, roots: &[usize], direction: Direction) -> Vec<f64> {
+        let levels = self.levels(roots, direction);
+        self.edges
+            .iter()
+            .map(|&(source, target)| {
+                let level = match direction {
+                    Direction::TopDown => levels[source],
+                    Direction::BottomUp => levels[target],
+                };
+                let weighted = self.in_degree[source] as f64 * 2f64.sqrt()
+                    + self.out_degree[source] as f64 * 3f64.sqrt()
+                    + self.in_degree[target] as f64 * 5f64.sqrt()
+                    + self.out_degree[target] as f64 * 7f64.sqrt()
+                    + level as f64 * 11f64.sqrt();
+                1.0 / weighted.sqrt()
+            })
+            .collect()
+    }
+
+    /// MD index of the whole graph, the sum of its edge MD indices
+    pub fn md_index(&self, roots: &[usize], direction: Direction) -> f64 {
+        self.edge_md_indices(roots, direction).iter().sum()
+    }
+
+    /// MD index of every node, the sum of the MD indices of its incident edges
+    pub fn node_md_indices(&self, roots: &[usize], direction: Direction) -> Vec<f64> {
+        let mut nodes = vec![0.0; self.nodes];
+        for (&(source, target), md_index) in self
+            .edges
+            .iter()
+            .zip(self.edge_md_indices(roots, direction))
+        {
+            nodes[source] += md_index;
+            nodes[target] += md_index;
+        }
+        nodes
+    }
+
+    /// Nodes without predecessors, or node 0 if every node has one
+    pub fn roots(&self) -> Vec<usize> {
+        let roots: Vec<usize> = (0..self.nodes)
+            .filter(|&n| self.in_degree[n] == 0)
+            .collect();
+        if roots.is_empty() && self.nodes > 0 {
+            vec![0]
+        } else {
+            roots
+        }
+    }
+}
+
+/// Flow graph of `function` with its basic blocks numbered in address order,
+/// and its entry block as the only root
+fn flow_graph(function: &Function) -> (Graph, Vec<usize>) {
+    let block_index: HashMap<usize, usize> = function
+        .basic_blocks()
+        .enumerate()
+        .map(|(local, block)| (block.index(), local))
+        .collect();
+    let edges: Vec<(usize, usize)> = function
+        .edges()
+        .filter_map(|edge| {
+            Some((
+                *block_index.get(&edge.source.index())?,
+                *block_index.get(&edge.target.index())?,
+            ))
+        })
+        .collect();
+    let roots = function
+        .entry_basic_block()
+        .and_then(|block| block_index.get(&block.index()).copied())
+        .into_iter()
+        .collect();
+    (Graph::new(block_index.len(), edges), roots)
+}
+
+/// MD index of a function's flow graph
+pub fn function_md_index(function: &Function, direction: Direction) -> f64 {
+    let (graph, roots) = flow_graph(function);
+    graph.md_index(&roots, direction)
+}
+
+/// MD index of every basic block of a function as `(address, md_index)`, in
+/// address order
+pub fn basic_block_md_indices(function: &Function, direction: Direction) -> Vec<(u64, f64)> {
+    let (graph, roots) = flow_graph(function);
+    function
+        .basic_blocks()
+        .map(|block| block.address())
+        .zip(graph.node_md_indices(&roots, direction))
+        .collect()
+}
+
+/// Call graph of a BinExport with vertex addresses, in vertex order
+fn call_graph(binexport: &BinExport) -> (Graph, Vec<u64>) {
+    let Some(call_graph) = &binexport.binexport.call_graph else {
+        return (Graph::new(0, Vec::new()), Vec::new());
+    };
+    let vertices: Vec<u64> = call_graph.vertex.iter().map(|v| v.address()).collect();
+    let edges = call_graph
+        .edge
+        .iter()
+        .map(|e| {
+            (
+                e.source_vertex_index() as usize,
+                e.target_vertex_index() as usize,
+            )
+        })
+        .filter(|&(s, t)| s < vertices.len() && t < vertices.len())
+        .collect();
+    (Graph::new(vertices.len(), edges), vertices)
+}
+
+/// MD index of the whole call graph
+pub fn call_graph_md_index(binexport: &BinExport, direction: Direction) -> f64 {
+    let (graph, _) = call_graph(binexport);
+    graph.md_index(&graph.roots(), direction)
+}
+
+/// MD index of every call graph vertex, by function address
+pub fn call_graph_md_indices(binexport: &BinExport, direction: Direction) -> HashMap<u64, f64> {
+    let (graph, vertices) = call_graph(binexport);
+    vertices
+        .into_iter()
+        .zip(graph.node_md_indices(&graph.roots(), direction))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::{binexport, FunctionSpec};
+
+    #[test]
+    fn test_md_index() {
+        let binexport = binexport(&[
+            FunctionSpec::new(0x1000, "foo", vec![vec!["bl bar"], vec!["ret"]])
+                .edges(&[(0, 1)])
+                .calls(&[0x2000]),
+            FunctionSpec::new(0x2000, "bar", vec![vec!["ret"]]),
+        ]);
+        let foo = binexport.function(0x1000).unwrap();
+
+        // Single edge: source out degree 1, target in degree 1, level 0
+        let edge = 1.0 / (3f64.sqrt() + 5f64.sqrt()).sqrt();
+        assert_eq!(function_md_index(&foo, Direction::TopDown), edge);
+        assert_eq!(
+            basic_block_md_indices(&foo, Direction::TopDown),
+            [(0x1000, edge), (0x1004, edge)]
+        );
+        // Bottom up, the level is counted from the target
+        assert_eq!(function_md_index(&foo, Direction::BottomUp), edge);
+        assert_eq!(call_graph_md_index(&binexport, Direction::TopDown), edge);
+
+        let indices = call_graph_md_indices(&binexport, Direction::BottomUp);
+        assert_eq!(indices[&0x1000], edge);
+        assert_eq!(indices[&0x2000], edge);
+        assert_eq!(
+            function_md_index(&binexport.function(0x2000).unwrap(), Direction::TopDown),
+            0.0
+        );
+    }
+}
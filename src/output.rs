@@ -0,0 +1,334 @@
+//! Record output for the CLI: text, JSON, newline-delimited JSON, CSV and
+//! aligned tables. Every format but the table is written as records arrive, so
+//! large diffs are never held in memory.
+
+use crate::{BasicBlockMatch, FunctionMatch, Instruction};
+use anyhow::{bail, Result};
+use serde::Serialize;
+use std::io::Write;
+
+/// Output format of a list of records
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// One `Display` line per record
+    Text,
+    /// A pretty-printed JSON array
+    Json,
+    /// One compact JSON object per line
+    Ndjson,
+    /// CSV with a header row
+    Csv,
+    /// Columns padded to a common width
+    Table,
+}
+
+impl std::str::FromStr for OutputFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "text" => Ok(OutputFormat::Text),
+            "json" => Ok(OutputFormat::Json),
+            "ndjson" | "jsonl" => Ok(OutputFormat::Ndjson),
+            "csv" => Ok(OutputFormat::Csv),
+            "table" => Ok(OutputFormat::Table),
+            _ => bail!("Unknown output format: {}", s),
+        }
+    }
+}
+
+/// A column value, formatted by kind
+#[derive(Debug, Clone, PartialEq)]
+pub enum Field {
+    Text(String),
+    Integer(i64),
+    /// Printed with full precision in CSV and three decimals in tables
+    Score(f64),
+    /// Printed as `0x` prefixed hex
+    Address(i64),
+}
+
+impl Field {
+    fn csv(&self) -> String {
+        match self {
+            Field::Text(text) => text.clone(),
+            Field::Integer(value) => value.to_string(),
+            Field::Score(value) => value.to_string(),
+            Field::Address(address) => format!("{:#x}", *address as u64),
+        }
+    }
+
+    fn table(&self) -> String {
+        match self {
+            Field::Score(value) => format!("{:.3}", value),
+            _ => self.csv(),
+        }
+    }
+
+    fn is_numeric(&self) -> bool {
+        matches!(self, Field::Integer(_) | Field::Score(_))
+    }
+}
+
+/// A row type that can be written in every output format
+pub trait Record: Serialize + std::fmt::Display {
+    /// Column names, in the order of `fields`
+    fn header() -> &'static [&'static str];
+
+    fn fields(&self) -> Vec<Field>;
+
+    /// Line of the text format, the `Display` output unless overridden
+    fn text(&self) -> String {
+        self.to_string()
+    }
+}
+
+impl Record for FunctionMatch {
+    fn header() -> &'static [&'static str] {
+        &[
+            "address1",
+            "name1",
+            "address2",
+            "name2",
+            "similarity",
+            "confidence",
+            "algorithm",
+            "basic_blocks",
+            "edges",
+            "instructions",
+        ]
+    }
+
+    fn fields(&self) -> Vec<Field> {
+        vec![
+            Field::Address(self.address1),
+            Field::Text(self.name1.clone()),
+            Field::Address(self.address2),
+            Field::Text(self.name2.clone()),
+            Field::Score(self.similarity),
+            Field::Score(self.confidence),
+            Field::Text(self.algorithm.to_string()),
+            Field::Integer(self.basic_blocks),
+            Field::Integer(self.edges),
+            Field::Integer(self.instructions),
+        ]
+    }
+}
+
+impl Record for BasicBlockMatch {
+    fn header() -> &'static [&'static str] {
+        &["function_id", "address1", "address2", "algorithm"]
+    }
+
+    fn fields(&self) -> Vec<Field> {
+        vec![
+            Field::Integer(self.function_id),
+            Field::Address(self.address1),
+            Field::Address(self.address2),
+            Field::Text(self.algorithm.to_string()),
+        ]
+    }
+}
+
+impl Record for Instruction {
+    fn header() -> &'static [&'static str] {
+        &["basic_block_id", "address1", "address2"]
+    }
+
+    fn fields(&self) -> Vec<Field> {
+        vec![
+            Field::Integer(self.id),
+            Field::Address(self.address1),
+            Field::Address(self.address2),
+        ]
+    }
+
+    /// Addresses in hex like the other records; `Display` keeps decimal
+    fn text(&self) -> String {
+        format!("{:#x} -> {:#x}", self.address1 as u64, self.address2 as u64)
+    }
+}
+
+/// Writes records of one type in a given format. Call `finish` after the last
+/// record to close JSON arrays and write tables.
+pub struct RecordWriter<W: Write, T: Record> {
+    out: W,
+    format: OutputFormat,
+    count: usize,
+    /// Rows buffered to compute column widths
+    table: Vec<Vec<Field>>,
+    _record: std::marker::PhantomData<T>,
+}
+
+impl<W: Write, T: Record> RecordWriter<W, T> {
+    pub fn new(out: W, format: OutputFormat) -> Self {
+        Self {
+            out,
+            format,
+            count: 0,
+            table: Vec::new(),
+            _record: std::marker::PhantomData,
+        }
+    }
+
+    fn write_csv_row<I, S>(&mut self, row: I) -> Result<()>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<[u8]>,
+    {
+        let mut writer = csv::Writer::from_writer(&mut self.out);
+        writer.write_record(row).map_err(std::io::Error::from)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    pub fn write(&mut self, record: &T) -> Result<()> {
+        match self.format {
+            OutputFormat::Text => writeln!(self.out, "{}", record.text())?,
+            OutputFormat::Json => {
+                let separator = if self.count == 0 { "[\n" } else { ",\n" };
+                let json = serde_json::to_string_pretty(record)?;
+                write!(self.out, "{}", separator)?;
+                for (index, line) in json.lines().enumerate() {
+                    if index > 0 {
+                        writeln!(self.out)?;
+                    }
+                    write!(self.out, "  {}", line)?;
+                }
+            }
+            OutputFormat::Ndjson => {
+                writeln!(self.out, "{}", serde_json::to_string(record)?)?;
+            }
+            OutputFormat::Csv => {
+                if self.count == 0 {
+                    self.write_csv_row(T::header())?;
+                }
+                self.write_csv_row(record.fields().iter().map(Field::csv))?;
+            }
+            OutputFormat::Table => self.table.push(record.fields()),
+        }
+        self.count += 1;
+        Ok(())
+    }
+
+    /// Number of records written so far
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    /// Write what is still pending and return the underlying writer
+    pub fn finish(mut self) -> Result<W> {
+        match self.format {
+            OutputFormat::Json if self.count == 0 => writeln!(self.out, "[]")?,
+            OutputFormat::Json => writeln!(self.out, "\n]")?,
+            OutputFormat::Csv if self.count == 0 => self.write_csv_row(T::header())?,
+            OutputFormat::Table => write_table(&mut self.out, T::header(), &self.table)?,
+            OutputFormat::Text | OutputFormat::Ndjson | OutputFormat::Csv => {}
+        }
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+fn write_table<W: Write>(out: &mut W, header: &[&str], rows: &[Vec<Field>]) -> Result<()> {
+    let cells: Vec<Vec<String>> = rows
+        .iter()
+        .map(|row| row.iter().map(Field::table).collect())
+        .collect();
+    let widths: Vec<usize> = (0..header.len())
+        .map(|column| {
+            cells
+                .iter()
+                .map(|row| row[column].chars().count())
+                .chain([header[column].len()])
+                .max()
+                .unwrap_or(0)
+        })
+        .collect();
+
+    let line = |out: &mut W, values: &[String], numeric: &[bool]| -> std::io::Result<()> {
+        let mut text = String::new();
+        for (column, value) in values.iter().enumerate() {
+            if column > 0 {
+                text.push_str("  ");
+            }
+            let padding = widths[column].saturating_sub(value.chars().count());
+            if numeric[column] {
+                text.push_str(&" ".repeat(padding));
+                text.push_str(value);
+            } else {
+                text.push_str(value);
+                text.push_str(&" ".repeat(padding));
+            }
+        }
+        writeln!(out, "{}", text.trim_end())
+    };
+
+    let numeric: Vec<bool> = match rows.first() {
+        Some(row) => row.iter().map(Field::is_numeric).collect(),
+        None => vec![false; header.len()],
+    };
+    let header: Vec<String> = header.iter().map(|name| name.to_uppercase()).collect();
+    line(out, &header, &numeric)?;
+    for row in &cells {
+        line(out, row, &numeric)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::function_match;
+
+    fn render(matches: &[FunctionMatch], format: OutputFormat) -> Result<String> {
+        let mut writer = RecordWriter::new(Vec::new(), format);
+        for function_match in matches {
+            writer.write(function_match)?;
+        }
+        Ok(String::from_utf8(writer.finish()?)?)
+    }
+
+    #[test]
+    fn test_record_writer() -> Result<()> {
+        let named = |address1, name: &str, similarity| FunctionMatch {
+            name1: name.to_string(),
+            name2: name.to_string(),
+            similarity,
+            ..function_match(address1, address1 + 0x100)
+        };
+        let matches = [
+            named(0x1000, "memset_s", 1.0),
+            named(0x2000, "_vm_map_copyin", 0.75),
+        ];
+
+        let ndjson = render(&matches, OutputFormat::Ndjson)?;
+        assert_eq!(ndjson.lines().count(), 2);
+        let parsed: Vec<serde_json::Value> =
+            serde_json::from_str(&render(&matches, OutputFormat::Json)?)?;
+        assert_eq!(parsed.len(), 2);
+        assert_eq!(parsed[1]["name1"], "_vm_map_copyin");
+        assert_eq!(render(&[], OutputFormat::Json)?, "[]\n");
+
+        let csv = render(&matches, OutputFormat::Csv)?;
+        let lines: Vec<&str> = csv.lines().collect();
+        assert!(lines[0].starts_with("address1,name1,address2"));
+        assert!(lines[2].starts_with("0x2000,_vm_map_copyin,0x2100,_vm_map_copyin,0.75,"));
+
+        let table = render(&matches, OutputFormat::Table)?;
+        let lines: Vec<&str> = table.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("ADDRESS1  NAME1           ADDRESS2"));
+        assert!(lines[1].contains("memset_s        0x1100"));
+        assert!(lines[2].contains("0.750"));
+
+        let instruction = Instruction {
+            id: 1,
+            address1: 0x1000,
+            address2: 0x2000,
+        };
+        assert_eq!(instruction.text(), "0x1000 -> 0x2000");
+        assert_eq!(instruction.to_string(), "4096 -> 8192");
+        Ok(())
+    }
+}
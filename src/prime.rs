@@ -0,0 +1,197 @@
+//! Prime product signatures: every mnemonic maps to its own prime and a
+//! sequence of instructions to the (wrapping) product of its primes, which is
+//! independent of instruction order.
+//!
+//! BinDiff-style signatures draw the primes from a small table indexed by a
+//! hash of the mnemonic, so distinct mnemonics can share a prime: x86 alone
+//! has more mnemonics than a 1024 entry table has primes, and blocks that
+//! differ only in such mnemonics get the same signature. Here every mnemonic
+//! gets a prime in [2^62, 2^63) derived from its hash instead. The values
+//! therefore differ from BinDiff's, but BinDiff databases do not store them,
+//! so they are only ever compared with other signatures from this module.
+
+use crate::flowgraph::{BasicBlock, Function};
+use crate::BinExport;
+use std::collections::HashMap;
+use std::sync::{Mutex, MutexGuard, OnceLock};
+
+fn mul_mod(a: u64, b: u64, modulus: u64) -> u64 {
+    (a as u128 * b as u128 % modulus as u128) as u64
+}
+
+fn pow_mod(mut base: u64, mut exponent: u64, modulus: u64) -> u64 {
+    let mut result = 1;
+    base %= modulus;
+    while exponent > 0 {
+        if exponent & 1 == 1 {
+            result = mul_mod(result, base, modulus);
+        }
+        base = mul_mod(base, base, modulus);
+        exponent >>= 1;
+    }
+    result
+}
+
+/// Miller-Rabin with the witnesses that are deterministic for all `u64`
+fn is_prime(n: u64) -> bool {
+    const WITNESSES: [u64; 12] = [2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37];
+    if n < 2 {
+        return false;
+    }
+    if let Some(&p) = WITNESSES.iter().find(|&&p| n.is_multiple_of(p)) {
+        return n == p;
+    }
+    let mut d = n - 1;
+    let mut s = 0;
+    while d.is_multiple_of(2) {
+        d /= 2;
+        s += 1;
+    }
+    WITNESSES.iter().all(|&a| {
+        let mut x = pow_mod(a, d, n);
+        if x == 1 || x == n - 1 {
+            return true;
+        }
+        for _ in 1..s {
+            x = mul_mod(x, x, n);
+            if x == n - 1 {
+                return true;
+            }
+        }
+        false
+    })
+}
+
+/// Primes already found per mnemonic, as the prime search is the costly part
+fn cache() -> MutexGuard<'static, HashMap<String, u64>> {
+    static CACHE: OnceLock<Mutex<HashMap<String, u64>>> = OnceLock::new();
+    CACHE
+        .get_or_init(Default::default)
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+fn cached_prime(cache: &mut HashMap<String, u64>, mnemonic: &str) -> u64 {
+    if let Some(&prime) = cache.get(mnemonic) {
+        return prime;
+    }
+
+    // FNV-1a, so the mapping does not depend on the standard library's hasher
+    let hash = mnemonic.bytes().fold(0xcbf29ce484222325u64, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    });
+    // The first prime from an odd start in [2^62, 2^63): with primes this
+    // large two mnemonics only share one if their hashes fall into the same
+    // gap between primes, which is vanishingly unlikely
+    let mut prime = (hash >> 1) | (1 << 62) | 1;
+    while !is_prime(prime) {
+        prime += 2;
+    }
+    cache.insert(mnemonic.to_string(), prime);
+    prime
+}
+
+/// Prime assigned to a mnemonic, stable across binaries and runs
+pub fn mnemonic_prime(mnemonic: &str) -> u64 {
+    cached_prime(&mut cache(), mnemonic)
+}
+
+/// Product of the primes of all `mnemonics`
+pub fn prime_product<'a>(mnemonics: impl IntoIterator<Item = &'a str>) -> u64 {
+    let mut cache = cache();
+    mnemonics.into_iter().fold(1u64, |product, mnemonic| {
+        product.wrapping_mul(cached_prime(&mut cache, mnemonic))
+    })
+}
+
+/// Prime product signature of a basic block's instructions
+pub fn basic_block_prime(basic_block: &BasicBlock) -> u64 {
+    prime_product(
+        basic_block
+            .instructions()
+            .map(|instruction| instruction.mnemonic()),
+    )
+}
+
+/// Prime product signature of all instructions of a function
+pub fn function_prime(function: &Function) -> u64 {
+    function
+        .basic_blocks()
+        .map(|basic_block| basic_block_prime(&basic_block))
+        .fold(1u64, u64::wrapping_mul)
+}
+
+/// Prime product signature of every basic block of a function as
+/// `(address, prime)`, in address order
+pub fn basic_block_primes(function: &Function) -> Vec<(u64, u64)> {
+    function
+        .basic_blocks()
+        .map(|basic_block| (basic_block.address(), basic_block_prime(&basic_block)))
+        .collect()
+}
+
+/// Prime product signature of every function of a BinExport as
+/// `(address, prime)`
+pub fn function_primes(binexport: &BinExport) -> Vec<(u64, u64)> {
+    binexport
+        .functions()
+        .map(|function| (function.address(), function_prime(&function)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::{binexport, FunctionSpec};
+
+    #[test]
+    fn test_prime_signatures() {
+        let binexport = binexport(&[
+            FunctionSpec::new(
+                0x1000,
+                "foo",
+                vec![vec!["add x0, x0, #0x1"], vec!["sub x1, x1, #0x1", "ret"]],
+            )
+            .edges(&[(0, 1)]),
+            FunctionSpec::new(
+                0x2000,
+                "bar",
+                vec![vec!["sub x2, x2, #0x2", "add x3, x3, #0x2", "ret"]],
+            ),
+            FunctionSpec::new(
+                0x3000,
+                "baz",
+                vec![vec!["add x0, x0, #0x1", "add x1, x1, #0x1", "ret"]],
+            ),
+        ]);
+        let foo = binexport.function(0x1000).unwrap();
+
+        let blocks = basic_block_primes(&foo);
+        assert_eq!(blocks[0], (0x1000, mnemonic_prime("add")));
+        assert_eq!(
+            blocks[1],
+            (
+                0x1004,
+                mnemonic_prime("sub").wrapping_mul(mnemonic_prime("ret"))
+            )
+        );
+
+        // Same mnemonic multiset regardless of order and block layout
+        let primes = function_primes(&binexport);
+        assert_eq!(primes[0].1, primes[1].1);
+        assert_ne!(primes[0].1, primes[2].1);
+
+        let mnemonics = [
+            "add", "adc", "adr", "adrp", "and", "b", "bl", "blr", "br", "cbz", "cbnz", "cmp",
+            "cmn", "csel", "eor", "ldr", "ldrb", "ldrh", "ldp", "lsl", "lsr", "mov", "movk",
+            "movz", "mul", "nop", "orr", "ret", "stp", "str", "strb", "sub", "tbz", "tbnz", "call",
+            "jmp", "je", "jne", "lea", "push", "pop", "test", "xor", "imul", "int3",
+        ];
+        let mut primes: Vec<u64> = mnemonics.iter().map(|m| mnemonic_prime(m)).collect();
+        assert!(primes.iter().all(|&p| is_prime(p)));
+        primes.sort_unstable();
+        primes.dedup();
+        assert_eq!(primes.len(), mnemonics.len());
+        assert!(is_prime(18446744073709551557) && !is_prime(3215031751));
+    }
+}
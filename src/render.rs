@@ -0,0 +1,491 @@
+//! Render the primary and secondary flow graphs of a function match side by
+//! side as DOT or SVG, colored the way BinDiff's UI colors basic blocks.
+
+use crate::{BasicBlockMatch, BinExport, FunctionMatch};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::fmt::Write;
+
+const BLOCK_WIDTH: usize = 160;
+const BLOCK_HEIGHT: usize = 40;
+const H_GAP: usize = 20;
+const V_GAP: usize = 40;
+const MARGIN: usize = 20;
+const HEADER: usize = 30;
+const SIDE_GAP: usize = 80;
+
+/// How a basic block relates to the other side of the diff
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BlockState {
+    /// Matched to a block with the same instruction count
+    Matched,
+    /// Matched to a block with a different instruction count
+    Changed,
+    /// No counterpart on the other side
+    Unmatched,
+}
+
+impl BlockState {
+    /// Fill color used by both the DOT and SVG output
+    pub fn color(&self) -> &'static str {
+        match self {
+            BlockState::Matched => "#b3e6b3",
+            BlockState::Changed => "#ffff80",
+            BlockState::Unmatched => "#ff9999",
+        }
+    }
+}
+
+impl std::fmt::Display for BlockState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BlockState::Matched => write!(f, "matched"),
+            BlockState::Changed => write!(f, "changed"),
+            BlockState::Unmatched => write!(f, "unmatched"),
+        }
+    }
+}
+
+/// A basic block of one side of a flow graph diff
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DiffBlock {
+    pub address: u64,
+    pub instruction_count: usize,
+    pub state: BlockState,
+    /// Address of the matched block on the other side
+    pub partner: Option<u64>,
+    /// Disassembly of the block, one instruction per line
+    pub disassembly: Vec<String>,
+}
+
+/// One side (primary or secondary) of a flow graph diff
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DiffGraph {
+    pub address: u64,
+    pub name: String,
+    pub entry: Option<u64>,
+    /// Blocks sorted by address
+    pub blocks: Vec<DiffBlock>,
+    /// Flow graph edges as (source, target) block addresses
+    pub edges: Vec<(u64, u64)>,
+}
+
+/// Side-by-side flow graph diff of a single function match
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FlowGraphDiff {
+    pub primary: DiffGraph,
+    pub secondary: DiffGraph,
+    pub similarity: f64,
+    pub confidence: f64,
+}
+
+impl FlowGraphDiff {
+    /// Build the diff of `function_match` from its basic block matches and the
+    /// two BinExport files the BinDiff result was created from
+    pub fn new(
+        function_match: &FunctionMatch,
+        basic_block_matches: &[BasicBlockMatch],
+        primary: &BinExport,
+        secondary: &BinExport,
+    ) -> Result<Self> {
+        let forward: HashMap<u64, u64> = basic_block_matches
+            .iter()
+            .map(|m| (m.address1 as u64, m.address2 as u64))
+            .collect();
+        let backward: HashMap<u64, u64> = forward.iter().map(|(&a, &b)| (b, a)).collect();
+
+        let mut primary_graph = diff_graph(
+            primary,
+            function_match.address1 as u64,
+            &function_match.name1,
+            &forward,
+        )
+        .context("Failed to find primary function in BinExport")?;
+        let mut secondary_graph = diff_graph(
+            secondary,
+            function_match.address2 as u64,
+            &function_match.name2,
+            &backward,
+        )
+        .context("Failed to find secondary function in BinExport")?;
+
+        let primary_counts = instruction_counts(&primary_graph);
+        let secondary_counts = instruction_counts(&secondary_graph);
+        mark_changed(&mut primary_graph, &secondary_counts);
+        mark_changed(&mut secondary_graph, &primary_counts);
+
+        Ok(Self {
+            primary: primary_graph,
+            secondary: secondary_graph,
+            similarity: function_match.similarity,
+            confidence: function_match.confidence,
+        })
+    }
+
+    /// Render both flow graphs as a single Graphviz DOT digraph, with matched
+    /// blocks linked by dashed edges
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::new();
+        writeln!(dot, "digraph \"{}\" {{", escape_dot(&self.primary.name)).unwrap();
+        writeln!(
+            dot,
+            "  node [shape=box, style=filled, fontname=\"monospace\"];"
+        )
+        .unwrap();
+        for (prefix, graph) in [("p", &self.primary), ("s", &self.secondary)] {
+            writeln!(dot, "  subgraph cluster_{} {{", prefix).unwrap();
+            writeln!(
+                dot,
+                "    label=\"{} ({:#x})\";",
+                escape_dot(&graph.name),
+                graph.address
+            )
+            .unwrap();
+            for block in &graph.blocks {
+                let mut label = format!("{:#x}\\l", block.address);
+                for line in &block.disassembly {
+                    label.push_str(&escape_dot(line));
+                    label.push_str("\\l");
+                }
+                writeln!(
+                    dot,
+                    "    {}_{:x} [label=\"{}\", fillcolor=\"{}\"];",
+                    prefix,
+                    block.address,
+                    label,
+                    block.state.color()
+                )
+                .unwrap();
+            }
+            for (source, target) in &graph.edges {
+                writeln!(
+                    dot,
+                    "    {}_{:x} -> {}_{:x};",
+                    prefix, source, prefix, target
+                )
+                .unwrap();
+            }
+            writeln!(dot, "  }}").unwrap();
+        }
+        for block in &self.primary.blocks {
+            if let Some(partner) = block.partner {
+                writeln!(
+                    dot,
+                    "  p_{:x} -> s_{:x} [style=dashed, color=gray, dir=none, constraint=false];",
+                    block.address, partner
+                )
+                .unwrap();
+            }
+        }
+        writeln!(dot, "}}").unwrap();
+        dot
+    }
+
+    /// Render both flow graphs as a standalone SVG image using a simple
+    /// layered layout, so no Graphviz installation is required
+    pub fn to_svg(&self) -> String {
+        let primary = layout(&self.primary);
+        let secondary = layout(&self.secondary);
+        let primary_width = side_width(&primary);
+        let secondary_x = MARGIN + primary_width + SIDE_GAP;
+        let width = secondary_x + side_width(&secondary) + MARGIN;
+        let height = HEADER + MARGIN * 2 + side_height(&primary).max(side_height(&secondary));
+
+        let mut svg = String::new();
+        writeln!(
+            svg,
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\" \
+             font-family=\"monospace\" font-size=\"11\">",
+            width, height
+        )
+        .unwrap();
+        writeln!(
+            svg,
+            "<defs><marker id=\"arrow\" viewBox=\"0 0 10 10\" refX=\"10\" refY=\"5\" \
+             markerWidth=\"6\" markerHeight=\"6\" orient=\"auto\">\
+             <path d=\"M0,0 L10,5 L0,10 z\" fill=\"#444\"/></marker></defs>"
+        )
+        .unwrap();
+
+        let primary_positions = positions(&primary, MARGIN);
+        let secondary_positions = positions(&secondary, secondary_x);
+
+        for (graph, x, positions) in [
+            (&self.primary, MARGIN, &primary_positions),
+            (&self.secondary, secondary_x, &secondary_positions),
+        ] {
+            writeln!(
+                svg,
+                "<text x=\"{}\" y=\"{}\" font-weight=\"bold\">{} ({:#x})</text>",
+                x,
+                MARGIN,
+                escape_xml(&graph.name),
+                graph.address
+            )
+            .unwrap();
+            for (source, target) in &graph.edges {
+                if let (Some(&(x1, y1)), Some(&(x2, y2))) =
+                    (positions.get(source), positions.get(target))
+                {
+                    writeln!(
+                        svg,
+                        "<line x1=\"{}\" y1=\"{}\" x2=\"{}\" y2=\"{}\" stroke=\"#444\" \
+                         marker-end=\"url(#arrow)\"/>",
+                        x1 + BLOCK_WIDTH / 2,
+                        y1 + BLOCK_HEIGHT,
+                        x2 + BLOCK_WIDTH / 2,
+                        y2
+                    )
+                    .unwrap();
+                }
+            }
+            for block in &graph.blocks {
+                let (bx, by) = positions[&block.address];
+                writeln!(
+                    svg,
+                    "<g><title>{}</title><rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" \
+                     fill=\"{}\" stroke=\"#333\"/>\
+                     <text x=\"{}\" y=\"{}\">{:#x}</text>\
+                     <text x=\"{}\" y=\"{}\">{} instructions</text></g>",
+                    escape_xml(&block.disassembly.join("\n")),
+                    bx,
+                    by,
+                    BLOCK_WIDTH,
+                    BLOCK_HEIGHT,
+                    block.state.color(),
+                    bx + 6,
+                    by + 16,
+                    block.address,
+                    bx + 6,
+                    by + 32,
+                    block.instruction_count
+                )
+                .unwrap();
+            }
+        }
+
+        for block in &self.primary.blocks {
+            let Some(partner) = block.partner else {
+                continue;
+            };
+            if let (Some(&(x1, y1)), Some(&(x2, y2))) = (
+                primary_positions.get(&block.address),
+                secondary_positions.get(&partner),
+            ) {
+                writeln!(
+                    svg,
+                    "<line x1=\"{}\" y1=\"{}\" x2=\"{}\" y2=\"{}\" stroke=\"#999\" \
+                     stroke-dasharray=\"4,4\" stroke-opacity=\"0.5\"/>",
+                    x1 + BLOCK_WIDTH,
+                    y1 + BLOCK_HEIGHT / 2,
+                    x2,
+                    y2 + BLOCK_HEIGHT / 2
+                )
+                .unwrap();
+            }
+        }
+
+        writeln!(svg, "</svg>").unwrap();
+        svg
+    }
+}
+
+fn diff_graph(
+    binexport: &BinExport,
+    address: u64,
+    fallback_name: &str,
+    matches: &HashMap<u64, u64>,
+) -> Option<DiffGraph> {
+    let function = binexport.function(address)?;
+    let blocks = function
+        .basic_blocks()
+        .map(|basic_block| {
+            let partner = matches.get(&basic_block.address()).copied();
+            DiffBlock {
+                address: basic_block.address(),
+                instruction_count: basic_block.instruction_count(),
+                state: if partner.is_some() {
+                    BlockState::Matched
+                } else {
+                    BlockState::Unmatched
+                },
+                partner,
+                disassembly: basic_block
+                    .instructions()
+                    .map(|instruction| instruction.to_string())
+                    .collect(),
+            }
+        })
+        .collect();
+    let edges = function
+        .edges()
+        .map(|edge| (edge.source.address(), edge.target.address()))
+        .collect();
+
+    Some(DiffGraph {
+        address,
+        name: function.name().unwrap_or(fallback_name).to_string(),
+        entry: function.entry_basic_block().map(|block| block.address()),
+        blocks,
+        edges,
+    })
+}
+
+fn instruction_counts(graph: &DiffGraph) -> HashMap<u64, usize> {
+    graph
+        .blocks
+        .iter()
+        .map(|block| (block.address, block.instruction_count))
+        .collect()
+}
+
+fn mark_changed(graph: &mut DiffGraph, other_counts: &HashMap<u64, usize>) {
+    for block in &mut graph.blocks {
+        if let Some(partner) = block.partner {
+            if other_counts.get(&partner) != Some(&block.instruction_count) {
+                block.state = BlockState::Changed;
+            }
+        }
+    }
+}
+
+/// Assign each block to a layer by breadth-first distance from the entry
+/// block; unreachable blocks go to a final layer
+fn layout(graph: &DiffGraph) -> Vec<Vec<u64>> {
+    let mut successors: HashMap<u64, Vec<u64>> = HashMap::new();
+    for &(source, target) in &graph.edges {
+        successors.entry(source).or_default().push(target);
+    }
+
+    let mut level: HashMap<u64, usize> = HashMap::new();
+    let mut queue = VecDeque::new();
+    if let Some(entry) = graph.entry {
+        level.insert(entry, 0);
+        queue.push_back(entry);
+    }
+    while let Some(address) = queue.pop_front() {
+        let next = level[&address] + 1;
+        for &target in successors.get(&address).into_iter().flatten() {
+            if let std::collections::hash_map::Entry::Vacant(e) = level.entry(target) {
+                e.insert(next);
+                queue.push_back(target);
+            }
+        }
+    }
+
+    let unreachable = level.values().max().map_or(0, |max| max + 1);
+    let mut layers: Vec<Vec<u64>> = Vec::new();
+    for block in &graph.blocks {
+        let index = level.get(&block.address).copied().unwrap_or(unreachable);
+        if layers.len() <= index {
+            layers.resize(index + 1, Vec::new());
+        }
+        layers[index].push(block.address);
+    }
+    layers.retain(|layer| !layer.is_empty());
+    layers
+}
+
+fn side_width(layers: &[Vec<u64>]) -> usize {
+    let widest = layers.iter().map(Vec::len).max().unwrap_or(0);
+    (widest * (BLOCK_WIDTH + H_GAP)).saturating_sub(H_GAP)
+}
+
+fn side_height(layers: &[Vec<u64>]) -> usize {
+    (layers.len() * (BLOCK_HEIGHT + V_GAP)).saturating_sub(V_GAP)
+}
+
+fn positions(layers: &[Vec<u64>], x: usize) -> HashMap<u64, (usize, usize)> {
+    let width = side_width(layers);
+    let mut positions = HashMap::new();
+    for (row, layer) in layers.iter().enumerate() {
+        let layer_width = layer.len() * (BLOCK_WIDTH + H_GAP) - H_GAP;
+        let offset = x + (width - layer_width) / 2;
+        for (column, &address) in layer.iter().enumerate() {
+            positions.insert(
+                address,
+                (
+                    offset + column * (BLOCK_WIDTH + H_GAP),
+                    HEADER + MARGIN + row * (BLOCK_HEIGHT + V_GAP),
+                ),
+            );
+        }
+    }
+    positions
+}
+
+fn escape_dot(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+pub(crate) fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::{binexport, function_match, FunctionSpec};
+    use crate::BasicBlockAlgorithm;
+
+    fn block_match(address1: i64, address2: i64) -> BasicBlockMatch {
+        BasicBlockMatch {
+            id: 0,
+            function_id: 1,
+            address1,
+            address2,
+            algorithm: BasicBlockAlgorithm::EdgesPrimeProduct,
+            evaluate: false,
+        }
+    }
+
+    #[test]
+    fn test_flow_graph_diff_states() -> Result<()> {
+        let primary = binexport(&[FunctionSpec::new(
+            0x1000,
+            "foo",
+            vec![vec!["cmp x0, x1", "b.eq"], vec!["ret"], vec!["brk"]],
+        )
+        .edges(&[(0, 1), (0, 2)])]);
+        let secondary = binexport(&[FunctionSpec::new(
+            0x2000,
+            "foo",
+            vec![vec!["cmp x0, x1", "b.eq"], vec!["mov x0, x2", "ret"]],
+        )
+        .edges(&[(0, 1)])]);
+
+        let diff = FlowGraphDiff::new(
+            &FunctionMatch {
+                similarity: 0.5,
+                confidence: 0.9,
+                ..function_match(0x1000, 0x2000)
+            },
+            &[block_match(0x1000, 0x2000), block_match(0x1008, 0x2008)],
+            &primary,
+            &secondary,
+        )?;
+
+        let states: Vec<BlockState> = diff.primary.blocks.iter().map(|b| b.state).collect();
+        assert_eq!(
+            states,
+            [
+                BlockState::Matched,
+                BlockState::Changed,
+                BlockState::Unmatched
+            ]
+        );
+        assert_eq!(diff.secondary.blocks[1].state, BlockState::Changed);
+        assert_eq!(diff.primary.blocks[0].disassembly, ["cmp x0, x1", "b.eq"]);
+
+        let dot = diff.to_dot();
+        assert!(dot.contains("p_100c [label=\"0x100c\\lbrk\\l\", fillcolor=\"#ff9999\"]"));
+        assert!(dot.contains("p_1000 -> s_2000 [style=dashed"));
+        assert!(diff.to_svg().starts_with("<svg"));
+
+        Ok(())
+    }
+}
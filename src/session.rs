@@ -0,0 +1,351 @@
+//! A BinDiff result together with the primary and secondary BinExport files it
+//! was created from.
+
+use crate::comments::{self, PortedComment};
+use crate::evaluation::{self, Evaluation};
+use crate::flowgraph::Function;
+use crate::instruction_diff::InstructionDiff;
+use crate::render::FlowGraphDiff;
+use crate::unmatched::{self, UnmatchedBasicBlocks, UnmatchedFunctions};
+use crate::{BasicBlockMatch, BinDiff, BinExport, File, FunctionMatch, Instruction, Metadata};
+use anyhow::{bail, Context, Result};
+use std::path::Path;
+
+/// Joins a `.BinDiff` database with its two `.BinExport` inputs
+pub struct DiffSession {
+    bindiff: BinDiff,
+    primary: BinExport,
+    secondary: BinExport,
+    metadata: Metadata,
+    primary_file: File,
+    secondary_file: File,
+}
+
+impl DiffSession {
+    /// Open a BinDiff database and the primary and secondary BinExport files,
+    /// verifying that they belong together
+    pub fn open<P, Q, R>(bindiff: P, primary: Q, secondary: R) -> Result<Self>
+    where
+        P: AsRef<Path>,
+        Q: AsRef<Path>,
+        R: AsRef<Path>,
+    {
+        Self::new(
+            BinDiff::open(bindiff)?,
+            BinExport::open(primary)?,
+            BinExport::open(secondary)?,
+        )
+    }
+
+    /// Join already opened files, verifying that they belong together
+    pub fn new(bindiff: BinDiff, primary: BinExport, secondary: BinExport) -> Result<Self> {
+        let metadata = bindiff.read_metadata()?;
+        let primary_file = bindiff.read_file_by_id(metadata.file1)?;
+        let secondary_file = bindiff.read_file_by_id(metadata.file2)?;
+
+        verify(&primary_file, &primary).context("Primary BinExport does not match BinDiff")?;
+        verify(&secondary_file, &secondary)
+            .context("Secondary BinExport does not match BinDiff")?;
+
+        Ok(Self {
+            bindiff,
+            primary,
+            secondary,
+            metadata,
+            primary_file,
+            secondary_file,
+        })
+    }
+
+    pub fn close(self) -> std::result::Result<(), rusqlite::Error> {
+        self.bindiff.close()
+    }
+
+    pub fn bindiff(&self) -> &BinDiff {
+        &self.bindiff
+    }
+
+    pub fn primary(&self) -> &BinExport {
+        &self.primary
+    }
+
+    pub fn secondary(&self) -> &BinExport {
+        &self.secondary
+    }
+
+    pub fn metadata(&self) -> &Metadata {
+        &self.metadata
+    }
+
+    pub fn primary_file(&self) -> &File {
+        &self.primary_file
+    }
+
+    pub fn secondary_file(&self) -> &File {
+        &self.secondary_file
+    }
+
+    /// Read all function matches, resolved against both BinExport files;
+    /// matches whose functions are missing from either file are set aside in
+    /// `unresolved` instead of failing the whole read
+    pub fn function_matches(&self) -> Result<FunctionMatches<'_>> {
+        let mut function_matches = FunctionMatches::default();
+        for function_match in self.bindiff.read_function_matches()? {
+            if self
+                .primary
+                .function(function_match.address1 as u64)
+                .is_some()
+                && self
+                    .secondary
+                    .function(function_match.address2 as u64)
+                    .is_some()
+            {
+                function_matches.matched.push(self.resolve(function_match)?);
+            } else {
+                function_matches.unresolved.push(function_match);
+            }
+        }
+        Ok(function_matches)
+    }
+
+    /// Look up the function match for the primary function at `address`
+    pub fn function_match(&self, address: u64) -> Result<Option<MatchedFunction<'_>>> {
+        self.bindiff
+            .read_function_match(address)?
+            .map(|function_match| self.resolve(function_match))
+            .transpose()
+    }
+
+    /// Look up a function match by primary or secondary address (`0x` prefixed
+    /// hex) or by primary or secondary name
+    pub fn find_function_match(&self, query: &str) -> Result<Option<MatchedFunction<'_>>> {
+        self.bindiff
+            .find_function_match(query)?
+            .map(|function_match| self.resolve(function_match))
+            .transpose()
+    }
+
+    /// Functions of either binary that BinDiff did not match
+    pub fn unmatched_functions(&self) -> Result<UnmatchedFunctions> {
+        let matches = self.bindiff.read_function_matches()?;
+        Ok(unmatched::unmatched_functions(
+            &self.primary,
+            &self.secondary,
+            &matches,
+        ))
+    }
+
+    /// Score the function matches against the function names of both
+    /// BinExport files, for diffs of symbolized builds
+    pub fn evaluate(&self) -> Result<Evaluation> {
+        let matches = self.bindiff.read_function_matches()?;
+        Ok(evaluation::evaluate(
+            &self.primary,
+            &self.secondary,
+            &matches,
+        ))
+    }
+
+    /// Comments of the primary BinExport placed at their matched secondary
+    /// addresses
+    pub fn port_comments(&self) -> Result<Vec<PortedComment>> {
+        Ok(comments::port_comments(
+            &self.primary,
+            &self.bindiff.read_function_matches()?,
+            &self.bindiff.read_basic_block_matches()?,
+            &self.bindiff.read_instruction_matches()?,
+        ))
+    }
+
+    fn resolve(&self, function_match: FunctionMatch) -> Result<MatchedFunction<'_>> {
+        let primary = self
+            .primary
+            .function(function_match.address1 as u64)
+            .with_context(|| {
+                format!(
+                    "Primary function {:#x} not found in BinExport",
+                    function_match.address1
+                )
+            })?;
+        let secondary = self
+            .secondary
+            .function(function_match.address2 as u64)
+            .with_context(|| {
+                format!(
+                    "Secondary function {:#x} not found in BinExport",
+                    function_match.address2
+                )
+            })?;
+        Ok(MatchedFunction {
+            session: self,
+            function_match,
+            primary,
+            secondary,
+        })
+    }
+}
+
+/// The function matches of a session, split by whether both functions were
+/// found in the BinExport files
+#[derive(Default)]
+pub struct FunctionMatches<'a> {
+    pub matched: Vec<MatchedFunction<'a>>,
+    pub unresolved: Vec<FunctionMatch>,
+}
+
+/// A function match with access to both sides' flow graphs
+pub struct MatchedFunction<'a> {
+    session: &'a DiffSession,
+    pub function_match: FunctionMatch,
+    pub primary: Function<'a>,
+    pub secondary: Function<'a>,
+}
+
+impl MatchedFunction<'_> {
+    pub fn basic_block_matches(&self) -> Result<Vec<BasicBlockMatch>> {
+        self.session
+            .bindiff
+            .read_basic_block_matches_for_function(self.function_match.id)
+    }
+
+    pub fn instruction_matches(&self) -> Result<Vec<Instruction>> {
+        self.session
+            .bindiff
+            .read_instruction_matches_for_function(self.function_match.id)
+    }
+
+    /// Basic blocks and instructions of this match without a counterpart
+    pub fn unmatched_basic_blocks(&self) -> Result<UnmatchedBasicBlocks> {
+        Ok(unmatched::unmatched_basic_blocks(
+            &self.primary,
+            &self.secondary,
+            &self.basic_block_matches()?,
+            &self.instruction_matches()?,
+        ))
+    }
+
+    /// Build the side-by-side instruction diff of this match
+    pub fn instruction_diff(&self) -> Result<InstructionDiff> {
+        InstructionDiff::new(
+            &self.function_match,
+            &self.basic_block_matches()?,
+            &self.instruction_matches()?,
+            &self.session.primary,
+            &self.session.secondary,
+        )
+    }
+
+    /// Build the side-by-side flow graph diff of this match
+    pub fn flow_graph_diff(&self) -> Result<FlowGraphDiff> {
+        FlowGraphDiff::new(
+            &self.function_match,
+            &self.basic_block_matches()?,
+            &self.session.primary,
+            &self.session.secondary,
+        )
+    }
+}
+
+/// Check that a BinDiff file row describes the given BinExport, by hash when
+/// both sides record one and by executable name otherwise
+fn verify(file: &File, binexport: &BinExport) -> Result<()> {
+    let executable_id = binexport.executable_id()?;
+    if !file.hash.is_empty() && !executable_id.is_empty() {
+        if !file.hash.eq_ignore_ascii_case(&executable_id) {
+            bail!(
+                "hash mismatch: BinDiff has {}, BinExport has {}",
+                file.hash,
+                executable_id
+            );
+        }
+        return Ok(());
+    }
+
+    let executable_name = binexport.executable_name()?;
+    let basename = Path::new(&executable_name)
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or(&executable_name);
+    if file.exe_filename != executable_name && file.exe_filename != basename {
+        bail!(
+            "executable name mismatch: BinDiff has {}, BinExport has {}",
+            file.exe_filename,
+            executable_name
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::{binexport, function_match, FunctionSpec};
+
+    fn file(exe_filename: &str, hash: &str) -> File {
+        File {
+            id: 1,
+            filename: "test".to_string(),
+            exe_filename: exe_filename.to_string(),
+            hash: hash.to_string(),
+            functions: 1,
+            lib_functions: 0,
+            calls: 0,
+            basic_blocks: 1,
+            lib_basic_blocks: 0,
+            edges: 0,
+            lib_edges: 0,
+            instructions: 1,
+            lib_instructions: 0,
+        }
+    }
+
+    #[test]
+    fn test_verify() {
+        let binexport = binexport(&[FunctionSpec::new(0x1000, "foo", vec![vec!["ret"]])]);
+
+        assert!(verify(&file("test", "0123456789ABCDEF"), &binexport).is_ok());
+        assert!(verify(&file("other", "0123456789abcdef"), &binexport).is_ok());
+        assert!(verify(&file("test", "deadbeef"), &binexport).is_err());
+        assert!(verify(&file("test", ""), &binexport).is_ok());
+        assert!(verify(&file("other", ""), &binexport).is_err());
+    }
+
+    #[test]
+    fn test_function_matches() -> Result<()> {
+        let path =
+            std::env::temp_dir().join(format!("bindiff-rs-session-{}.BinDiff", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let db = BinDiff::create(&path)?;
+        db.write_file(&file("test", "0123456789abcdef"))?;
+        db.write_metadata(&Metadata {
+            version: "BinDiff 8".to_string(),
+            file1: 1,
+            file2: 1,
+            description: String::new(),
+            created: chrono::Utc::now(),
+            modified: chrono::Utc::now(),
+            similarity: 1.0,
+            confidence: 1.0,
+        })?;
+        db.write_function_matches(&[
+            function_match(0x1000, 0x1000),
+            FunctionMatch {
+                id: 2,
+                ..function_match(0x2000, 0x2000)
+            },
+        ])?;
+        let functions = [FunctionSpec::new(0x1000, "foo", vec![vec!["ret"]])];
+        let session = DiffSession::new(db, binexport(&functions), binexport(&functions))?;
+
+        let function_matches = session.function_matches()?;
+        assert_eq!(function_matches.matched.len(), 1);
+        assert_eq!(function_matches.unresolved[0].address1, 0x2000);
+        assert!(session.function_match(0x1000)?.is_some());
+        assert!(session.function_match(0x3000)?.is_none());
+        assert!(session.function_match(0x2000).is_err());
+
+        session.close()?;
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+}
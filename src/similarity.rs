@@ -0,0 +1,337 @@
+//! Similarity and confidence scores computed from match counts and both
+//! BinExport files, for rescoring a BinDiff result independently of the
+//! values BinDiff wrote.
+
+use crate::config::MatchingConfig;
+use crate::flowgraph::Function;
+use crate::md_index::{self, Direction};
+use crate::session::DiffSession;
+use crate::{BasicBlockMatch, File, FunctionMatch, Instruction, Metadata};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+/// Map an average step confidence onto (0, 1) the way BinDiff does
+pub fn confidence_sigmoid(confidence: f64) -> f64 {
+    1.0 / (1.0 + (-(confidence - 0.5) * 10.0).exp())
+}
+
+/// Combine the edge, basic block and instruction shares of a match the way
+/// BinDiff weighs them
+pub(crate) fn weighted_share(edges: f64, basic_blocks: f64, instructions: f64) -> f64 {
+    0.55 * edges + 0.30 * basic_blocks + 0.15 * instructions
+}
+
+/// Closeness of two flow graph MD indices, 1.0 if they are equal
+pub(crate) fn md_index_similarity(md1: f64, md2: f64) -> f64 {
+    1.0 - (md1 - md2).abs() / (1.0 + md1 + md2)
+}
+
+/// Share of `a` and `b` that is matched, 1.0 if both are empty
+fn ratio(matched: f64, a: f64, b: f64) -> f64 {
+    if a + b == 0.0 {
+        1.0
+    } else {
+        (2.0 * matched / (a + b)).min(1.0)
+    }
+}
+
+/// Similarity of two matched functions from the share of their edges, basic
+/// blocks and instructions that were matched and the distance of their flow
+/// graph MD indices
+pub fn function_similarity(
+    function_match: &FunctionMatch,
+    primary: &Function,
+    secondary: &Function,
+) -> f64 {
+    let edges = ratio(
+        function_match.edges as f64,
+        primary.edge_count() as f64,
+        secondary.edge_count() as f64,
+    );
+    let basic_blocks = ratio(
+        function_match.basic_blocks as f64,
+        primary.basic_block_count() as f64,
+        secondary.basic_block_count() as f64,
+    );
+    let instructions = ratio(
+        function_match.instructions as f64,
+        primary.instruction_count() as f64,
+        secondary.instruction_count() as f64,
+    );
+    let md1 = md_index::function_md_index(primary, Direction::TopDown);
+    let md2 = md_index::function_md_index(secondary, Direction::TopDown);
+    (weighted_share(edges, basic_blocks, instructions) + md_index_similarity(md1, md2)) / 2.0
+}
+
+/// Confidence of a function match: the confidence of the step that matched
+/// the function averaged with those of the steps that matched its basic
+/// blocks, mapped through the sigmoid
+pub fn function_confidence(
+    function_match: &FunctionMatch,
+    basic_block_matches: &[BasicBlockMatch],
+    config: &MatchingConfig,
+) -> f64 {
+    let confidence = config.function_confidence(&function_match.algorithm)
+        + basic_block_matches
+            .iter()
+            .map(|m| config.basic_block_confidence(&m.algorithm))
+            .sum::<f64>();
+    confidence_sigmoid(confidence / (1 + basic_block_matches.len()) as f64)
+}
+
+/// Number of primary flow graph edges whose endpoints are matched to the
+/// endpoints of a secondary edge
+pub fn matched_edges(
+    primary: &Function,
+    secondary: &Function,
+    basic_block_matches: &[BasicBlockMatch],
+) -> usize {
+    let partner: HashMap<u64, u64> = basic_block_matches
+        .iter()
+        .map(|m| (m.address1 as u64, m.address2 as u64))
+        .collect();
+    let secondary_edges: HashSet<(u64, u64)> = secondary
+        .edges()
+        .map(|edge| (edge.source.address(), edge.target.address()))
+        .collect();
+    primary
+        .edges()
+        .filter(|edge| {
+            match (
+                partner.get(&edge.source.address()),
+                partner.get(&edge.target.address()),
+            ) {
+                (Some(&source), Some(&target)) => secondary_edges.contains(&(source, target)),
+                _ => false,
+            }
+        })
+        .count()
+}
+
+/// Overall similarity of two binaries from the share of their edges, basic
+/// blocks and instructions, library functions included, covered by function
+/// matches
+pub fn overall_similarity(
+    primary: &File,
+    secondary: &File,
+    function_matches: &[FunctionMatch],
+) -> f64 {
+    if function_matches.is_empty() {
+        return 0.0;
+    }
+    let matched = |get: fn(&FunctionMatch) -> i64| -> f64 {
+        function_matches.iter().map(get).sum::<i64>() as f64
+    };
+    let edges = ratio(
+        matched(|m| m.edges),
+        (primary.edges + primary.lib_edges) as f64,
+        (secondary.edges + secondary.lib_edges) as f64,
+    );
+    let basic_blocks = ratio(
+        matched(|m| m.basic_blocks),
+        (primary.basic_blocks + primary.lib_basic_blocks) as f64,
+        (secondary.basic_blocks + secondary.lib_basic_blocks) as f64,
+    );
+    let instructions = ratio(
+        matched(|m| m.instructions),
+        (primary.instructions + primary.lib_instructions) as f64,
+        (secondary.instructions + secondary.lib_instructions) as f64,
+    );
+    weighted_share(edges, basic_blocks, instructions)
+}
+
+/// Overall confidence, the average confidence of all function matches
+pub fn overall_confidence(function_matches: &[FunctionMatch]) -> f64 {
+    if function_matches.is_empty() {
+        return 0.0;
+    }
+    function_matches.iter().map(|m| m.confidence).sum::<f64>() / function_matches.len() as f64
+}
+
+/// Recompute the matched counts, similarity and confidence of a function
+/// match from its basic block and instruction matches
+pub fn rescore_function_match(
+    function_match: &FunctionMatch,
+    primary: &Function,
+    secondary: &Function,
+    basic_block_matches: &[BasicBlockMatch],
+    instruction_matches: &[Instruction],
+    config: &MatchingConfig,
+) -> FunctionMatch {
+    let mut function_match = FunctionMatch {
+        basic_blocks: basic_block_matches.len() as i64,
+        edges: matched_edges(primary, secondary, basic_block_matches) as i64,
+        instructions: instruction_matches.len() as i64,
+        ..function_match.clone()
+    };
+    function_match.similarity = function_similarity(&function_match, primary, secondary);
+    function_match.confidence = function_confidence(&function_match, basic_block_matches, config);
+    function_match
+}
+
+/// Function matches and metadata of a session with recomputed scores
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Rescore {
+    pub metadata: Metadata,
+    pub function_matches: Vec<FunctionMatch>,
+    /// Matches whose functions are missing from a BinExport file, kept with
+    /// their stored scores
+    pub unresolved: Vec<FunctionMatch>,
+}
+
+impl std::fmt::Display for Rescore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{}", self.metadata)?;
+        for function_match in &self.function_matches {
+            writeln!(f, "{}", function_match)?;
+        }
+        if !self.unresolved.is_empty() {
+            writeln!(f, "UNRESOLVED ({}):", self.unresolved.len())?;
+            for function_match in &self.unresolved {
+                writeln!(f, "  {}", function_match)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Recompute every function match of a session and the overall scores, using
+/// the step confidences of `config`; matches that cannot be resolved against
+/// the BinExport files count towards the overall scores unchanged
+pub fn rescore(session: &DiffSession, config: &MatchingConfig) -> Result<Rescore> {
+    let session_matches = session.function_matches()?;
+    let function_matches = session_matches
+        .matched
+        .iter()
+        .map(|matched| {
+            Ok(rescore_function_match(
+                &matched.function_match,
+                &matched.primary,
+                &matched.secondary,
+                &matched.basic_block_matches()?,
+                &matched.instruction_matches()?,
+                config,
+            ))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let unresolved = session_matches.unresolved;
+    let all: Vec<FunctionMatch> = function_matches
+        .iter()
+        .chain(&unresolved)
+        .cloned()
+        .collect();
+    let metadata = Metadata {
+        similarity: overall_similarity(session.primary_file(), session.secondary_file(), &all),
+        confidence: overall_confidence(&all),
+        ..session.metadata().clone()
+    };
+    Ok(Rescore {
+        metadata,
+        function_matches,
+        unresolved,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::{binexport, function_match, FunctionSpec};
+    use crate::BasicBlockAlgorithm;
+
+    #[test]
+    fn test_rescore_function_match() {
+        let primary = binexport(&[FunctionSpec::new(
+            0x1000,
+            "foo",
+            vec![vec!["cbz x0"], vec!["mov x0, #0x1"], vec!["ret"]],
+        )
+        .edges(&[(0, 1), (0, 2), (1, 2)])]);
+        let secondary = binexport(&[FunctionSpec::new(
+            0x2000,
+            "foo",
+            vec![vec!["cbz x0"], vec!["mov x0, #0x1"], vec!["ret"]],
+        )
+        .edges(&[(0, 1), (0, 2), (1, 2)])]);
+        let primary = primary.function(0x1000).unwrap();
+        let secondary = secondary.function(0x2000).unwrap();
+
+        let function_match = function_match(0x1000, 0x2000);
+        let block = |address1: i64, address2: i64| BasicBlockMatch {
+            id: 1,
+            function_id: 1,
+            address1,
+            address2,
+            algorithm: BasicBlockAlgorithm::EdgesPrimeProduct,
+            evaluate: false,
+        };
+        let instruction = |address1: i64, address2: i64| Instruction {
+            id: 1,
+            address1,
+            address2,
+        };
+        let config = MatchingConfig::default();
+
+        let blocks = [
+            block(0x1000, 0x2000),
+            block(0x1004, 0x2004),
+            block(0x1008, 0x2008),
+        ];
+        let instructions = [
+            instruction(0x1000, 0x2000),
+            instruction(0x1004, 0x2004),
+            instruction(0x1008, 0x2008),
+        ];
+        let rescored = rescore_function_match(
+            &function_match,
+            &primary,
+            &secondary,
+            &blocks,
+            &instructions,
+            &config,
+        );
+        assert_eq!((rescored.basic_blocks, rescored.edges), (3, 3));
+        assert_eq!(rescored.similarity, 1.0);
+        assert_eq!(rescored.confidence, confidence_sigmoid(1.0));
+
+        // Dropping the middle block loses two edges and an instruction
+        let partial = rescore_function_match(
+            &function_match,
+            &primary,
+            &secondary,
+            &[blocks[0].clone(), blocks[2].clone()],
+            &[instructions[0].clone(), instructions[2].clone()],
+            &config,
+        );
+        assert_eq!((partial.basic_blocks, partial.edges), (2, 1));
+        assert!(partial.similarity < rescored.similarity);
+    }
+
+    #[test]
+    fn test_overall_similarity() {
+        // Half of each binary is library code that matches cover as well
+        let file = File {
+            id: 1,
+            filename: "test".to_string(),
+            exe_filename: "test".to_string(),
+            hash: String::new(),
+            functions: 2,
+            lib_functions: 2,
+            calls: 0,
+            basic_blocks: 4,
+            lib_basic_blocks: 4,
+            edges: 4,
+            lib_edges: 4,
+            instructions: 8,
+            lib_instructions: 8,
+        };
+        let function_match = FunctionMatch {
+            basic_blocks: 4,
+            edges: 4,
+            instructions: 8,
+            ..function_match(0x1000, 0x2000)
+        };
+        assert_eq!(overall_similarity(&file, &file, &[function_match]), 0.5);
+        assert_eq!(overall_similarity(&file, &file, &[]), 0.0);
+    }
+}
@@ -0,0 +1,348 @@
+//! Aggregate statistics of a BinDiff result: score histograms, matches per
+//! matching step and how much of each binary the matches cover.
+
+use crate::{BasicBlockAlgorithm, BinDiff, File, FunctionAlgorithm, FunctionFilter};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Number of equal-width histogram buckets between 0.0 and 1.0
+const BUCKETS: usize = 10;
+
+/// Matches with a score in `[min, max)`, or `[min, max]` for the last bucket
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Bucket {
+    pub min: f64,
+    pub max: f64,
+    pub count: usize,
+}
+
+impl std::fmt::Display for Bucket {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:.1}-{:.1}\t{}", self.min, self.max, self.count)
+    }
+}
+
+fn histogram(scores: &[usize; BUCKETS]) -> Vec<Bucket> {
+    scores
+        .iter()
+        .enumerate()
+        .map(|(index, &count)| Bucket {
+            min: index as f64 / BUCKETS as f64,
+            max: (index + 1) as f64 / BUCKETS as f64,
+            count,
+        })
+        .collect()
+}
+
+fn bucket(score: f64) -> usize {
+    ((score * BUCKETS as f64).floor().max(0.0) as usize).min(BUCKETS - 1)
+}
+
+/// Number of matches found by one matching step
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AlgorithmCount<A> {
+    pub algorithm: A,
+    pub count: usize,
+}
+
+impl<A: std::fmt::Display> std::fmt::Display for AlgorithmCount<A> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}\t{}", self.algorithm, self.count)
+    }
+}
+
+/// Matched share of one kind of item of a binary
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Coverage {
+    pub matched: usize,
+    pub total: usize,
+    pub ratio: f64,
+}
+
+impl Coverage {
+    fn new(matched: usize, total: i64) -> Self {
+        let total = total.max(0) as usize;
+        Self {
+            matched,
+            total,
+            ratio: if total == 0 {
+                0.0
+            } else {
+                matched as f64 / total as f64
+            },
+        }
+    }
+}
+
+impl std::fmt::Display for Coverage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}/{} ({:.1}%)",
+            self.matched,
+            self.total,
+            self.ratio * 100.0
+        )
+    }
+}
+
+/// Match coverage of one binary, relative to its `File` totals including
+/// library functions
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileCoverage {
+    pub filename: String,
+    pub functions: Coverage,
+    pub basic_blocks: Coverage,
+    pub instructions: Coverage,
+}
+
+impl FileCoverage {
+    fn new(file: &File, functions: usize, basic_blocks: usize, instructions: usize) -> Self {
+        Self {
+            filename: file.filename.clone(),
+            functions: Coverage::new(functions, file.functions + file.lib_functions),
+            basic_blocks: Coverage::new(basic_blocks, file.basic_blocks + file.lib_basic_blocks),
+            instructions: Coverage::new(instructions, file.instructions + file.lib_instructions),
+        }
+    }
+}
+
+impl std::fmt::Display for FileCoverage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}\tfunctions: {}, basic_blocks: {}, instructions: {}",
+            self.filename, self.functions, self.basic_blocks, self.instructions
+        )
+    }
+}
+
+/// Aggregate statistics of the match tables of a BinDiff result
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Stats {
+    pub function_matches: usize,
+    pub basic_block_matches: usize,
+    pub instruction_matches: usize,
+    /// Function matches with a similarity of 1.0
+    pub identical: usize,
+    /// Function matches whose primary and secondary names differ
+    pub renamed: usize,
+    pub similarity: Vec<Bucket>,
+    pub confidence: Vec<Bucket>,
+    /// Function matches per step, most frequent first
+    pub function_algorithms: Vec<AlgorithmCount<FunctionAlgorithm>>,
+    /// Basic block matches per step, most frequent first
+    pub basic_block_algorithms: Vec<AlgorithmCount<BasicBlockAlgorithm>>,
+    pub primary: FileCoverage,
+    pub secondary: FileCoverage,
+}
+
+impl std::fmt::Display for Stats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "functions: {}, basic_blocks: {}, instructions: {}, identical: {}, renamed: {}",
+            self.function_matches,
+            self.basic_block_matches,
+            self.instruction_matches,
+            self.identical,
+            self.renamed
+        )?;
+        writeln!(f, "COVERAGE:")?;
+        writeln!(f, "  primary:   {}", self.primary)?;
+        writeln!(f, "  secondary: {}", self.secondary)?;
+        writeln!(f, "SIMILARITY:")?;
+        for bucket in &self.similarity {
+            writeln!(f, "  {}", bucket)?;
+        }
+        writeln!(f, "CONFIDENCE:")?;
+        for bucket in &self.confidence {
+            writeln!(f, "  {}", bucket)?;
+        }
+        writeln!(
+            f,
+            "FUNCTION ALGORITHMS ({}):",
+            self.function_algorithms.len()
+        )?;
+        for algorithm in &self.function_algorithms {
+            writeln!(f, "  {}", algorithm)?;
+        }
+        writeln!(
+            f,
+            "BASIC BLOCK ALGORITHMS ({}):",
+            self.basic_block_algorithms.len()
+        )?;
+        for algorithm in &self.basic_block_algorithms {
+            writeln!(f, "  {}", algorithm)?;
+        }
+        Ok(())
+    }
+}
+
+/// Sort per-step counts by count, descending, then by step id
+fn algorithm_counts<A>(counts: HashMap<A, usize>, id: fn(&A) -> i64) -> Vec<AlgorithmCount<A>> {
+    let mut counts: Vec<AlgorithmCount<A>> = counts
+        .into_iter()
+        .map(|(algorithm, count)| AlgorithmCount { algorithm, count })
+        .collect();
+    counts.sort_by_key(|c| (std::cmp::Reverse(c.count), id(&c.algorithm)));
+    counts
+}
+
+/// Compute the statistics of a BinDiff database, streaming the function and
+/// basic block matches
+pub fn stats(bindiff: &BinDiff) -> Result<Stats> {
+    let metadata = bindiff.read_metadata()?;
+    let primary = bindiff.read_file_by_id(metadata.file1)?;
+    let secondary = bindiff.read_file_by_id(metadata.file2)?;
+
+    let mut function_matches = 0;
+    let mut identical = 0;
+    let mut renamed = 0;
+    let mut similarity = [0; BUCKETS];
+    let mut confidence = [0; BUCKETS];
+    let mut function_algorithms = HashMap::new();
+    bindiff.for_each_function_match(&FunctionFilter::default(), |function_match| {
+        function_matches += 1;
+        if function_match.similarity >= 1.0 {
+            identical += 1;
+        }
+        if function_match.name1 != function_match.name2 {
+            renamed += 1;
+        }
+        similarity[bucket(function_match.similarity)] += 1;
+        confidence[bucket(function_match.confidence)] += 1;
+        *function_algorithms
+            .entry(function_match.algorithm)
+            .or_insert(0) += 1;
+        Ok(())
+    })?;
+
+    let mut basic_block_matches = 0;
+    let mut basic_block_algorithms = HashMap::new();
+    bindiff.for_each_basic_block_match(|basic_block_match| {
+        basic_block_matches += 1;
+        *basic_block_algorithms
+            .entry(basic_block_match.algorithm)
+            .or_insert(0) += 1;
+        Ok(())
+    })?;
+
+    let instruction_matches = bindiff.count_instruction_matches()?;
+
+    Ok(Stats {
+        function_matches,
+        basic_block_matches,
+        instruction_matches,
+        identical,
+        renamed,
+        similarity: histogram(&similarity),
+        confidence: histogram(&confidence),
+        function_algorithms: algorithm_counts(function_algorithms, FunctionAlgorithm::id),
+        basic_block_algorithms: algorithm_counts(basic_block_algorithms, BasicBlockAlgorithm::id),
+        primary: FileCoverage::new(
+            &primary,
+            function_matches,
+            basic_block_matches,
+            instruction_matches,
+        ),
+        secondary: FileCoverage::new(
+            &secondary,
+            function_matches,
+            basic_block_matches,
+            instruction_matches,
+        ),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::function_match;
+    use crate::{BasicBlockMatch, FunctionMatch, Instruction, Metadata};
+
+    #[test]
+    fn test_stats() -> Result<()> {
+        let path =
+            std::env::temp_dir().join(format!("bindiff-rs-stats-{}.BinDiff", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let db = BinDiff::create(&path)?;
+        let file = |id, functions, lib_functions| File {
+            id,
+            filename: format!("file{}", id),
+            exe_filename: String::new(),
+            hash: String::new(),
+            functions,
+            lib_functions,
+            calls: 0,
+            basic_blocks: 8,
+            lib_basic_blocks: 0,
+            edges: 0,
+            lib_edges: 0,
+            instructions: 20,
+            lib_instructions: 0,
+        };
+        db.write_file(&file(1, 4, 0))?;
+        db.write_file(&file(2, 4, 2))?;
+        db.write_metadata(&Metadata {
+            version: "BinDiff 8".to_string(),
+            file1: 1,
+            file2: 2,
+            description: String::new(),
+            created: chrono::Utc::now(),
+            modified: chrono::Utc::now(),
+            similarity: 0.9,
+            confidence: 0.95,
+        })?;
+        let scored = |id: i64, name2: &str, similarity, algorithm| FunctionMatch {
+            id,
+            name1: format!("f{}", id),
+            name2: name2.to_string(),
+            similarity,
+            algorithm,
+            ..function_match(0x1000 * id, 0x1000 * id + 0x100)
+        };
+        db.write_function_matches(&[
+            scored(1, "f1", 1.0, FunctionAlgorithm::HashMatching),
+            scored(2, "f2", 0.55, FunctionAlgorithm::HashMatching),
+            scored(3, "sub_3100", 0.05, FunctionAlgorithm::AddressSequence),
+        ])?;
+        db.write_basic_block_matches(&[1, 2].map(|id| BasicBlockMatch {
+            id,
+            function_id: id,
+            address1: 0x1000 * id,
+            address2: 0x1000 * id + 0x100,
+            algorithm: BasicBlockAlgorithm::EdgesPrimeProduct,
+            evaluate: false,
+        }))?;
+        db.write_instruction_matches(&[Instruction {
+            id: 1,
+            address1: 0x1000,
+            address2: 0x1100,
+        }])?;
+
+        let stats = stats(&db)?;
+        assert_eq!(
+            (stats.function_matches, stats.identical, stats.renamed),
+            (3, 1, 1)
+        );
+        let counts: Vec<usize> = stats.similarity.iter().map(|b| b.count).collect();
+        assert_eq!(counts, [1, 0, 0, 0, 0, 1, 0, 0, 0, 1]);
+        assert_eq!(stats.confidence[9].count, 3);
+        assert_eq!(
+            stats.function_algorithms[0].algorithm,
+            FunctionAlgorithm::HashMatching
+        );
+        assert_eq!(stats.function_algorithms[0].count, 2);
+        assert_eq!(stats.basic_block_algorithms[0].count, 2);
+        assert_eq!(stats.primary.functions.ratio, 0.75);
+        assert_eq!(stats.secondary.functions.total, 6);
+        assert_eq!(stats.secondary.basic_blocks.ratio, 0.25);
+        assert_eq!(stats.secondary.instructions.matched, 1);
+
+        db.close()?;
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,279 @@
+//! Symbol porting: carry the names of a symbolized primary binary over to the
+//! matched functions of a stripped secondary binary, and emit them in formats
+//! that disassemblers can import.
+
+use crate::{is_generated_name, FunctionAlgorithm, FunctionMatch};
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+
+/// A name to apply to a secondary function
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PortedSymbol {
+    /// Secondary function address
+    pub address: u64,
+    /// Name taken from the primary function
+    pub name: String,
+    /// Placeholder name the secondary function had
+    pub previous_name: String,
+    /// Primary function address the name came from
+    pub source_address: u64,
+    pub similarity: f64,
+    pub confidence: f64,
+    pub algorithm: FunctionAlgorithm,
+}
+
+impl std::fmt::Display for PortedSymbol {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{:#x} {} -> {}\tsimilarity: {:.2}, confidence: {:.2}",
+            self.address, self.previous_name, self.name, self.similarity, self.confidence
+        )
+    }
+}
+
+/// Output format of a symbol map
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymbolFormat {
+    Json,
+    Csv,
+    /// IDAPython script calling `idc.set_name`
+    Ida,
+    /// Ghidra Python script renaming or creating functions
+    Ghidra,
+    /// radare2 `afn` commands
+    Radare2,
+}
+
+impl std::str::FromStr for SymbolFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "json" => Ok(SymbolFormat::Json),
+            "csv" => Ok(SymbolFormat::Csv),
+            "ida" | "idapython" => Ok(SymbolFormat::Ida),
+            "ghidra" => Ok(SymbolFormat::Ghidra),
+            "r2" | "radare2" => Ok(SymbolFormat::Radare2),
+            _ => bail!("Unknown symbol format: {}", s),
+        }
+    }
+}
+
+/// Names of primary functions matched to secondary functions that only have
+/// a generated name, for matches with at least `min_confidence`. Sorted by
+/// secondary address.
+pub fn port_symbols(matches: &[FunctionMatch], min_confidence: f64) -> Vec<PortedSymbol> {
+    let mut symbols: Vec<PortedSymbol> = matches
+        .iter()
+        .filter(|m| m.confidence >= min_confidence)
+        .filter(|m| !is_generated_name(&m.name1) && is_generated_name(&m.name2))
+        .map(|m| PortedSymbol {
+            address: m.address2 as u64,
+            name: m.name1.clone(),
+            previous_name: m.name2.clone(),
+            source_address: m.address1 as u64,
+            similarity: m.similarity,
+            confidence: m.confidence,
+            algorithm: m.algorithm.clone(),
+        })
+        .collect();
+    symbols.sort_by_key(|symbol| symbol.address);
+    symbols
+}
+
+/// Quote `text` as an ASCII-only Python string literal, a `u""` literal with
+/// `\u` escapes if it has non-ASCII characters, so scripts read the same under
+/// IDAPython 3 and Ghidra's Jython 2 whatever their source encoding
+pub(crate) fn python_string(text: &str) -> String {
+    let mut literal = String::from("\"");
+    let mut unicode = false;
+    for c in text.chars() {
+        match c {
+            '"' => literal.push_str("\\\""),
+            '\\' => literal.push_str("\\\\"),
+            '\n' => literal.push_str("\\n"),
+            '\r' => literal.push_str("\\r"),
+            '\t' => literal.push_str("\\t"),
+            ' '..='~' => literal.push(c),
+            '\0'..='\x7f' => literal.push_str(&format!("\\x{:02x}", c as u32)),
+            '\u{80}'..='\u{ffff}' => {
+                unicode = true;
+                literal.push_str(&format!("\\u{:04x}", c as u32));
+            }
+            _ => {
+                unicode = true;
+                literal.push_str(&format!("\\U{:08x}", c as u32));
+            }
+        }
+    }
+    literal.push('"');
+    if unicode {
+        literal.insert(0, 'u');
+    }
+    literal
+}
+
+/// radare2 flag names may not contain whitespace or shell metacharacters
+fn radare2_name(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | ':' | '$' | '@') {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+/// Ghidra rejects symbol names with whitespace, replace it like Ghidra's own
+/// symbol cleanup does
+fn ghidra_name(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if c.is_whitespace() || c.is_control() {
+                '_'
+            } else {
+                c
+            }
+        })
+        .collect()
+}
+
+/// Render a symbol map in `format`
+pub fn render_symbols(symbols: &[PortedSymbol], format: SymbolFormat) -> Result<String> {
+    let mut out = String::new();
+    match format {
+        SymbolFormat::Json => {
+            out = serde_json::to_string_pretty(symbols)?;
+            out.push('\n');
+        }
+        SymbolFormat::Csv => {
+            let mut writer = csv::Writer::from_writer(Vec::new());
+            writer.write_record([
+                "address",
+                "name",
+                "previous_name",
+                "source_address",
+                "similarity",
+                "confidence",
+                "algorithm",
+            ])?;
+            for symbol in symbols {
+                writer.write_record([
+                    format!("{:#x}", symbol.address),
+                    symbol.name.clone(),
+                    symbol.previous_name.clone(),
+                    format!("{:#x}", symbol.source_address),
+                    symbol.similarity.to_string(),
+                    symbol.confidence.to_string(),
+                    symbol.algorithm.to_string(),
+                ])?;
+            }
+            out = String::from_utf8(writer.into_inner().context("Failed to write CSV")?)?;
+        }
+        SymbolFormat::Ida => {
+            out.push_str("import idc\n\n");
+            for symbol in symbols {
+                out.push_str(&format!(
+                    "idc.set_name({:#x}, {}, idc.SN_NOWARN | idc.SN_NOCHECK)\n",
+                    symbol.address,
+                    python_string(&symbol.name)
+                ));
+            }
+        }
+        SymbolFormat::Ghidra => {
+            // Names Ghidra still refuses are reported at the end instead of
+            // stopping the script halfway
+            out.push_str(
+                "# Ported symbols, run from Ghidra's script manager\n\
+                 from ghidra.program.model.symbol import SourceType\n\
+                 from java.lang import Exception as JavaException\n\
+                 \n\
+                 skipped = []\n\
+                 \n\
+                 def port(address, name):\n    \
+                     try:\n        \
+                         function = getFunctionAt(toAddr(address))\n        \
+                         if function is None:\n            \
+                             createFunction(toAddr(address), name)\n        \
+                         else:\n            \
+                             function.setName(name, SourceType.IMPORTED)\n    \
+                     except (Exception, JavaException) as e:\n        \
+                         skipped.append((address, name, e))\n\
+                 \n",
+            );
+            for symbol in symbols {
+                out.push_str(&format!(
+                    "port({:#x}, {})\n",
+                    symbol.address,
+                    python_string(&ghidra_name(&symbol.name))
+                ));
+            }
+            out.push_str(
+                "\nfor address, name, e in skipped:\n    \
+                     print(\"Skipped %#x %s: %s\" % (address, name, e))\n",
+            );
+        }
+        SymbolFormat::Radare2 => {
+            for symbol in symbols {
+                out.push_str(&format!(
+                    "afn {} {:#x}\n",
+                    radare2_name(&symbol.name),
+                    symbol.address
+                ));
+            }
+        }
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::function_match;
+
+    #[test]
+    fn test_port_symbols() -> Result<()> {
+        let named = |address1, name1: &str, address2, name2: &str, confidence| FunctionMatch {
+            name1: name1.to_string(),
+            name2: name2.to_string(),
+            confidence,
+            ..function_match(address1, address2)
+        };
+        let matches = [
+            named(0x1000, "_vm_map_copyin", 0x2000, "sub_2000", 0.9),
+            named(0x1100, "operator new", 0x2100, "sub_2100", 0.8),
+            named(0x1200, "_weak", 0x2200, "sub_2200", 0.2),
+            named(0x1300, "sub_1300", 0x2300, "sub_2300", 0.9),
+            named(0x1400, "_named", 0x2400, "_named", 0.9),
+        ];
+
+        let symbols = port_symbols(&matches, 0.5);
+        let names: Vec<&str> = symbols.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, ["_vm_map_copyin", "operator new"]);
+        assert_eq!(symbols[0].address, 0x2000);
+        assert_eq!(symbols[0].source_address, 0x1000);
+
+        let ida = render_symbols(&symbols, SymbolFormat::Ida)?;
+        assert!(ida
+            .contains("idc.set_name(0x2000, \"_vm_map_copyin\", idc.SN_NOWARN | idc.SN_NOCHECK)"));
+        let radare2 = render_symbols(&symbols, SymbolFormat::Radare2)?;
+        assert_eq!(
+            radare2,
+            "afn _vm_map_copyin 0x2000\nafn operator_new 0x2100\n"
+        );
+        let csv = render_symbols(&symbols, SymbolFormat::Csv)?;
+        assert_eq!(csv.lines().count(), 3);
+        let ghidra = render_symbols(&symbols, SymbolFormat::Ghidra)?;
+        assert!(ghidra.contains("port(0x2100, \"operator_new\")"));
+        assert!(ghidra.contains("for address, name, e in skipped:"));
+        assert!("bogus".parse::<SymbolFormat>().is_err());
+
+        assert_eq!(python_string("a \"b\"\\\n"), r#""a \"b\"\\\n""#);
+        assert_eq!(python_string("café ☕\u{1}"), r#"u"caf\u00e9 \u2615\x01""#);
+        assert_eq!(python_string("😀"), r#"u"\U0001f600""#);
+        Ok(())
+    }
+}
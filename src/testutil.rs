@@ -0,0 +1,188 @@
+//! Helpers for building small synthetic BinExport files and BinDiff rows in
+//! unit tests.
+
+use crate::binexport::bin_export2::{self, basic_block, call_graph, expression, flow_graph};
+use crate::binexport::BinExport2;
+use crate::{BinExport, FunctionAlgorithm, FunctionMatch};
+
+/// An identical one block, one instruction function match named after its
+/// primary address, for tests to override with struct update syntax
+pub(crate) fn function_match(address1: i64, address2: i64) -> FunctionMatch {
+    let name = format!("sub_{:x}", address1);
+    FunctionMatch {
+        id: 1,
+        address1,
+        name1: name.clone(),
+        address2,
+        name2: name,
+        similarity: 1.0,
+        confidence: 1.0,
+        flags: 0,
+        algorithm: FunctionAlgorithm::NameHashMatching,
+        evaluate: false,
+        comment_supported: false,
+        basic_blocks: 1,
+        edges: 0,
+        instructions: 1,
+    }
+}
+
+/// A function made of basic blocks, each a list of instructions written as
+/// `mnemonic op1, op2`, with flow graph edges between block indices
+pub(crate) struct FunctionSpec<'a> {
+    pub address: u64,
+    pub name: &'a str,
+    pub blocks: Vec<Vec<&'a str>>,
+    pub edges: Vec<(usize, usize)>,
+    pub calls: Vec<u64>,
+}
+
+impl<'a> FunctionSpec<'a> {
+    pub fn new(address: u64, name: &'a str, blocks: Vec<Vec<&'a str>>) -> Self {
+        Self {
+            address,
+            name,
+            blocks,
+            edges: Vec::new(),
+            calls: Vec::new(),
+        }
+    }
+
+    pub fn edges(mut self, edges: &[(usize, usize)]) -> Self {
+        self.edges = edges.to_vec();
+        self
+    }
+
+    pub fn calls(mut self, calls: &[u64]) -> Self {
+        self.calls = calls.to_vec();
+        self
+    }
+}
+
+/// Build a BinExport where every instruction is 4 bytes long and blocks of a
+/// function are laid out contiguously from its address
+pub(crate) fn binexport(functions: &[FunctionSpec]) -> BinExport {
+    let mut proto = BinExport2 {
+        meta_information: Some(bin_export2::Meta {
+            executable_name: Some("test".to_string()),
+            executable_id: Some("0123456789abcdef".to_string()),
+            architecture_name: Some("arm-64".to_string()),
+            timestamp: None,
+        }),
+        call_graph: Some(bin_export2::CallGraph::default()),
+        ..Default::default()
+    };
+
+    for function in functions {
+        let mut address = function.address;
+        let mut block_indices = Vec::new();
+        for block in &function.blocks {
+            let begin = proto.instruction.len() as i32;
+            for text in block {
+                // Only the function entry carries an explicit address, the
+                // rest are implied by the preceding instruction's size
+                let explicit = (address == function.address).then_some(address);
+                let instruction = instruction(&mut proto, explicit, text);
+                proto.instruction.push(instruction);
+                address += 4;
+            }
+            block_indices.push(proto.basic_block.len() as i32);
+            proto.basic_block.push(bin_export2::BasicBlock {
+                instruction_index: vec![basic_block::IndexRange {
+                    begin_index: Some(begin),
+                    end_index: Some(proto.instruction.len() as i32),
+                }],
+            });
+        }
+        proto.flow_graph.push(bin_export2::FlowGraph {
+            basic_block_index: block_indices.clone(),
+            entry_basic_block_index: block_indices.first().copied(),
+            edge: function
+                .edges
+                .iter()
+                .map(|&(source, target)| flow_graph::Edge {
+                    source_basic_block_index: Some(block_indices[source]),
+                    target_basic_block_index: Some(block_indices[target]),
+                    r#type: None,
+                    is_back_edge: Some(target <= source),
+                })
+                .collect(),
+        });
+        proto
+            .call_graph
+            .as_mut()
+            .unwrap()
+            .vertex
+            .push(call_graph::Vertex {
+                address: Some(function.address),
+                mangled_name: Some(function.name.to_string()),
+                ..Default::default()
+            });
+    }
+
+    let addresses: Vec<u64> = functions.iter().map(|f| f.address).collect();
+    for (source, function) in functions.iter().enumerate() {
+        for call in &function.calls {
+            let target = addresses.iter().position(|a| a == call).unwrap();
+            proto
+                .call_graph
+                .as_mut()
+                .unwrap()
+                .edge
+                .push(call_graph::Edge {
+                    source_vertex_index: Some(source as i32),
+                    target_vertex_index: Some(target as i32),
+                });
+        }
+    }
+
+    BinExport::new(proto)
+}
+
+fn instruction(
+    proto: &mut BinExport2,
+    address: Option<u64>,
+    text: &str,
+) -> bin_export2::Instruction {
+    let (mnemonic, operands) = text.split_once(' ').unwrap_or((text, ""));
+    let mnemonic_index = match proto.mnemonic.iter().position(|m| m.name() == mnemonic) {
+        Some(index) => index,
+        None => {
+            proto.mnemonic.push(bin_export2::Mnemonic {
+                name: Some(mnemonic.to_string()),
+            });
+            proto.mnemonic.len() - 1
+        }
+    };
+    let operand_index = operands
+        .split(", ")
+        .filter(|operand| !operand.is_empty())
+        .map(|operand| {
+            proto.expression.push(bin_export2::Expression {
+                r#type: Some(expression::Type::Symbol as i32),
+                symbol: Some(operand.to_string()),
+                ..Default::default()
+            });
+            proto.operand.push(bin_export2::Operand {
+                expression_index: vec![proto.expression.len() as i32 - 1],
+            });
+            proto.operand.len() as i32 - 1
+        })
+        .collect();
+
+    bin_export2::Instruction {
+        address,
+        mnemonic_index: Some(mnemonic_index as i32),
+        operand_index,
+        // Derive the bytes from the text so identical instructions hash alike
+        raw_bytes: Some(
+            text.bytes()
+                .fold(0x811c9dc5u32, |hash, byte| {
+                    (hash ^ byte as u32).wrapping_mul(0x01000193)
+                })
+                .to_le_bytes()
+                .to_vec(),
+        ),
+        ..Default::default()
+    }
+}
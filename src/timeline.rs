@@ -0,0 +1,321 @@
+//! Function identity across a chain of diffs of consecutive releases (A vs B,
+//! B vs C, ...). The secondary address of a match in one diff is the primary
+//! address in the next, so matches chain into the history of one function.
+
+use crate::{BinDiff, FunctionAlgorithm, FunctionMatch};
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+/// A function as it exists in one version
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FunctionVersion {
+    pub version: usize,
+    pub address: u64,
+    pub name: String,
+}
+
+/// Something that happened to a function between two versions
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Event {
+    /// First seen in `version`, unmatched in the diff against the previous one
+    Appeared { version: usize },
+    /// Matched with a similarity below 1.0 from the previous version
+    Changed {
+        version: usize,
+        similarity: f64,
+        algorithm: FunctionAlgorithm,
+    },
+    /// Name differs from the one in the previous version
+    Renamed { version: usize, from: String },
+    /// Last seen in the version before `version`
+    Disappeared { version: usize },
+}
+
+impl std::fmt::Display for Event {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Event::Appeared { version } => write!(f, "v{}: appeared", version),
+            Event::Changed {
+                version,
+                similarity,
+                algorithm,
+            } => write!(
+                f,
+                "v{}: changed (similarity: {:.2}, algorithm: {})",
+                version, similarity, algorithm
+            ),
+            Event::Renamed { version, from } => write!(f, "v{}: renamed from {}", version, from),
+            Event::Disappeared { version } => write!(f, "v{}: disappeared", version),
+        }
+    }
+}
+
+/// One function followed through consecutive versions
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FunctionHistory {
+    /// The function in every version it was seen in, in order
+    pub versions: Vec<FunctionVersion>,
+    /// The matches linking consecutive entries of `versions`
+    pub matches: Vec<FunctionMatch>,
+    pub events: Vec<Event>,
+}
+
+impl FunctionHistory {
+    pub fn first_version(&self) -> usize {
+        self.versions[0].version
+    }
+
+    pub fn last_version(&self) -> usize {
+        self.versions[self.versions.len() - 1].version
+    }
+
+    /// Name in the last version the function was seen in
+    pub fn name(&self) -> &str {
+        &self.versions[self.versions.len() - 1].name
+    }
+
+    /// The function in `version`, if it existed then
+    pub fn in_version(&self, version: usize) -> Option<&FunctionVersion> {
+        self.versions
+            .get(version.checked_sub(self.first_version())?)
+    }
+}
+
+impl std::fmt::Display for FunctionHistory {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "{} (v{}..v{})",
+            self.name(),
+            self.first_version(),
+            self.last_version()
+        )?;
+        for version in &self.versions {
+            writeln!(
+                f,
+                "  v{}: {:#x} {}",
+                version.version, version.address, version.name
+            )?;
+        }
+        for event in &self.events {
+            writeln!(f, "  {}", event)?;
+        }
+        Ok(())
+    }
+}
+
+/// Histories of all functions seen in a chain of diffs
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Timeline {
+    /// Number of versions, one more than the number of diffs
+    pub versions: usize,
+    pub functions: Vec<FunctionHistory>,
+}
+
+impl Timeline {
+    /// Open an ordered list of BinDiff databases, checking that the secondary
+    /// binary of each diff is the primary binary of the next
+    pub fn open<P: AsRef<Path>>(paths: &[P]) -> Result<Self> {
+        let mut diffs = Vec::new();
+        let mut previous_hash: Option<String> = None;
+        for path in paths {
+            let path = path.as_ref();
+            let bindiff = BinDiff::open(path)?;
+            let metadata = bindiff.read_metadata()?;
+            let primary = bindiff.read_file_by_id(metadata.file1)?;
+            let secondary = bindiff.read_file_by_id(metadata.file2)?;
+            if let Some(previous_hash) = previous_hash {
+                if !previous_hash.is_empty()
+                    && !primary.hash.is_empty()
+                    && !previous_hash.eq_ignore_ascii_case(&primary.hash)
+                {
+                    bail!(
+                        "{} does not continue the previous diff: primary hash {}, expected {}",
+                        path.display(),
+                        primary.hash,
+                        previous_hash
+                    );
+                }
+            }
+            previous_hash = Some(secondary.hash);
+            diffs.push(
+                bindiff
+                    .read_function_matches()
+                    .with_context(|| format!("Failed to read {}", path.display()))?,
+            );
+        }
+        Ok(Self::from_matches(&diffs))
+    }
+
+    /// Chain the function matches of consecutive diffs, `diffs[i]` being the
+    /// matches between version `i` and `i + 1`. Functions that are unmatched on
+    /// both sides of a version do not appear in any diff and are not tracked.
+    pub fn from_matches(diffs: &[Vec<FunctionMatch>]) -> Self {
+        let by_address: Vec<HashMap<i64, &FunctionMatch>> = diffs
+            .iter()
+            .map(|matches| matches.iter().map(|m| (m.address1, m)).collect())
+            .collect();
+        // Addresses of each version reached from the previous diff
+        let continued: Vec<HashSet<i64>> = diffs
+            .iter()
+            .map(|matches| matches.iter().map(|m| m.address2).collect())
+            .collect();
+
+        let mut functions = Vec::new();
+        for (start, matches) in diffs.iter().enumerate() {
+            let mut starts: Vec<&FunctionMatch> = matches
+                .iter()
+                .filter(|m| start == 0 || !continued[start - 1].contains(&m.address1))
+                .collect();
+            starts.sort_by_key(|m| m.address1);
+            for first in starts {
+                functions.push(history(start, first, &by_address, diffs.len() + 1));
+            }
+        }
+
+        Self {
+            versions: diffs.len() + 1,
+            functions,
+        }
+    }
+
+    /// History of the function at `address` in `version`
+    pub fn function(&self, version: usize, address: u64) -> Option<&FunctionHistory> {
+        self.functions.iter().find(|history| {
+            history
+                .in_version(version)
+                .is_some_and(|entry| entry.address == address)
+        })
+    }
+
+    /// Histories of functions that carried `name` in any version
+    pub fn find_by_name<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a FunctionHistory> {
+        self.functions
+            .iter()
+            .filter(move |history| history.versions.iter().any(|entry| entry.name == name))
+    }
+}
+
+/// Follow the match `first` of diff `start` through the following diffs
+fn history(
+    start: usize,
+    first: &FunctionMatch,
+    by_address: &[HashMap<i64, &FunctionMatch>],
+    versions: usize,
+) -> FunctionHistory {
+    let mut events = Vec::new();
+    if start > 0 {
+        events.push(Event::Appeared { version: start });
+    }
+    let mut entries = vec![FunctionVersion {
+        version: start,
+        address: first.address1 as u64,
+        name: first.name1.clone(),
+    }];
+    let mut matches = Vec::new();
+    let mut current = Some(first);
+    let mut diff = start;
+    while let Some(function_match) = current {
+        let version = diff + 1;
+        let previous = &entries[entries.len() - 1].name;
+        if function_match.similarity < 1.0 {
+            events.push(Event::Changed {
+                version,
+                similarity: function_match.similarity,
+                algorithm: function_match.algorithm.clone(),
+            });
+        }
+        if &function_match.name2 != previous {
+            events.push(Event::Renamed {
+                version,
+                from: previous.clone(),
+            });
+        }
+        entries.push(FunctionVersion {
+            version,
+            address: function_match.address2 as u64,
+            name: function_match.name2.clone(),
+        });
+        matches.push(function_match.clone());
+        current = by_address
+            .get(version)
+            .and_then(|next| next.get(&function_match.address2).copied());
+        diff = version;
+    }
+    if diff + 1 < versions {
+        events.push(Event::Disappeared { version: diff + 1 });
+    }
+    FunctionHistory {
+        versions: entries,
+        matches,
+        events,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::function_match;
+
+    fn named(
+        address1: i64,
+        name1: &str,
+        address2: i64,
+        name2: &str,
+        similarity: f64,
+    ) -> FunctionMatch {
+        FunctionMatch {
+            name1: name1.to_string(),
+            name2: name2.to_string(),
+            similarity,
+            ..function_match(address1, address2)
+        }
+    }
+
+    #[test]
+    fn test_timeline() {
+        let diffs = vec![
+            vec![
+                named(0x1000, "alpha", 0x1100, "alpha", 1.0),
+                named(0x2000, "beta", 0x2100, "beta", 0.9),
+            ],
+            vec![
+                named(0x1100, "alpha", 0x1200, "alpha_v2", 0.7),
+                named(0x3100, "gamma", 0x3200, "gamma", 1.0),
+            ],
+        ];
+
+        let timeline = Timeline::from_matches(&diffs);
+        assert_eq!(timeline.versions, 3);
+        assert_eq!(timeline.functions.len(), 3);
+
+        let alpha = timeline.function(1, 0x1100).unwrap();
+        assert_eq!((alpha.first_version(), alpha.last_version()), (0, 2));
+        assert_eq!(alpha.name(), "alpha_v2");
+        assert_eq!(alpha.in_version(2).unwrap().address, 0x1200);
+        assert_eq!(
+            alpha.events,
+            [
+                Event::Changed {
+                    version: 2,
+                    similarity: 0.7,
+                    algorithm: FunctionAlgorithm::NameHashMatching
+                },
+                Event::Renamed {
+                    version: 2,
+                    from: "alpha".to_string()
+                },
+            ]
+        );
+
+        let beta = timeline.find_by_name("beta").next().unwrap();
+        assert_eq!(beta.last_version(), 1);
+        assert_eq!(beta.events[1], Event::Disappeared { version: 2 });
+
+        let gamma = timeline.function(2, 0x3200).unwrap();
+        assert_eq!(gamma.events, [Event::Appeared { version: 1 }]);
+        assert!(gamma.in_version(0).is_none());
+    }
+}
@@ -0,0 +1,280 @@
+//! Rank matched functions that actually changed by how interesting they are to
+//! look at when diffing patches.
+
+use crate::flowgraph::Function;
+use crate::session::DiffSession;
+use crate::{BasicBlockMatch, FunctionMatch};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+/// Why a matched function is considered changed
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum ChangeReason {
+    /// BinDiff similarity below 1.0
+    LowSimilarity(f64),
+    /// Basic blocks not covered by the match on either side
+    UnmatchedBasicBlocks { removed: usize, added: usize },
+    /// Edges not covered by the match on either side
+    UnmatchedEdges { removed: usize, added: usize },
+    /// Instructions not covered by the match on either side
+    UnmatchedInstructions { removed: usize, added: usize },
+}
+
+impl std::fmt::Display for ChangeReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ChangeReason::LowSimilarity(similarity) => write!(f, "similarity {:.2}", similarity),
+            ChangeReason::UnmatchedBasicBlocks { removed, added } => {
+                write!(f, "basic blocks -{} +{}", removed, added)
+            }
+            ChangeReason::UnmatchedEdges { removed, added } => {
+                write!(f, "edges -{} +{}", removed, added)
+            }
+            ChangeReason::UnmatchedInstructions { removed, added } => {
+                write!(f, "instructions -{} +{}", removed, added)
+            }
+        }
+    }
+}
+
+/// A changed function match with its interestingness score
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TriageEntry {
+    pub function_match: FunctionMatch,
+    pub score: f64,
+    pub primary_instructions: usize,
+    pub secondary_instructions: usize,
+    pub reasons: Vec<ChangeReason>,
+}
+
+impl std::fmt::Display for TriageEntry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let reasons: Vec<String> = self.reasons.iter().map(|r| r.to_string()).collect();
+        write!(
+            f,
+            "{:.4}\t{} -> {}\tconfidence: {:.2}, instructions: {}/{}\t[{}]",
+            self.score,
+            self.function_match.name1,
+            self.function_match.name2,
+            self.function_match.confidence,
+            self.primary_instructions,
+            self.secondary_instructions,
+            reasons.join(", ")
+        )
+    }
+}
+
+/// Score a function match against both sides' flow graphs and the match's
+/// basic block matches. Returns `None` if the function did not change.
+///
+/// The score favors low similarity, high confidence and small functions, as
+/// small, confidently matched changes are usually the quickest to review.
+pub fn score(
+    function_match: &FunctionMatch,
+    primary: &Function,
+    secondary: &Function,
+    basic_block_matches: &[BasicBlockMatch],
+) -> Option<TriageEntry> {
+    let mut reasons = Vec::new();
+    if function_match.similarity < 1.0 {
+        reasons.push(ChangeReason::LowSimilarity(function_match.similarity));
+    }
+
+    let matched1: HashSet<u64> = basic_block_matches
+        .iter()
+        .map(|m| m.address1 as u64)
+        .collect();
+    let matched2: HashSet<u64> = basic_block_matches
+        .iter()
+        .map(|m| m.address2 as u64)
+        .collect();
+    let removed = primary
+        .basic_blocks()
+        .filter(|block| !matched1.contains(&block.address()))
+        .count();
+    let added = secondary
+        .basic_blocks()
+        .filter(|block| !matched2.contains(&block.address()))
+        .count();
+    if removed > 0 || added > 0 {
+        reasons.push(ChangeReason::UnmatchedBasicBlocks { removed, added });
+    }
+
+    let unmatched = |matched: i64, primary: usize, secondary: usize| {
+        let matched = matched.max(0) as usize;
+        (
+            primary.saturating_sub(matched),
+            secondary.saturating_sub(matched),
+        )
+    };
+    let (removed, added) = unmatched(
+        function_match.edges,
+        primary.edge_count(),
+        secondary.edge_count(),
+    );
+    if removed > 0 || added > 0 {
+        reasons.push(ChangeReason::UnmatchedEdges { removed, added });
+    }
+    let primary_instructions = primary.instruction_count();
+    let secondary_instructions = secondary.instruction_count();
+    let (removed, added) = unmatched(
+        function_match.instructions,
+        primary_instructions,
+        secondary_instructions,
+    );
+    if removed > 0 || added > 0 {
+        reasons.push(ChangeReason::UnmatchedInstructions { removed, added });
+    }
+
+    if reasons.is_empty() {
+        return None;
+    }
+
+    // Changes BinDiff still rates 1.0 similar get a small floor so they rank
+    // below real changes but are not dropped
+    let change = (1.0 - function_match.similarity).max(0.01);
+    let size = primary_instructions.max(secondary_instructions) as f64;
+    let score = change * function_match.confidence / (1.0 + size.ln_1p());
+
+    Some(TriageEntry {
+        function_match: function_match.clone(),
+        score,
+        primary_instructions,
+        secondary_instructions,
+        reasons,
+    })
+}
+
+/// Rank all changed function matches of a session, most interesting first,
+/// skipping matches whose functions are missing from a BinExport file
+pub fn rank(session: &DiffSession) -> Result<Vec<TriageEntry>> {
+    let mut basic_block_matches: HashMap<i64, Vec<BasicBlockMatch>> = HashMap::new();
+    session
+        .bindiff()
+        .for_each_basic_block_match(|basic_block_match| {
+            basic_block_matches
+                .entry(basic_block_match.function_id)
+                .or_default()
+                .push(basic_block_match);
+            Ok(())
+        })?;
+    let mut entries: Vec<TriageEntry> = session
+        .function_matches()?
+        .matched
+        .iter()
+        .filter_map(|matched| {
+            score(
+                &matched.function_match,
+                &matched.primary,
+                &matched.secondary,
+                basic_block_matches
+                    .get(&matched.function_match.id)
+                    .map_or(&[], Vec::as_slice),
+            )
+        })
+        .collect();
+    entries.sort_by(|a, b| b.score.total_cmp(&a.score));
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::{binexport, function_match, FunctionSpec};
+    use crate::BasicBlockAlgorithm;
+
+    fn block_match(address1: i64, address2: i64) -> BasicBlockMatch {
+        BasicBlockMatch {
+            id: 0,
+            function_id: 1,
+            address1,
+            address2,
+            algorithm: BasicBlockAlgorithm::EdgesPrimeProduct,
+            evaluate: false,
+        }
+    }
+
+    #[test]
+    fn test_score() {
+        let primary =
+            binexport(&[
+                FunctionSpec::new(0x1000, "foo", vec![vec!["cbz x0"], vec!["ret"]])
+                    .edges(&[(0, 1)]),
+            ]);
+        let secondary = binexport(&[
+            FunctionSpec::new(
+                0x2000,
+                "foo",
+                vec![vec!["cbz x0"], vec!["cmp x1, #0x8"], vec!["ret"]],
+            )
+            .edges(&[(0, 1), (1, 2)]),
+            FunctionSpec::new(
+                0x3000,
+                "foo",
+                vec![vec!["cbz x0"], vec!["nop"; 8], vec!["ret"]],
+            )
+            .edges(&[(0, 1), (1, 2)]),
+        ]);
+        let primary = primary.function(0x1000).unwrap();
+        let large = secondary.function(0x3000).unwrap();
+        let secondary = secondary.function(0x2000).unwrap();
+        let scored = |similarity, confidence| FunctionMatch {
+            similarity,
+            confidence,
+            basic_blocks: 2,
+            edges: 1,
+            instructions: 2,
+            ..function_match(0x1000, 0x2000)
+        };
+        let blocks = [block_match(0x1000, 0x2000), block_match(0x1004, 0x2008)];
+
+        let identical = [block_match(0x1000, 0x1000), block_match(0x1004, 0x1004)];
+        let unchanged = score(&scored(1.0, 0.9), &primary, &primary, &identical);
+        assert!(unchanged.is_none());
+
+        let entry = score(&scored(0.8, 0.9), &primary, &secondary, &blocks).unwrap();
+        assert_eq!(
+            entry.reasons,
+            [
+                ChangeReason::LowSimilarity(0.8),
+                ChangeReason::UnmatchedBasicBlocks {
+                    removed: 0,
+                    added: 1
+                },
+                ChangeReason::UnmatchedEdges {
+                    removed: 0,
+                    added: 1
+                },
+                ChangeReason::UnmatchedInstructions {
+                    removed: 0,
+                    added: 1
+                },
+            ]
+        );
+
+        // Unmatched blocks come from the block matches, not from the counts
+        let partial = score(
+            &scored(0.8, 0.9),
+            &primary,
+            &secondary,
+            &[block_match(0x1000, 0x2000)],
+        )
+        .unwrap();
+        assert_eq!(
+            partial.reasons[1],
+            ChangeReason::UnmatchedBasicBlocks {
+                removed: 1,
+                added: 2
+            }
+        );
+
+        // Lower similarity, higher confidence and smaller functions rank first
+        let worse = score(&scored(0.5, 0.9), &primary, &secondary, &blocks).unwrap();
+        assert!(worse.score > entry.score);
+        let unsure = score(&scored(0.8, 0.3), &primary, &secondary, &blocks).unwrap();
+        assert!(entry.score > unsure.score);
+        let larger = score(&scored(0.8, 0.9), &primary, &large, &blocks).unwrap();
+        assert!(entry.score > larger.score);
+    }
+}
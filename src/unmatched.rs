@@ -0,0 +1,377 @@
+//! Functions, basic blocks and instructions that BinDiff could not match, i.e.
+//! that are only present in the primary (removed) or the secondary (added)
+//! binary.
+
+use crate::binexport::bin_export2::call_graph::vertex;
+use crate::flowgraph::{BasicBlock, Function};
+use crate::{BasicBlockMatch, BinExport, FunctionMatch, Instruction};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+/// Call graph vertex type of a function
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FunctionType {
+    Normal,
+    Library,
+    Imported,
+    Thunk,
+    Invalid,
+}
+
+impl From<vertex::Type> for FunctionType {
+    fn from(value: vertex::Type) -> Self {
+        match value {
+            vertex::Type::Normal => FunctionType::Normal,
+            vertex::Type::Library => FunctionType::Library,
+            vertex::Type::Imported => FunctionType::Imported,
+            vertex::Type::Thunk => FunctionType::Thunk,
+            vertex::Type::Invalid => FunctionType::Invalid,
+        }
+    }
+}
+
+impl std::fmt::Display for FunctionType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FunctionType::Normal => write!(f, "normal"),
+            FunctionType::Library => write!(f, "library"),
+            FunctionType::Imported => write!(f, "imported"),
+            FunctionType::Thunk => write!(f, "thunk"),
+            FunctionType::Invalid => write!(f, "invalid"),
+        }
+    }
+}
+
+/// A call graph function with the size of its flow graph
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FunctionInfo {
+    pub address: u64,
+    pub name: String,
+    pub function_type: FunctionType,
+    /// Zero for functions without a flow graph (e.g. imports)
+    pub basic_blocks: usize,
+    pub edges: usize,
+    pub instructions: usize,
+}
+
+impl std::fmt::Display for FunctionInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{:#x} {}\ttype: {}, basic_blocks: {}, edges: {}, instructions: {}",
+            self.address,
+            self.name,
+            self.function_type,
+            self.basic_blocks,
+            self.edges,
+            self.instructions
+        )
+    }
+}
+
+/// A call graph function without a counterpart in the other binary
+pub type UnmatchedFunction = FunctionInfo;
+
+/// Functions only present on one side of a diff
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct UnmatchedFunctions {
+    /// Functions of the primary binary that were not matched (removed)
+    pub primary: Vec<UnmatchedFunction>,
+    /// Functions of the secondary binary that were not matched (added)
+    pub secondary: Vec<UnmatchedFunction>,
+}
+
+impl std::fmt::Display for UnmatchedFunctions {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "PRIMARY ONLY ({}):", self.primary.len())?;
+        for function in &self.primary {
+            writeln!(f, "  {}", function)?;
+        }
+        writeln!(f, "SECONDARY ONLY ({}):", self.secondary.len())?;
+        for function in &self.secondary {
+            writeln!(f, "  {}", function)?;
+        }
+        Ok(())
+    }
+}
+
+/// Compute the call graph functions of both BinExports that do not appear in
+/// `matches`, sorted by address
+pub fn unmatched_functions(
+    primary: &BinExport,
+    secondary: &BinExport,
+    matches: &[FunctionMatch],
+) -> UnmatchedFunctions {
+    let matched1: HashSet<u64> = matches.iter().map(|m| m.address1 as u64).collect();
+    let matched2: HashSet<u64> = matches.iter().map(|m| m.address2 as u64).collect();
+
+    UnmatchedFunctions {
+        primary: collect_functions(primary, &matched1),
+        secondary: collect_functions(secondary, &matched2),
+    }
+}
+
+/// All call graph functions of a BinExport, sorted by address
+pub fn functions(binexport: &BinExport) -> Vec<FunctionInfo> {
+    collect_functions(binexport, &HashSet::new())
+}
+
+/// Call graph functions whose address is not in `skip`, sorted by address
+fn collect_functions(binexport: &BinExport, skip: &HashSet<u64>) -> Vec<FunctionInfo> {
+    let Some(call_graph) = &binexport.binexport.call_graph else {
+        return Vec::new();
+    };
+
+    let mut functions: Vec<FunctionInfo> = call_graph
+        .vertex
+        .iter()
+        .filter(|vertex| !skip.contains(&vertex.address()))
+        .map(|vertex| {
+            let address = vertex.address();
+            let function = binexport.function(address);
+            FunctionInfo {
+                address,
+                name: binexport
+                    .function_name(address)
+                    .map(str::to_string)
+                    .unwrap_or_else(|| format!("sub_{:X}", address)),
+                function_type: vertex.r#type().into(),
+                basic_blocks: function.map_or(0, |f| f.basic_block_count()),
+                edges: function.map_or(0, |f| f.edge_count()),
+                instructions: function.map_or(0, |f| f.instruction_count()),
+            }
+        })
+        .collect();
+    functions.sort_by_key(|function| function.address);
+    functions
+}
+
+/// A basic block without a counterpart in the other function
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UnmatchedBasicBlock {
+    pub address: u64,
+    pub instructions: usize,
+}
+
+/// A matched basic block pair whose instructions are not all matched
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChangedBasicBlock {
+    pub address1: u64,
+    pub address2: u64,
+    /// Primary instructions without a match
+    pub removed: usize,
+    /// Secondary instructions without a match
+    pub added: usize,
+}
+
+/// Basic blocks and instructions of a function match that have no counterpart
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UnmatchedBasicBlocks {
+    pub address1: u64,
+    pub address2: u64,
+    /// Primary blocks that were not matched
+    pub removed: Vec<UnmatchedBasicBlock>,
+    /// Secondary blocks that were not matched
+    pub added: Vec<UnmatchedBasicBlock>,
+    /// Matched blocks with added or removed instructions
+    pub changed: Vec<ChangedBasicBlock>,
+}
+
+impl UnmatchedBasicBlocks {
+    /// Total number of unmatched primary instructions, including those of
+    /// removed blocks
+    pub fn removed_instructions(&self) -> usize {
+        self.removed.iter().map(|b| b.instructions).sum::<usize>()
+            + self.changed.iter().map(|b| b.removed).sum::<usize>()
+    }
+
+    /// Total number of unmatched secondary instructions, including those of
+    /// added blocks
+    pub fn added_instructions(&self) -> usize {
+        self.added.iter().map(|b| b.instructions).sum::<usize>()
+            + self.changed.iter().map(|b| b.added).sum::<usize>()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.removed.is_empty() && self.added.is_empty() && self.changed.is_empty()
+    }
+}
+
+impl std::fmt::Display for UnmatchedBasicBlocks {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{:#x} -> {:#x}:", self.address1, self.address2)?;
+        for block in &self.removed {
+            writeln!(
+                f,
+                "  - {:#x}\t({} instructions)",
+                block.address, block.instructions
+            )?;
+        }
+        for block in &self.added {
+            writeln!(
+                f,
+                "  + {:#x}\t({} instructions)",
+                block.address, block.instructions
+            )?;
+        }
+        for block in &self.changed {
+            writeln!(
+                f,
+                "  ~ {:#x} -> {:#x}\t(-{} +{} instructions)",
+                block.address1, block.address2, block.removed, block.added
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Compute the basic blocks and instructions of `primary` and `secondary` that
+/// are not covered by the function's basic block and instruction matches
+pub fn unmatched_basic_blocks(
+    primary: &Function,
+    secondary: &Function,
+    basic_block_matches: &[BasicBlockMatch],
+    instruction_matches: &[Instruction],
+) -> UnmatchedBasicBlocks {
+    let blocks: HashMap<u64, u64> = basic_block_matches
+        .iter()
+        .map(|m| (m.address1 as u64, m.address2 as u64))
+        .collect();
+    let matched2: HashSet<u64> = blocks.values().copied().collect();
+    let instructions1: HashSet<u64> = instruction_matches
+        .iter()
+        .map(|m| m.address1 as u64)
+        .collect();
+    let instructions2: HashSet<u64> = instruction_matches
+        .iter()
+        .map(|m| m.address2 as u64)
+        .collect();
+    let unmatched_count = |block: &BasicBlock, matched: &HashSet<u64>| {
+        block
+            .instructions()
+            .filter(|instruction| !matched.contains(&instruction.address()))
+            .count()
+    };
+
+    let mut removed = Vec::new();
+    let mut changed = Vec::new();
+    for block in primary.basic_blocks() {
+        let address = block.address();
+        match blocks.get(&address) {
+            None => removed.push(UnmatchedBasicBlock {
+                address,
+                instructions: block.instruction_count(),
+            }),
+            Some(&partner) => {
+                let removed = unmatched_count(&block, &instructions1);
+                let added = secondary
+                    .basic_block(partner)
+                    .map_or(0, |partner| unmatched_count(&partner, &instructions2));
+                if removed > 0 || added > 0 {
+                    changed.push(ChangedBasicBlock {
+                        address1: address,
+                        address2: partner,
+                        removed,
+                        added,
+                    });
+                }
+            }
+        }
+    }
+
+    let added = secondary
+        .basic_blocks()
+        .filter(|block| !matched2.contains(&block.address()))
+        .map(|block| UnmatchedBasicBlock {
+            address: block.address(),
+            instructions: block.instruction_count(),
+        })
+        .collect();
+
+    UnmatchedBasicBlocks {
+        address1: primary.address(),
+        address2: secondary.address(),
+        removed,
+        added,
+        changed,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::{binexport, function_match, FunctionSpec};
+
+    #[test]
+    fn test_unmatched_functions() {
+        let primary = binexport(&[
+            FunctionSpec::new(0x1000, "kept", vec![vec!["ret"]]),
+            FunctionSpec::new(0x2000, "removed", vec![vec!["nop", "ret"]]),
+        ]);
+        let secondary = binexport(&[
+            FunctionSpec::new(0x1100, "kept", vec![vec!["ret"]]),
+            FunctionSpec::new(0x3000, "added", vec![vec!["b"], vec!["ret"]]).edges(&[(0, 1)]),
+        ]);
+        let matches = [function_match(0x1000, 0x1100)];
+
+        let unmatched = unmatched_functions(&primary, &secondary, &matches);
+        assert_eq!(unmatched.primary.len(), 1);
+        assert_eq!(unmatched.primary[0].name, "removed");
+        assert_eq!(unmatched.primary[0].instructions, 2);
+        assert_eq!(unmatched.secondary.len(), 1);
+        assert_eq!(unmatched.secondary[0].address, 0x3000);
+        assert_eq!(unmatched.secondary[0].basic_blocks, 2);
+        assert_eq!(unmatched.secondary[0].edges, 1);
+        assert_eq!(unmatched.secondary[0].function_type, FunctionType::Normal);
+    }
+
+    #[test]
+    fn test_unmatched_basic_blocks() {
+        let primary = binexport(&[FunctionSpec::new(
+            0x1000,
+            "foo",
+            vec![vec!["cmp x0, #0x10", "b.hi"], vec!["ret"]],
+        )
+        .edges(&[(0, 1)])]);
+        let secondary = binexport(&[FunctionSpec::new(
+            0x2000,
+            "foo",
+            vec![
+                vec!["cmp x0, #0x10", "b.hi"],
+                vec!["cmp x1, #0x20", "b.hs"],
+                vec!["ret"],
+            ],
+        )
+        .edges(&[(0, 1), (1, 2)])]);
+        let basic_block_matches =
+            [(0x1000, 0x2000), (0x1008, 0x2010)].map(|(a1, a2)| BasicBlockMatch {
+                id: 0,
+                function_id: 1,
+                address1: a1,
+                address2: a2,
+                algorithm: crate::BasicBlockAlgorithm::EdgesPrimeProduct,
+                evaluate: false,
+            });
+        let instruction_matches =
+            [(0x1000, 0x2000), (0x1008, 0x2010)].map(|(a1, a2)| Instruction {
+                id: 0,
+                address1: a1,
+                address2: a2,
+            });
+
+        let unmatched = unmatched_basic_blocks(
+            &primary.function(0x1000).unwrap(),
+            &secondary.function(0x2000).unwrap(),
+            &basic_block_matches,
+            &instruction_matches,
+        );
+        assert!(unmatched.removed.is_empty());
+        assert_eq!(unmatched.added.len(), 1);
+        assert_eq!(unmatched.added[0].address, 0x2008);
+        assert_eq!(unmatched.changed.len(), 1);
+        assert_eq!(unmatched.changed[0].removed, 1);
+        assert_eq!(unmatched.changed[0].added, 1);
+        assert_eq!(unmatched.removed_instructions(), 1);
+        assert_eq!(unmatched.added_instructions(), 3);
+    }
+}